@@ -0,0 +1,4 @@
+fn main() {
+    // sqlx::migrate!() встраивает миграции при сборке
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,8 @@
+-- Момент, с которого курс действует: полночь по Москве даты, установленной ЦБ
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS effective_at TIMESTAMPTZ;
+
+UPDATE exchange_rates
+SET effective_at = date::timestamp AT TIME ZONE 'Europe/Moscow'
+WHERE effective_at IS NULL;
+
+ALTER TABLE exchange_rates ALTER COLUMN effective_at SET NOT NULL;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS currencies (
+    char_code TEXT PRIMARY KEY,
+    cbr_id TEXT NOT NULL,
+    num_code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
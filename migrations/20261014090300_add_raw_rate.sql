@@ -0,0 +1,8 @@
+-- Курс с полной точностью, как он получен; rate хранит округлённое по RATE_SCALE значение
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS raw_rate NUMERIC;
+
+UPDATE exchange_rates
+SET raw_rate = rate
+WHERE raw_rate IS NULL;
+
+ALTER TABLE exchange_rates ALTER COLUMN raw_rate SET NOT NULL;
@@ -0,0 +1,2 @@
+-- Первая дата, на которую валюта есть в фиде ЦБ; заполняет discover-available-from
+ALTER TABLE currencies ADD COLUMN IF NOT EXISTS available_from DATE;
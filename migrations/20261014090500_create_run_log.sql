@@ -0,0 +1,14 @@
+-- Запуски, которые пишут курсы: кто, когда, по каким датам и зачем
+CREATE TABLE IF NOT EXISTS run_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    run_id TEXT NOT NULL,
+    reason TEXT,
+    start_date DATE NOT NULL,
+    end_date DATE NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    summary JSONB,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS run_log_dates_idx ON run_log (start_date, end_date);
@@ -0,0 +1,5 @@
+-- Одна строка на дату с колонкой на валюту (usd_rub, eur_rub, ...) для BI; колонки
+-- добавляет ingest --maintain-wide по CURRENCIES
+CREATE TABLE IF NOT EXISTS exchange_rates_wide (
+    date DATE PRIMARY KEY
+);
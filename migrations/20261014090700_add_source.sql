@@ -0,0 +1,2 @@
+-- Откуда строка: cbr — курс ЦБ и производные от него, basket — курс корзины из CURRENCY_BASKETS
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS source TEXT NOT NULL DEFAULT 'cbr';
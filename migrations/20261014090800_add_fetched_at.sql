@@ -0,0 +1,3 @@
+-- Когда курс был получен из фида; более старая выборка не перезаписывает более новую.
+-- NULL — время неизвестно (старые строки и import)
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS fetched_at TIMESTAMPTZ;
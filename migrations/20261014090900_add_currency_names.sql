@@ -0,0 +1,3 @@
+-- Названия валюты из русского и английского фидов; заполняет ingest --fetch-names both
+ALTER TABLE currencies ADD COLUMN IF NOT EXISTS name_ru TEXT;
+ALTER TABLE currencies ADD COLUMN IF NOT EXISTS name_en TEXT;
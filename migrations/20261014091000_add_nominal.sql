@@ -0,0 +1,2 @@
+-- Сколько единиц не-рублёвой валюты пары стоит за курсом; больше 1 только для --keep-nominal-for
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS nominal INTEGER NOT NULL DEFAULT 1;
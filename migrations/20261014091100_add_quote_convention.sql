@@ -0,0 +1,6 @@
+-- Какая валюта пары — база в рыночных терминах: direct — X -> RUB, как котирует ЦБ,
+-- indirect — RUB -> X, cross — пара без RUB
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS quote_convention TEXT NOT NULL DEFAULT 'direct';
+
+UPDATE exchange_rates SET quote_convention = 'indirect' WHERE from_currency = 'RUB' AND quote_convention = 'direct';
+UPDATE exchange_rates SET quote_convention = 'cross' WHERE from_currency <> 'RUB' AND to_currency <> 'RUB' AND quote_convention = 'direct';
@@ -0,0 +1,4 @@
+-- date — календарный день, в который курс действует; feed_date — Date из фида ЦБ, день, на
+-- который курс установлен (для выходных раньше date). NULL у строк не из фида и записанных до
+-- появления колонки
+ALTER TABLE exchange_rates ADD COLUMN IF NOT EXISTS feed_date DATE;
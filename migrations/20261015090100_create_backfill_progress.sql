@@ -0,0 +1,8 @@
+-- Последняя завершённая дата ingest --job; по ней --resume продолжает прерванный бэкфилл
+CREATE TABLE IF NOT EXISTS backfill_progress (
+    job TEXT PRIMARY KEY,
+    last_date DATE NOT NULL,
+    start_date DATE NOT NULL,
+    end_date DATE NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
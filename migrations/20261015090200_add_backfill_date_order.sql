@@ -0,0 +1,3 @@
+-- --order, в котором шёл бэкфилл: --resume с другим порядком пропустил бы не те даты. NULL у
+-- задач, записанных до появления колонки
+ALTER TABLE backfill_progress ADD COLUMN IF NOT EXISTS date_order TEXT;
@@ -0,0 +1,21 @@
+-- Запись без блокировки ingest (import, recompute-cross) могла вставить вторую строку пары
+-- и даты между SELECT и INSERT. Из дублей остаётся последняя обновлённая
+DELETE FROM exchange_rates
+WHERE id IN (
+    SELECT id
+    FROM (
+        SELECT
+            id,
+            row_number() OVER (
+                PARTITION BY from_currency, to_currency, date
+                ORDER BY updated_at DESC, id
+            ) AS position
+        FROM exchange_rates
+    ) ranked
+    WHERE position > 1
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS exchange_rates_pair_date_key
+    ON exchange_rates (from_currency, to_currency, date);
+
+DROP INDEX IF EXISTS exchange_rates_pair_date_idx;
@@ -0,0 +1,79 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Result, anyhow};
+
+use crate::cli::AuditArgs;
+use crate::config::{get_currencies, get_currency_aliases};
+use crate::{
+    get_curs_map, get_db_pool, get_nominal_rates, get_rounded_rate, get_stored_rates, get_val_curs,
+};
+
+/// Compares the stored `X -> RUB` rates of a date against a fresh CBR feed, without
+/// writing. Reverse and cross rates are derived from these, so they are not checked.
+pub async fn audit(args: AuditArgs) -> Result<()> {
+    let pool = get_db_pool().await?;
+    let stored_rates = get_stored_rates(&args.date, &pool).await?;
+
+    // Валюты из фида, которых нет в CURRENCIES, не сохраняются и не проверяются
+    let mut currencies: BTreeSet<String> = get_currencies()?.into_iter().collect();
+    currencies.extend(
+        stored_rates
+            .keys()
+            .filter(|(_, to_currency)| to_currency == "RUB")
+            .map(|(from_currency, _)| from_currency.clone()),
+    );
+
+    let currencies: Vec<String> = currencies.into_iter().collect();
+    let val_curs = get_val_curs(args.date).await?;
+    let aliases = get_currency_aliases()?;
+    let mut feed_rates = get_curs_map(&val_curs, &aliases, &currencies).await?;
+
+    // Для --keep-nominal-for хранится курс за Nominal единиц, с ним и сравниваем
+    for (currency, nominal_rate) in get_nominal_rates(&val_curs, &aliases)? {
+        feed_rates.insert(currency, nominal_rate.value);
+    }
+
+    let mut matches = 0;
+    let mut discrepancies = 0;
+
+    for currency in &currencies {
+        let stored = stored_rates.get(&(currency.clone(), "RUB".to_string()));
+        let feed = feed_rates.get(currency).map(get_rounded_rate).transpose()?;
+
+        match (stored, feed) {
+            (Some(stored), Some(feed)) if *stored == feed => {
+                matches += 1;
+                println!("{} match {}", currency, stored);
+            }
+            (Some(stored), Some(feed)) => {
+                discrepancies += 1;
+                println!("{} mismatch: stored {}, feed {}", currency, stored, feed);
+            }
+            (None, Some(feed)) => {
+                discrepancies += 1;
+                println!("{} missing in the database: feed {}", currency, feed);
+            }
+            (Some(stored), None) => {
+                discrepancies += 1;
+                println!("{} missing in the feed: stored {}", currency, stored);
+            }
+            (None, None) => {
+                discrepancies += 1;
+                println!("{} missing in the database and the feed", currency);
+            }
+        }
+    }
+
+    println!("{}: {} match, {} differ", args.date, matches, discrepancies);
+
+    if args.strict && discrepancies > 0 {
+        return Err(anyhow!(
+            "{} of {} currencies at {} differ from the CBR feed",
+            discrepancies,
+            currencies.len(),
+            args.date
+        ));
+    }
+
+    Ok(())
+}
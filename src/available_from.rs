@@ -0,0 +1,122 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use sqlx::PgPool;
+
+use crate::cli::DiscoverAvailableFromArgs;
+use crate::config::{get_currencies, get_table_name};
+use crate::currency_cache::CurrencyCache;
+use crate::val_curs::Valute;
+use crate::{get_db_pool, get_today, get_val_curs};
+
+/// Earliest date the daily XML feed is published for.
+const CBR_FIRST_DATE: (i32, u32, u32) = (1992, 7, 1);
+
+/// Finds the first feed date of every requested currency and stores it in
+/// `currencies.available_from`, so that backfills skip the earlier dates.
+pub async fn discover(args: DiscoverAvailableFromArgs, today: Option<NaiveDate>) -> Result<()> {
+    let currencies = if args.currencies.is_empty() {
+        get_currencies()?
+    } else {
+        args.currencies
+            .iter()
+            .map(|code| code.to_uppercase())
+            .collect()
+    };
+
+    let pool = get_db_pool().await?;
+    let currency_cache = CurrencyCache::load(&pool).await?;
+    let (year, month, day) = CBR_FIRST_DATE;
+    let first_date =
+        NaiveDate::from_ymd_opt(year, month, day).ok_or(anyhow!("Invalid CBR first date"))?;
+    let today = get_today(today)?;
+
+    for currency in &currencies {
+        if !args.refresh
+            && let Some(date) = currency_cache.available_from.get(currency)
+        {
+            println!("{} {} (stored)", currency, date);
+            continue;
+        }
+
+        match find_available_from(currency, first_date, today).await? {
+            Some((date, valute)) => {
+                set_available_from(currency, date, &valute, &pool).await?;
+                println!("{} {}", currency, date);
+            }
+            None => println!("{} is not in the feed at {}", currency, today),
+        }
+    }
+
+    Ok(())
+}
+
+/// Binary search over daily feeds; assumes a currency stays in the feed once it appears.
+async fn find_available_from(
+    currency: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Option<(NaiveDate, Valute)>> {
+    let Some(mut found) = get_valute(currency, end).await? else {
+        return Ok(None);
+    };
+
+    if let Some(valute) = get_valute(currency, start).await? {
+        return Ok(Some((start, valute)));
+    }
+
+    // На low валюты ещё нет, на high уже есть
+    let mut low = start;
+    let mut high = end;
+
+    while (high - low).num_days() > 1 {
+        let middle = low + (high - low) / 2;
+
+        match get_valute(currency, middle).await? {
+            Some(valute) => {
+                high = middle;
+                found = valute;
+            }
+            None => low = middle,
+        }
+
+        log::debug!("{}: between {} and {}", currency, low, high);
+    }
+
+    Ok(Some((high, found)))
+}
+
+async fn get_valute(currency: &str, date: NaiveDate) -> Result<Option<Valute>> {
+    let val_curs = get_val_curs(date).await?;
+
+    Ok(val_curs
+        .valute
+        .into_iter()
+        .find(|valute| valute.char_code == currency))
+}
+
+/// Metadata of an existing row is left alone: the old feed may carry an outdated name.
+async fn set_available_from(
+    currency: &str,
+    date: NaiveDate,
+    valute: &Valute,
+    pool: &PgPool,
+) -> Result<()> {
+    sqlx::query(&format!(
+        r#"
+            INSERT INTO {currencies} (char_code, cbr_id, num_code, name, available_from, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, NOW(), NOW())
+            ON CONFLICT (char_code) DO UPDATE
+            SET available_from = EXCLUDED.available_from, updated_at = NOW()
+        "#,
+        currencies = get_table_name("currencies")?,
+    ))
+    .bind(currency)
+    .bind(&valute.id)
+    .bind(&valute.num_code)
+    .bind(&valute.name)
+    .bind(date)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use sqlx::PgPool;
+
+use crate::DateOrder;
+use crate::config::get_table_name;
+
+/// For `--resume`: the dates of `dates` after the last one `job` completed, in the same
+/// order. A job without stored progress, or whose last date isn't among `dates` because
+/// the range changed, starts over with a warning in the latter case. A job run in another
+/// `order` fails instead: the dates after its last one are the ones it already did.
+pub async fn resume<'a>(
+    pool: &PgPool,
+    job: &str,
+    dates: &'a [NaiveDate],
+    order: DateOrder,
+) -> Result<&'a [NaiveDate]> {
+    let progress: Option<(NaiveDate, NaiveDate, NaiveDate, Option<String>)> =
+        sqlx::query_as(&format!(
+            r#"
+                SELECT last_date, start_date, end_date, date_order
+                FROM {backfill_progress}
+                WHERE job = $1
+            "#,
+            backfill_progress = get_table_name("backfill_progress")?,
+        ))
+        .bind(job)
+        .fetch_optional(pool)
+        .await?;
+
+    let Some((last_date, start_date, end_date, date_order)) = progress else {
+        log::info!(
+            "No progress stored for job {}, starting from the first date",
+            job
+        );
+        return Ok(dates);
+    };
+
+    match date_order {
+        Some(date_order) if date_order == order.as_str() => {}
+        Some(date_order) => {
+            return Err(anyhow!(
+                "Job {} ran with --order {}, can't resume it with --order {}",
+                job,
+                date_order,
+                order.as_str()
+            ));
+        }
+        None => {
+            return Err(anyhow!(
+                "Job {} was stored without its --order, can't resume it; run it without --resume",
+                job
+            ));
+        }
+    }
+
+    match dates.iter().position(|date| *date == last_date) {
+        Some(position) => {
+            log::info!(
+                "Resuming job {} after {}: {} of {} dates left",
+                job,
+                last_date,
+                dates.len() - position - 1,
+                dates.len()
+            );
+            Ok(&dates[position + 1..])
+        }
+        None => {
+            log::warn!(
+                "Job {} last completed {} of {} to {}, which isn't in this run's dates; starting from the first date",
+                job,
+                last_date,
+                start_date,
+                end_date
+            );
+            Ok(dates)
+        }
+    }
+}
+
+/// Upserts `date` as the last date `job` completed, with the range and order of the run.
+/// The ingest lock is held meanwhile, so two runs never move one job at once.
+pub async fn record(
+    pool: &PgPool,
+    job: &str,
+    date: &NaiveDate,
+    dates: &[NaiveDate],
+    order: DateOrder,
+) -> Result<()> {
+    let start_date = dates.iter().min().ok_or(anyhow!("No dates to store"))?;
+    let end_date = dates.iter().max().ok_or(anyhow!("No dates to store"))?;
+
+    sqlx::query(&format!(
+        r#"
+            INSERT INTO {backfill_progress}
+                (job, last_date, start_date, end_date, date_order, updated_at)
+            VALUES ($1, $2, $3, $4, $5, NOW())
+            ON CONFLICT (job) DO UPDATE
+            SET last_date = EXCLUDED.last_date,
+                start_date = EXCLUDED.start_date,
+                end_date = EXCLUDED.end_date,
+                date_order = EXCLUDED.date_order,
+                updated_at = NOW()
+        "#,
+        backfill_progress = get_table_name("backfill_progress")?,
+    ))
+    .bind(job)
+    .bind(date)
+    .bind(start_date)
+    .bind(end_date)
+    .bind(order.as_str())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::config::{get_cbr_base_url, get_currency_available_until};
+use crate::currency_cache::CurrencyCache;
+use crate::http;
+use crate::val_curs::{ValCurs, Valute};
+
+/// CBR keeps a rate until the next one is set, so a date's rate is the latest record on or
+/// before it. The archive starts this many days before the first date, to find the rate
+/// in force at it even after the January holidays.
+const CARRY_OVER_DAYS: u64 = 14;
+
+/// `XML_dynamic.asp`, the rates of one currency over a range, e.g.
+/// `<ValCurs ID="R01235"><Record Date="02.03.2024" Id="R01235"><Nominal>1</Nominal>
+/// <Value>91,3336</Value><VunitRate>91,3336</VunitRate></Record>...</ValCurs>`, with a
+/// record only for the dates CBR set a rate on.
+#[derive(Debug, Deserialize)]
+struct ValCursDynamic {
+    #[serde(rename = "Record", default)]
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    #[serde(rename = "@Date")]
+    date: String,
+    #[serde(rename = "Nominal")]
+    nominal: String,
+    #[serde(rename = "Value")]
+    value: String,
+    // Как и в ежедневном фиде, в старых записях VunitRate нет
+    #[serde(rename = "VunitRate", default)]
+    vunit_rate: Option<String>,
+}
+
+/// The archives of `--bulk-source`: every currency's records of the range, as the valutes
+/// a daily feed would have, sorted by date.
+#[derive(Debug)]
+pub struct BulkFeed {
+    pub fetched_at: DateTime<Utc>,
+    records: HashMap<String, Vec<(NaiveDate, Valute)>>,
+}
+
+impl BulkFeed {
+    /// Downloads the archive of each currency over `start..=end` in one request per
+    /// currency. A currency without a known CBR ID (not yet fetched by a daily run) or
+    /// whose archive can't be fetched is left out, with a warning, so its dates are
+    /// fetched one by one.
+    pub async fn fetch(
+        start: NaiveDate,
+        end: NaiveDate,
+        currencies: &[String],
+        currency_cache: &CurrencyCache,
+    ) -> Result<Self> {
+        let archive_start = start
+            .checked_sub_days(Days::new(CARRY_OVER_DAYS))
+            .unwrap_or(start);
+        let mut records = HashMap::new();
+
+        for currency in currencies {
+            let Some(cbr_id) = currency_cache.get_cbr_id(currency) else {
+                log::warn!(
+                    "No CBR ID stored for {}, fetching its dates one by one",
+                    currency
+                );
+                continue;
+            };
+
+            match get_records(archive_start, end, currency, cbr_id).await {
+                Ok(currency_records) => {
+                    log::info!(
+                        "CBR archive of {} from {} to {}: {} records",
+                        currency,
+                        archive_start,
+                        end,
+                        currency_records.len()
+                    );
+                    records.insert(currency.clone(), currency_records);
+                }
+                Err(err) => log::warn!(
+                    "Can't fetch the CBR archive of {}, fetching its dates one by one: {}",
+                    currency,
+                    err
+                ),
+            }
+        }
+
+        Ok(BulkFeed {
+            fetched_at: Utc::now(),
+            records,
+        })
+    }
+
+    /// The daily feed of `date` rebuilt from the archives, dated with the latest record it
+    /// takes, or `None` when an archive lacks
+    /// a record on or before the date or one on or after it for any of `currencies`
+    /// (published at the date): a date after the last record may not be published yet, so it
+    /// is fetched from the daily feed instead.
+    pub fn get_val_curs(
+        &self,
+        date: NaiveDate,
+        currencies: &[String],
+        available_from: &HashMap<String, NaiveDate>,
+    ) -> Result<Option<ValCurs>> {
+        let available_until = get_currency_available_until()?;
+        let mut valute = vec![];
+        // Как Date ежедневного фида — дата последней установки курса
+        let mut feed_date = None;
+
+        for currency in currencies {
+            if available_from
+                .get(currency)
+                .is_some_and(|first_date| date < *first_date)
+                || available_until
+                    .get(currency)
+                    .is_some_and(|last_date| date > *last_date)
+            {
+                continue;
+            }
+
+            let Some(records) = self.records.get(currency) else {
+                return Ok(None);
+            };
+
+            if records
+                .last()
+                .is_none_or(|(last_date, _)| *last_date < date)
+            {
+                return Ok(None);
+            }
+
+            let Some((record_date, record)) = records
+                .iter()
+                .rev()
+                .find(|(record_date, _)| *record_date <= date)
+            else {
+                return Ok(None);
+            };
+            feed_date = feed_date.max(Some(*record_date));
+            valute.push(record.clone());
+        }
+
+        Ok(Some(ValCurs {
+            date: feed_date.map(|feed_date| feed_date.format("%d.%m.%Y").to_string()),
+            valute,
+        }))
+    }
+}
+
+async fn get_records(
+    start: NaiveDate,
+    end: NaiveDate,
+    currency: &str,
+    cbr_id: &str,
+) -> Result<Vec<(NaiveDate, Valute)>> {
+    let mut url = get_cbr_base_url()?.join("XML_dynamic.asp")?;
+    url.set_query(Some(&format!(
+        "date_req1={}&date_req2={}&VAL_NM_RQ={}",
+        start.format("%d/%m/%Y"),
+        end.format("%d/%m/%Y"),
+        cbr_id
+    )));
+
+    let text = http::load_xml(url.as_str()).await?;
+    let dynamic: ValCursDynamic = quick_xml::de::from_str(&text)?;
+    let mut records = dynamic
+        .records
+        .into_iter()
+        .map(|record| {
+            let date = NaiveDate::parse_from_str(&record.date, "%d.%m.%Y")?;
+
+            // Курс разбирается так же, как в ежедневном фиде, вместе с ним
+            Ok((
+                date,
+                Valute {
+                    id: cbr_id.to_string(),
+                    num_code: String::new(),
+                    char_code: currency.to_string(),
+                    nominal: record.nominal,
+                    name: String::new(),
+                    value: record.value,
+                    vunit_rate: record.vunit_rate,
+                },
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    records.sort_by_key(|(date, _)| *date);
+
+    Ok(records)
+}
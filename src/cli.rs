@@ -0,0 +1,459 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, builder::BoolishValueParser};
+use reqwest::Url;
+use rust_decimal::Decimal;
+
+use crate::DateOrder;
+use crate::currency_cache::FetchNames;
+use crate::export::ExportFormat;
+use crate::logging::LogFormat;
+use crate::verify::parse_tolerance;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Use this date instead of the current one when computing the default window
+    #[arg(long, global = true, env = "VALUT_NOW", value_name = "YYYY-MM-DD")]
+    pub today: Option<NaiveDate>,
+
+    /// Log line format
+    #[arg(long, global = true, env = "LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// Also write the log lines to this file, rotated by size
+    #[arg(long, global = true, env = "LOG_FILE", value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it would grow past this many megabytes
+    #[arg(
+        long,
+        global = true,
+        env = "LOG_FILE_MAX_MB",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub log_file_max_mb: u64,
+
+    /// Rotated log files to keep, as PATH.1 (newest) to PATH.N
+    #[arg(
+        long,
+        global = true,
+        env = "LOG_FILE_KEEP",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub log_file_keep: u32,
+
+    /// Proxy for CBR requests, overrides HTTP_PROXY/HTTPS_PROXY (NO_PROXY still applies)
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<Url>,
+
+    /// Speak HTTP/1.1 to CBR instead of negotiating HTTP/2, if CBR misbehaves over HTTP/2
+    #[arg(long, global = true)]
+    pub http1_only: bool,
+
+    /// Retry every failed CBR response, not only 5xx and 429
+    #[arg(long, global = true)]
+    pub retry_all_http: bool,
+
+    /// Fail the run instead of retrying once its CBR requests have retried this many times in total
+    #[arg(long, global = true, value_name = "N")]
+    pub max_total_retries: Option<u32>,
+
+    /// Wait exactly the backoff delay between retries instead of a random part of it
+    #[arg(long, global = true)]
+    pub no_jitter: bool,
+
+    /// Store the RUB pairs of these currencies for CBR's Nominal (e.g. 10000 IRR) instead of per unit
+    #[arg(
+        long,
+        global = true,
+        env = "KEEP_NOMINAL_FOR",
+        value_delimiter = ',',
+        value_name = "CODE,CODE"
+    )]
+    pub keep_nominal_for: Vec<String>,
+
+    /// Let /rate fetch and store a date missing from the database from CBR instead of answering 404
+    #[arg(long, global = true)]
+    pub read_through: bool,
+
+    /// Apply the embedded database migrations that haven't been applied yet before running
+    #[arg(long, global = true, env = "AUTO_MIGRATE", value_parser = BoolishValueParser::new())]
+    pub migrate: bool,
+}
+
+impl Cli {
+    /// Whether the command stores anything in the database, the only ones `--migrate`
+    /// applies to: `config-check`, `sample` and the read-only commands never change the
+    /// schema, so a shared `AUTO_MIGRATE=1` doesn't make them write.
+    pub fn writes_to_db(&self) -> bool {
+        match &self.command {
+            None
+            | Some(Command::Ingest(_))
+            | Some(Command::RecomputeCross(_))
+            | Some(Command::Import(_))
+            | Some(Command::DiscoverAvailableFrom(_)) => true,
+            // Без --read-through сервер только читает
+            #[cfg(feature = "server")]
+            Some(Command::Serve) => self.read_through,
+            // golden-test применяет миграции к своей временной схеме сам
+            Some(Command::ConfigCheck(_))
+            | Some(Command::SchemaCheck)
+            | Some(Command::Export(_))
+            | Some(Command::Audit(_))
+            | Some(Command::Coverage(_))
+            | Some(Command::VerifyAll(_))
+            | Some(Command::Sample(_))
+            | Some(Command::Trail(_))
+            | Some(Command::Stats(_))
+            | Some(Command::GoldenTest(_)) => false,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Only serve the HTTP API, without the hourly refresh
+    #[cfg(feature = "server")]
+    Serve,
+
+    /// Fetch and store exchange rates once, then exit
+    Ingest(IngestArgs),
+
+    /// Rewrite cross rates between the configured currencies from the stored RUB rates
+    RecomputeCross(RecomputeCrossArgs),
+
+    /// Validate the environment configuration without connecting anywhere
+    ConfigCheck(ConfigCheckArgs),
+
+    /// Compare the live exchange_rates columns and indexes with the ones the migrations create
+    SchemaCheck,
+
+    /// Print stored rates to stdout
+    Export(ExportArgs),
+
+    /// Store rates from a CSV file without fetching CBR
+    Import(ImportArgs),
+
+    /// Find the first date each currency appears in the CBR feed
+    DiscoverAvailableFrom(DiscoverAvailableFromArgs),
+
+    /// Compare the stored rates of a date against a fresh CBR feed, without writing
+    Audit(AuditArgs),
+
+    /// List the dates of a range that have stored rates and the ones that are missing
+    Coverage(CoverageArgs),
+
+    /// Check every stored date for reciprocal and cross rates that don't add up
+    VerifyAll(VerifyAllArgs),
+
+    /// Fetch and print one CBR feed without connecting to the database
+    #[command(visible_alias = "dump-feed")]
+    Sample(SampleArgs),
+
+    /// Print the stored rates of one pair with the change from the previous date
+    Trail(TrailArgs),
+
+    /// Print the count, min, max, mean and standard deviation of one pair's stored rates
+    Stats(StatsArgs),
+
+    /// Store fixture feeds in a scratch schema and compare the rows with a golden file
+    GoldenTest(GoldenTestArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct IngestArgs {
+    /// First date to fetch (defaults to LOOKBACK_DAYS before today)
+    #[arg(long)]
+    pub start: Option<NaiveDate>,
+
+    /// Last date to fetch (defaults to tomorrow)
+    #[arg(long)]
+    pub end: Option<NaiveDate>,
+
+    /// Fetch exactly these dates instead of a range, e.g. 2024-01-03,2024-01-17
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["start", "end"])]
+    pub dates: Vec<NaiveDate>,
+
+    /// Fetch a single date, same as --start DATE --end DATE
+    #[arg(long, conflicts_with_all = ["start", "end", "dates"])]
+    pub date: Option<NaiveDate>,
+
+    /// Print the INSERT/UPDATE statements as a SQL script instead of executing them
+    #[arg(long)]
+    pub output_sql: bool,
+
+    /// Print the stored and incoming rate of every pair that would change, without writing
+    #[arg(long, conflicts_with = "output_sql")]
+    pub dry_run: bool,
+
+    /// Wait for a concurrent run to finish instead of exiting
+    #[arg(long)]
+    pub wait_for_lock: bool,
+
+    /// Why the run was started, recorded in run_log
+    #[arg(long)]
+    pub reason: Option<String>,
+
+    /// POST the JSON run summary here when the run finishes, whether it succeeded or not
+    #[arg(long, env = "WEBHOOK_URL", value_name = "URL")]
+    pub webhook_url: Option<Url>,
+
+    /// Also store name_ru and name_en, fetching the feed in the other language too
+    #[arg(long, value_enum, default_value_t)]
+    pub fetch_names: FetchNames,
+
+    /// Show a progress bar of fetched dates when stderr is a terminal
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Fail instead of skipping dates after tomorrow, which CBR can't have published yet
+    #[arg(long)]
+    pub strict_future: bool,
+
+    /// Fail when a fetched feed lacks any of CURRENCIES, not only REQUIRED_CURRENCIES
+    #[arg(long)]
+    pub fail_on_missing: bool,
+
+    /// Don't fetch dates that already have every currency stored, fetched on or after the date
+    #[arg(long)]
+    pub skip_complete: bool,
+
+    /// Load the range from CBR's per-currency archives, fetching only uncovered dates one by one
+    #[arg(long)]
+    pub bulk_source: bool,
+
+    /// Keep parsed daily feeds in this directory and reuse them instead of fetching again
+    #[arg(long, env = "FEED_CACHE_DIR", value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Don't print the `SUMMARY {...}` line at the end of stdout
+    #[arg(long)]
+    pub no_summary: bool,
+
+    /// Order the dates are fetched and stored in
+    #[arg(long, value_enum, default_value_t)]
+    pub order: DateOrder,
+
+    /// Rewrite the run's dates in exchange_rates_wide, one row per date and a column per currency
+    #[arg(long, conflicts_with_all = ["output_sql", "dry_run"])]
+    pub maintain_wide: bool,
+
+    /// Record the last completed date of the run in backfill_progress under this name
+    #[arg(long, env = "BACKFILL_JOB", value_name = "NAME", conflicts_with_all = ["output_sql", "dry_run"])]
+    pub job: Option<String>,
+
+    /// Skip the dates up to the last one the job completed, as recorded in backfill_progress
+    #[arg(long, requires = "job")]
+    pub resume: bool,
+
+    /// Print the resolved dates and settings with where each came from, then exit without running
+    #[arg(long)]
+    pub explain: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RecomputeCrossArgs {
+    /// First date to recompute
+    #[arg(long)]
+    pub start: NaiveDate,
+
+    /// Last date to recompute
+    #[arg(long)]
+    pub end: NaiveDate,
+
+    /// Wait for a concurrent run to finish instead of exiting
+    #[arg(long)]
+    pub wait_for_lock: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// First date to export
+    #[arg(long, required_unless_present = "diff")]
+    pub start: Option<NaiveDate>,
+
+    /// Last date to export
+    #[arg(long, required_unless_present = "diff")]
+    pub end: Option<NaiveDate>,
+
+    /// Print only the pairs whose rate differs between two dates
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["DATE1", "DATE2"],
+        conflicts_with_all = ["start", "end"]
+    )]
+    pub diff: Vec<NaiveDate>,
+
+    /// Only pairs from this currency
+    #[arg(long)]
+    pub from: Option<String>,
+
+    /// Only pairs to this currency
+    #[arg(long)]
+    pub to: Option<String>,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+
+    /// Write to this file instead of stdout
+    #[arg(long, value_name = "FILE")]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// CSV with from,to,rate,date[,nominal] rows; a header line is optional
+    #[arg(long)]
+    pub file: PathBuf,
+
+    /// Only validate the file
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Skip the rows up to the line recorded in FILE.checkpoint by an interrupted import
+    #[arg(long, conflicts_with = "dry_run")]
+    pub resume: bool,
+
+    /// Wait for a concurrent run to finish instead of exiting
+    #[arg(long)]
+    pub wait_for_lock: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DiscoverAvailableFromArgs {
+    /// Currencies to look up (defaults to CURRENCIES)
+    #[arg(long = "currency", value_delimiter = ',')]
+    pub currencies: Vec<String>,
+
+    /// Search again even if a date is already stored
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Date to compare
+    #[arg(long)]
+    pub date: NaiveDate,
+
+    /// Exit with an error if any currency differs or is missing on either side
+    #[arg(long)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigCheckArgs {
+    /// Also connect to the database and check the scale of the rate columns
+    #[arg(long)]
+    pub db: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CoverageArgs {
+    /// First date to check
+    #[arg(long)]
+    pub start: NaiveDate,
+
+    /// Last date to check
+    #[arg(long)]
+    pub end: NaiveDate,
+
+    /// Print the summary as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyAllArgs {
+    /// Largest allowed relative deviation, e.g. 1e-9
+    #[arg(long, default_value = "1e-9", value_parser = parse_tolerance)]
+    pub tolerance: Decimal,
+
+    /// How many violations to print
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    pub sample: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct SampleArgs {
+    /// Date to fetch (defaults to today)
+    #[arg(long)]
+    pub date: Option<NaiveDate>,
+
+    /// Print every currency of the feed, not only CURRENCIES
+    #[arg(long)]
+    pub all: bool,
+
+    /// Print the source, the feed's Date and a code -> per-unit rate map as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TrailArgs {
+    /// Currency code, e.g. USD
+    #[arg(long)]
+    pub from: String,
+
+    /// Currency code, e.g. RUB
+    #[arg(long)]
+    pub to: String,
+
+    /// First date to print
+    #[arg(long)]
+    pub start: NaiveDate,
+
+    /// Last date to print
+    #[arg(long)]
+    pub end: NaiveDate,
+
+    /// Print the dates as a JSON array
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Currency code, e.g. USD
+    #[arg(long)]
+    pub from: String,
+
+    /// Currency code, e.g. RUB
+    #[arg(long)]
+    pub to: String,
+
+    /// First date of the range
+    #[arg(long)]
+    pub start: NaiveDate,
+
+    /// Last date of the range
+    #[arg(long)]
+    pub end: NaiveDate,
+
+    /// Print the statistics as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GoldenTestArgs {
+    /// Directory of fixture feeds named YYYY-MM-DD.xml, each stored for its date
+    #[arg(long, default_value = "golden/feeds", value_name = "DIR")]
+    pub fixtures: PathBuf,
+
+    /// JSON file of the expected rows
+    #[arg(long, default_value = "golden/rates.json", value_name = "PATH")]
+    pub golden: PathBuf,
+
+    /// Write the resulting rows to the golden file instead of comparing
+    #[arg(long)]
+    pub update: bool,
+}
@@ -0,0 +1,974 @@
+use std::{collections::HashMap, env, fmt::Display, path::Path, str::FromStr, sync::Once};
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use reqwest::Url;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+const DEFAULT_CURRENCIES: [&str; 2] = ["USD", "EUR"];
+const DEFAULT_LOOKBACK_DAYS: u64 = 6;
+const DEFAULT_DB_CONNECT_RETRIES: u32 = 5;
+const DEFAULT_DB_ACQUIRE_RETRIES: u32 = 2;
+const DEFAULT_HTTP_RETRIES: u32 = 3;
+const DEFAULT_MAX_STALENESS_DAYS: u64 = 14;
+const DEFAULT_MIN_FETCHED_DATES: usize = 1;
+const DEFAULT_CBR_TIMEZONE: Tz = Tz::Europe__Moscow;
+const DEFAULT_CBR_BASE_URL: &str = "https://cbr.ru/scripts/";
+const DEFAULT_HTTP_USER_AGENT: &str = "valut/{version}";
+const MASK: &str = "****";
+
+static RUB_IN_CURRENCIES_WARNING: Once = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CbrLang {
+    Ru,
+    En,
+}
+
+impl FromStr for CbrLang {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "ru" => Ok(CbrLang::Ru),
+            "en" => Ok(CbrLang::En),
+            _ => Err(anyhow!("Unknown CBR_LANG {}, expected ru or en", s)),
+        }
+    }
+}
+
+impl Display for CbrLang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CbrLang::Ru => write!(f, "ru"),
+            CbrLang::En => write!(f, "en"),
+        }
+    }
+}
+
+/// How `rate` is rounded to `RATE_SCALE` places.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateRounding {
+    /// Half away from zero, e.g. 1.2345 -> 1.235: the commercial rule of Russian
+    /// accounting practice and of the EU euro conversion rules (Regulation 1103/97)
+    HalfUp,
+    /// Half to even, e.g. 1.2345 -> 1.234: banker's rounding, the IEEE 754 default, which
+    /// doesn't drift upwards over many roundings
+    HalfEven,
+    /// Drop the extra digits, e.g. 1.2349 -> 1.234: never overstates a rate
+    Truncate,
+}
+
+impl RateRounding {
+    pub fn strategy(&self) -> RoundingStrategy {
+        match self {
+            RateRounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RateRounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RateRounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+impl FromStr for RateRounding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "half_up" => Ok(RateRounding::HalfUp),
+            "half_even" => Ok(RateRounding::HalfEven),
+            "truncate" => Ok(RateRounding::Truncate),
+            _ => Err(anyhow!(
+                "Unknown RATE_ROUNDING {}, expected half_up, half_even or truncate",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for RateRounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateRounding::HalfUp => write!(f, "half_up"),
+            RateRounding::HalfEven => write!(f, "half_even"),
+            RateRounding::Truncate => write!(f, "truncate"),
+        }
+    }
+}
+
+/// What happens when `exchange_rates` stores fewer decimal places than `RATE_SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleCheck {
+    /// Log a warning and carry on
+    Warn,
+    /// Fail before anything is read or written
+    Error,
+    /// Don't look at the columns
+    Off,
+}
+
+impl FromStr for ScaleCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "warn" => Ok(ScaleCheck::Warn),
+            "error" => Ok(ScaleCheck::Error),
+            "off" => Ok(ScaleCheck::Off),
+            _ => Err(anyhow!(
+                "Unknown DB_SCALE_CHECK {}, expected warn, error or off",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for ScaleCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScaleCheck::Warn => write!(f, "warn"),
+            ScaleCheck::Error => write!(f, "error"),
+            ScaleCheck::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// `DATABASE_URL` wins over the individual `POSTGRES_*`/`DB_*` variables. `DB_SSLMODE` and
+/// `DB_SSLROOTCERT` are added to either form; without them sqlx defaults to `prefer`.
+pub fn get_connection_string() -> Result<String> {
+    let connection_string = match env::var("DATABASE_URL") {
+        Ok(url) => check_database_url(&url).map(|_| url)?,
+        Err(_) => get_connection_string_from_parts()?,
+    };
+
+    add_ssl_options(&connection_string)
+}
+
+/// Postgres URL every written rate is mirrored to, best effort; none by default.
+pub fn get_secondary_connection_string() -> Result<Option<String>> {
+    match env::var("DATABASE_URL_SECONDARY") {
+        Ok(url) => check_database_url(&url)
+            .map(|_| Some(url))
+            .map_err(|err| anyhow!("DATABASE_URL_SECONDARY: {}", err)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The connection string with the password replaced, safe to print.
+pub fn get_masked_connection_string() -> Result<String> {
+    check_database_url(&get_connection_string()?)
+}
+
+fn get_connection_string_from_parts() -> Result<String> {
+    let username = get_required("POSTGRES_USER")?;
+    let password = get_required("POSTGRES_PASSWORD")?;
+    let host = get_required("DB_HOST")?;
+    let port = get_db_port()?;
+    let database = get_required("POSTGRES_DB")?;
+
+    let connection_string = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        username, password, host, port, database
+    );
+
+    Ok(connection_string)
+}
+
+pub fn get_db_connect_retries() -> Result<u32> {
+    get_env_or("DB_CONNECT_RETRIES", DEFAULT_DB_CONNECT_RETRIES)
+}
+
+/// Retries of a date's writes when a connection can't be acquired from the pool in time,
+/// unlike `DB_CONNECT_RETRIES`, which only covers the initial connection.
+pub fn get_db_acquire_retries() -> Result<u32> {
+    get_env_or("DB_ACQUIRE_RETRIES", DEFAULT_DB_ACQUIRE_RETRIES)
+}
+
+pub fn get_http_retries() -> Result<u32> {
+    get_env_or("HTTP_RETRIES", DEFAULT_HTTP_RETRIES)
+}
+
+/// `User-Agent` of CBR requests, `valut/0.3.1` by default; `{version}` in `HTTP_USER_AGENT`
+/// is replaced with the crate version, e.g. `valut/{version} (+mailto:ops@example.com)`.
+pub fn get_http_user_agent() -> Result<String> {
+    let value = env::var("HTTP_USER_AGENT").unwrap_or(DEFAULT_HTTP_USER_AGENT.to_string());
+    let user_agent = value.replace("{version}", env!("CARGO_PKG_VERSION"));
+
+    // Заголовок не может быть пустым или содержать управляющие символы
+    if user_agent.trim().is_empty() || !user_agent.chars().all(|c| c == ' ' || c.is_ascii_graphic())
+    {
+        return Err(anyhow!(
+            "Invalid HTTP_USER_AGENT {}, expected printable ASCII",
+            value
+        ));
+    }
+
+    Ok(user_agent)
+}
+
+/// RUB is the base every rate is quoted against, so a `RUB` entry is dropped with a
+/// warning, logged once per process.
+pub fn get_currencies() -> Result<Vec<String>> {
+    let Ok(value) = env::var("CURRENCIES") else {
+        return Ok(DEFAULT_CURRENCIES.map(String::from).to_vec());
+    };
+
+    let mut currencies = get_list(&value)
+        .map(|code| check_currency_code("CURRENCIES", code))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Пара RUB -> RUB была бы тождественной
+    if currencies.iter().any(|code| code == "RUB") {
+        currencies.retain(|code| code != "RUB");
+        RUB_IN_CURRENCIES_WARNING.call_once(|| {
+            log::warn!("Skipping RUB in CURRENCIES, the base every rate is quoted against")
+        });
+    }
+
+    if currencies.is_empty() {
+        return Err(anyhow!("CURRENCIES must list at least one currency"));
+    }
+
+    Ok(currencies)
+}
+
+/// Currencies of `CURRENCIES` whose absence from a feed fails the run; any other missing
+/// currency is logged and skipped.
+pub fn get_required_currencies() -> Result<Vec<String>> {
+    let Ok(value) = env::var("REQUIRED_CURRENCIES") else {
+        return Ok(vec![]);
+    };
+
+    let currencies = get_currencies()?;
+
+    get_list(&value)
+        .map(|code| {
+            let code = check_currency_code("REQUIRED_CURRENCIES", code)?;
+
+            if !currencies.contains(&code) {
+                return Err(anyhow!(
+                    "Required currency {} in REQUIRED_CURRENCIES is not in CURRENCIES",
+                    code
+                ));
+            }
+
+            Ok(code)
+        })
+        .collect()
+}
+
+pub fn get_lookback_days() -> Result<u64> {
+    get_env_or("LOOKBACK_DAYS", DEFAULT_LOOKBACK_DAYS)
+}
+
+pub fn get_max_staleness_days() -> Result<u64> {
+    get_env_or("MAX_STALENESS_DAYS", DEFAULT_MAX_STALENESS_DAYS)
+}
+
+/// Seed of the retry jitter; unset seeds it from the OS.
+pub fn get_retry_jitter_seed() -> Result<Option<u64>> {
+    if env::var("RETRY_JITTER_SEED").is_err() {
+        return Ok(None);
+    }
+
+    get_env_or("RETRY_JITTER_SEED", 0).map(Some)
+}
+
+/// Dates of a run that must have CBR data; `0` lets a run where every date is missing
+/// succeed.
+pub fn get_min_fetched_dates() -> Result<usize> {
+    get_env_or("MIN_FETCHED_DATES", DEFAULT_MIN_FETCHED_DATES)
+}
+
+/// Decimal places `rate` is rounded to; unset keeps the full precision.
+pub fn get_rate_scale() -> Result<Option<u32>> {
+    if env::var("RATE_SCALE").is_err() {
+        return Ok(None);
+    }
+
+    let scale: u32 = get_env_or("RATE_SCALE", 0)?;
+
+    if scale > Decimal::MAX_SCALE {
+        return Err(anyhow!(
+            "Invalid RATE_SCALE value {}, expected at most {}",
+            scale,
+            Decimal::MAX_SCALE
+        ));
+    }
+
+    Ok(Some(scale))
+}
+
+pub fn get_scale_check() -> Result<ScaleCheck> {
+    get_env_or("DB_SCALE_CHECK", ScaleCheck::Warn)
+}
+
+/// Half even unless `RATE_ROUNDING` says otherwise. Before the setting `RATE_SCALE` rounded
+/// half up, so a deployment that must keep its stored values sets `half_up`.
+pub fn get_rate_rounding() -> Result<RateRounding> {
+    get_env_or("RATE_ROUNDING", RateRounding::HalfEven)
+}
+
+pub fn get_cbr_lang() -> Result<CbrLang> {
+    get_env_or("CBR_LANG", CbrLang::Ru)
+}
+
+/// Prepended to every table name, e.g. `TABLE_PREFIX=tenant1_` for
+/// `tenant1_exchange_rates`, so tenants can share a database. The prefix is inserted into
+/// the SQL text, so it is restricted to lowercase letters, digits and underscores.
+pub fn get_table_prefix() -> Result<String> {
+    let prefix = env::var("TABLE_PREFIX").unwrap_or_default();
+
+    if let Some(first) = prefix.chars().next()
+        && !(first.is_ascii_lowercase() || first == '_')
+    {
+        return Err(anyhow!(
+            "Invalid TABLE_PREFIX {}, expected it to start with a lowercase letter or _",
+            prefix
+        ));
+    }
+
+    if !prefix
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid TABLE_PREFIX {}, expected lowercase letters, digits and _",
+            prefix
+        ));
+    }
+
+    // Postgres обрезает имена длиннее 63 байт, самое длинное — exchange_rates_wide
+    if prefix.len() + "exchange_rates_wide".len() > 63 {
+        return Err(anyhow!(
+            "TABLE_PREFIX {} is too long, at most {} characters are allowed",
+            prefix,
+            63 - "exchange_rates_wide".len()
+        ));
+    }
+
+    Ok(prefix)
+}
+
+pub fn get_table_name(table: &str) -> Result<String> {
+    Ok(format!("{}{}", get_table_prefix()?, table))
+}
+
+/// Directory the daily feed pages are requested from. A `file://` URL reads the page
+/// file from disk instead, e.g. `file:///tmp/cbr/` reads `/tmp/cbr/XML_daily.asp`.
+pub fn get_cbr_base_url() -> Result<Url> {
+    let value = env::var("CBR_BASE_URL").unwrap_or(DEFAULT_CBR_BASE_URL.to_string());
+
+    // Без завершающего слэша join заменил бы последний сегмент пути
+    let value = if value.ends_with('/') {
+        value
+    } else {
+        format!("{}/", value)
+    };
+
+    let url =
+        Url::parse(&value).map_err(|err| anyhow!("Invalid CBR_BASE_URL {}: {}", value, err))?;
+
+    if !matches!(url.scheme(), "http" | "https" | "file") {
+        return Err(anyhow!(
+            "Invalid CBR_BASE_URL scheme {}, expected http, https or file",
+            url.scheme()
+        ));
+    }
+
+    Ok(url)
+}
+
+/// IANA time zone "today" is taken in; CBR's business day follows Moscow.
+pub fn get_cbr_timezone() -> Result<Tz> {
+    get_env_or("CBR_TIMEZONE", DEFAULT_CBR_TIMEZONE)
+}
+
+/// Legacy CBR codes mapped to the code they are stored under, e.g. `TMM:TMT,BYR:BYN`.
+pub fn get_currency_aliases() -> Result<HashMap<String, String>> {
+    let Ok(value) = env::var("CURRENCY_ALIASES") else {
+        return Ok(HashMap::new());
+    };
+
+    get_list(&value)
+        .map(|item| {
+            let (legacy_code, canonical_code) = item.split_once(':').ok_or(anyhow!(
+                "Invalid CURRENCY_ALIASES item {}, expected OLD:NEW",
+                item
+            ))?;
+
+            Ok((
+                check_currency_code("CURRENCY_ALIASES", legacy_code)?,
+                check_currency_code("CURRENCY_ALIASES", canonical_code)?,
+            ))
+        })
+        .collect()
+}
+
+/// Weighted pseudo-currencies stored against RUB, e.g. `BSK:USD*0.6+EUR*0.4`. Weights
+/// must be positive and sum to 1, and every component must be in `CURRENCIES`.
+pub fn get_currency_baskets() -> Result<HashMap<String, Vec<(String, Decimal)>>> {
+    let Ok(value) = env::var("CURRENCY_BASKETS") else {
+        return Ok(HashMap::new());
+    };
+
+    let currencies = get_currencies()?;
+
+    get_list(&value)
+        .map(|item| {
+            let (code, components) = item.split_once(':').ok_or(anyhow!(
+                "Invalid CURRENCY_BASKETS item {}, expected CODE:CUR*WEIGHT+CUR*WEIGHT",
+                item
+            ))?;
+            let code = check_currency_code("CURRENCY_BASKETS", code)?;
+
+            if code == "RUB" || currencies.contains(&code) {
+                return Err(anyhow!(
+                    "Basket {} in CURRENCY_BASKETS clashes with a real currency",
+                    code
+                ));
+            }
+
+            let components =
+                get_weighted_components("CURRENCY_BASKETS", &code, components, &currencies)?;
+
+            Ok((code, components))
+        })
+        .collect()
+}
+
+/// A trade-weighted index of the RUB against its components, see [`get_currency_indices`].
+#[derive(Debug, Clone)]
+pub struct CurrencyIndex {
+    pub base_date: NaiveDate,
+    pub components: Vec<(String, Decimal)>,
+}
+
+/// Trade-weighted indices of the RUB, e.g. `NER:2024-01-02:USD*0.5+EUR*0.3+CNY*0.2`, stored
+/// as `NER -> RUB` with 100 at the base date. Weights must be positive and sum to 1, every
+/// component must be in `CURRENCIES`, and the code must not clash with a currency or basket.
+pub fn get_currency_indices() -> Result<HashMap<String, CurrencyIndex>> {
+    let Ok(value) = env::var("CURRENCY_INDICES") else {
+        return Ok(HashMap::new());
+    };
+
+    let currencies = get_currencies()?;
+    let baskets = get_currency_baskets()?;
+
+    get_list(&value)
+        .map(|item| {
+            let invalid_item = || {
+                anyhow!(
+                    "Invalid CURRENCY_INDICES item {}, expected CODE:YYYY-MM-DD:CUR*WEIGHT+CUR*WEIGHT",
+                    item
+                )
+            };
+            let (code, rest) = item.split_once(':').ok_or_else(invalid_item)?;
+            let (base_date, components) = rest.split_once(':').ok_or_else(invalid_item)?;
+            let code = check_currency_code("CURRENCY_INDICES", code)?;
+
+            if code == "RUB" || currencies.contains(&code) || baskets.contains_key(&code) {
+                return Err(anyhow!(
+                    "Index {} in CURRENCY_INDICES clashes with a currency or basket",
+                    code
+                ));
+            }
+
+            let base_date = base_date.trim().parse().map_err(|err| {
+                anyhow!(
+                    "Invalid base date {} of index {} in CURRENCY_INDICES: {}",
+                    base_date,
+                    code,
+                    err
+                )
+            })?;
+            let components =
+                get_weighted_components("CURRENCY_INDICES", &code, components, &currencies)?;
+
+            Ok((
+                code,
+                CurrencyIndex {
+                    base_date,
+                    components,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Parses `CUR*WEIGHT+CUR*WEIGHT` of a basket or index: every currency once and in
+/// `CURRENCIES`, every weight positive and all of them summing to 1.
+fn get_weighted_components(
+    name: &str,
+    code: &str,
+    components: &str,
+    currencies: &[String],
+) -> Result<Vec<(String, Decimal)>> {
+    let components = components
+        .split('+')
+        .map(|component| get_weighted_component(name, code, component, currencies))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (i, (currency, _)) in components.iter().enumerate() {
+        if components[..i].iter().any(|(other, _)| other == currency) {
+            return Err(anyhow!(
+                "Component {} of {} in {} is listed twice",
+                currency,
+                code,
+                name
+            ));
+        }
+    }
+
+    let total: Decimal = components.iter().map(|(_, weight)| weight).sum();
+
+    if total != Decimal::ONE {
+        return Err(anyhow!(
+            "Weights of {} in {} sum to {}, expected 1",
+            code,
+            name,
+            total
+        ));
+    }
+
+    Ok(components)
+}
+
+fn get_weighted_component(
+    name: &str,
+    code: &str,
+    component: &str,
+    currencies: &[String],
+) -> Result<(String, Decimal)> {
+    let (currency, weight) = component.split_once('*').ok_or(anyhow!(
+        "Invalid component {} of {} in {}, expected CUR*WEIGHT",
+        component,
+        code,
+        name
+    ))?;
+    let currency = check_currency_code(name, currency)?;
+
+    if !currencies.contains(&currency) {
+        return Err(anyhow!(
+            "Component {} of {} in {} is not in CURRENCIES",
+            currency,
+            code,
+            name
+        ));
+    }
+
+    let weight: Decimal = weight.trim().parse().map_err(|err| {
+        anyhow!(
+            "Invalid weight {} of {} in {} in {}: {}",
+            weight,
+            currency,
+            code,
+            name,
+            err
+        )
+    })?;
+
+    if weight <= Decimal::ZERO {
+        return Err(anyhow!(
+            "Weight {} of {} in {} in {} is not positive",
+            weight,
+            currency,
+            code,
+            name
+        ));
+    }
+
+    Ok((currency, weight))
+}
+
+/// First date each currency is published, e.g. `CNY:1992-07-01`; earlier dates skip it.
+pub fn get_currency_available_from() -> Result<HashMap<String, NaiveDate>> {
+    get_currency_dates("CURRENCY_AVAILABLE_FROM")
+}
+
+/// Last date each deprecated currency is published, e.g. `EEK:2010-12-31`; later dates
+/// skip it without asking CBR for it, and its stored rates are kept.
+pub fn get_currency_available_until() -> Result<HashMap<String, NaiveDate>> {
+    get_currency_dates("CURRENCY_AVAILABLE_UNTIL")
+}
+
+fn get_currency_dates(name: &str) -> Result<HashMap<String, NaiveDate>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(HashMap::new());
+    };
+
+    get_list(&value)
+        .map(|item| {
+            let (code, date) = item.split_once(':').ok_or(anyhow!(
+                "Invalid {} item {}, expected CODE:YYYY-MM-DD",
+                name,
+                item
+            ))?;
+            let date = date
+                .trim()
+                .parse()
+                .map_err(|err| anyhow!("Invalid {} date {} for {}: {}", name, date, code, err))?;
+
+            Ok((check_currency_code(name, code)?, date))
+        })
+        .collect()
+}
+
+pub fn get_env_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err| anyhow!("Invalid {} value {}: {}", name, value, err)),
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(err) => Err(anyhow!("Can't read {}: {}", name, err)),
+    }
+}
+
+/// Validates every setting without connecting anywhere and prints one line per setting.
+pub fn check() -> Result<()> {
+    let mut failed = 0;
+    let mut report = |name: &str, result: Result<String>| match result {
+        Ok(value) => println!("[ OK ] {} = {}", name, value),
+        Err(err) => {
+            failed += 1;
+            println!("[FAIL] {}: {}", name, err);
+        }
+    };
+
+    if let Ok(url) = env::var("DATABASE_URL") {
+        report("DATABASE_URL", check_database_url(&url));
+    } else {
+        report("POSTGRES_USER", get_required("POSTGRES_USER"));
+        report(
+            "POSTGRES_PASSWORD",
+            get_required("POSTGRES_PASSWORD").map(|_| MASK.to_string()),
+        );
+        report("DB_HOST", get_required("DB_HOST"));
+        report("DB_PORT", get_db_port().map(|port| port.to_string()));
+        report("POSTGRES_DB", get_required("POSTGRES_DB"));
+    }
+
+    report(
+        "DATABASE_URL_SECONDARY",
+        match env::var("DATABASE_URL_SECONDARY") {
+            Ok(url) => check_database_url(&url),
+            Err(_) => Ok("(not set, no mirror)".to_string()),
+        },
+    );
+    report(
+        "DB_SSLMODE",
+        get_db_ssl_mode().map(|value| value.unwrap_or("(not set, sqlx default)".to_string())),
+    );
+    report(
+        "DB_SSLROOTCERT",
+        get_db_ssl_root_cert().map(|value| value.unwrap_or("(not set)".to_string())),
+    );
+    report(
+        "DB_CONNECT_RETRIES",
+        get_db_connect_retries().map(|value| describe("DB_CONNECT_RETRIES", value)),
+    );
+    report(
+        "DB_ACQUIRE_RETRIES",
+        get_db_acquire_retries().map(|value| describe("DB_ACQUIRE_RETRIES", value)),
+    );
+    report(
+        "HTTP_RETRIES",
+        get_http_retries().map(|value| describe("HTTP_RETRIES", value)),
+    );
+    report("HTTP_USER_AGENT", get_http_user_agent());
+    report(
+        "RETRY_JITTER_SEED",
+        get_retry_jitter_seed().map(|seed| match seed {
+            Some(seed) => seed.to_string(),
+            None => "(not set, random)".to_string(),
+        }),
+    );
+    report(
+        "CURRENCIES",
+        get_currencies().map(|value| describe("CURRENCIES", value.join(","))),
+    );
+    report(
+        "REQUIRED_CURRENCIES",
+        get_required_currencies().map(|currencies| {
+            if currencies.is_empty() {
+                "(none)".to_string()
+            } else {
+                currencies.join(",")
+            }
+        }),
+    );
+    report(
+        "LOOKBACK_DAYS",
+        get_lookback_days().map(|value| describe("LOOKBACK_DAYS", value)),
+    );
+    report(
+        "MAX_STALENESS_DAYS",
+        get_max_staleness_days().map(|value| describe("MAX_STALENESS_DAYS", value)),
+    );
+    report(
+        "MIN_FETCHED_DATES",
+        get_min_fetched_dates().map(|value| describe("MIN_FETCHED_DATES", value)),
+    );
+    report(
+        "RATE_SCALE",
+        get_rate_scale().map(|scale| match scale {
+            Some(scale) => scale.to_string(),
+            None => "(not set, full precision)".to_string(),
+        }),
+    );
+    report(
+        "RATE_ROUNDING",
+        get_rate_rounding().map(|value| describe("RATE_ROUNDING", value)),
+    );
+    report(
+        "DB_SCALE_CHECK",
+        get_scale_check().map(|value| describe("DB_SCALE_CHECK", value)),
+    );
+    report(
+        "CBR_LANG",
+        get_cbr_lang().map(|value| describe("CBR_LANG", value)),
+    );
+    report(
+        "CBR_TIMEZONE",
+        get_cbr_timezone().map(|value| describe("CBR_TIMEZONE", value)),
+    );
+    report(
+        "TABLE_PREFIX",
+        get_table_prefix().map(|prefix| {
+            if prefix.is_empty() {
+                "(not set)".to_string()
+            } else {
+                prefix
+            }
+        }),
+    );
+    report(
+        "CBR_BASE_URL",
+        get_cbr_base_url().map(|value| describe("CBR_BASE_URL", value)),
+    );
+    report(
+        "CURRENCY_ALIASES",
+        get_currency_aliases().map(|aliases| {
+            let mut aliases: Vec<_> = aliases
+                .iter()
+                .map(|(legacy_code, canonical_code)| format!("{}:{}", legacy_code, canonical_code))
+                .collect();
+            aliases.sort();
+
+            if aliases.is_empty() {
+                "(none)".to_string()
+            } else {
+                aliases.join(",")
+            }
+        }),
+    );
+    report(
+        "CURRENCY_AVAILABLE_FROM",
+        get_currency_available_from().map(|available_from| describe_dates(&available_from)),
+    );
+    report(
+        "CURRENCY_AVAILABLE_UNTIL",
+        get_currency_available_until().map(|available_until| describe_dates(&available_until)),
+    );
+    report(
+        "CURRENCY_BASKETS",
+        get_currency_baskets().map(|baskets| {
+            let mut baskets: Vec<_> = baskets
+                .iter()
+                .map(|(code, components)| {
+                    let components: Vec<_> = components
+                        .iter()
+                        .map(|(currency, weight)| format!("{}*{}", currency, weight))
+                        .collect();
+
+                    format!("{}:{}", code, components.join("+"))
+                })
+                .collect();
+            baskets.sort();
+
+            if baskets.is_empty() {
+                "(none)".to_string()
+            } else {
+                baskets.join(",")
+            }
+        }),
+    );
+    report(
+        "CURRENCY_INDICES",
+        get_currency_indices().map(|indices| {
+            let mut indices: Vec<_> = indices
+                .iter()
+                .map(|(code, index)| {
+                    let components: Vec<_> = index
+                        .components
+                        .iter()
+                        .map(|(currency, weight)| format!("{}*{}", currency, weight))
+                        .collect();
+
+                    format!("{}:{}:{}", code, index.base_date, components.join("+"))
+                })
+                .collect();
+            indices.sort();
+
+            if indices.is_empty() {
+                "(none)".to_string()
+            } else {
+                indices.join(",")
+            }
+        }),
+    );
+    report(
+        "ADMIN_TOKEN",
+        Ok(match env::var("ADMIN_TOKEN") {
+            Ok(_) => MASK.to_string(),
+            Err(_) => "(not set, /reingest is disabled)".to_string(),
+        }),
+    );
+    report("KAFKA_BROKERS, KAFKA_TOPIC", check_kafka());
+
+    if failed > 0 {
+        return Err(anyhow!("Configuration check failed: {} problem(s)", failed));
+    }
+
+    Ok(())
+}
+
+fn add_ssl_options(connection_string: &str) -> Result<String> {
+    let ssl_mode = get_db_ssl_mode()?;
+    let ssl_root_cert = get_db_ssl_root_cert()?;
+
+    if ssl_mode.is_none() && ssl_root_cert.is_none() {
+        return Ok(connection_string.to_string());
+    }
+
+    let mut url = Url::parse(connection_string)
+        .map_err(|err| anyhow!("Invalid database connection string: {}", err))?;
+
+    {
+        let mut query = url.query_pairs_mut();
+
+        if let Some(ssl_mode) = &ssl_mode {
+            query.append_pair("sslmode", ssl_mode);
+        }
+
+        if let Some(ssl_root_cert) = &ssl_root_cert {
+            query.append_pair("sslrootcert", ssl_root_cert);
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+fn get_db_ssl_mode() -> Result<Option<String>> {
+    let Ok(value) = env::var("DB_SSLMODE") else {
+        return Ok(None);
+    };
+
+    match value.as_str() {
+        "disable" | "allow" | "prefer" | "require" | "verify-ca" | "verify-full" => Ok(Some(value)),
+        _ => Err(anyhow!(
+            "Invalid DB_SSLMODE value {}, expected disable, allow, prefer, require, verify-ca or verify-full",
+            value
+        )),
+    }
+}
+
+fn get_db_ssl_root_cert() -> Result<Option<String>> {
+    let Ok(path) = env::var("DB_SSLROOTCERT") else {
+        return Ok(None);
+    };
+
+    if !Path::new(&path).is_file() {
+        return Err(anyhow!("DB_SSLROOTCERT file {} does not exist", path));
+    }
+
+    Ok(Some(path))
+}
+
+fn get_required(name: &str) -> Result<String> {
+    env::var(name).map_err(|err| anyhow!("Can't read {}: {}", name, err))
+}
+
+fn get_db_port() -> Result<u16> {
+    let value = get_required("DB_PORT")?;
+
+    value
+        .parse()
+        .map_err(|err| anyhow!("Invalid DB_PORT value {}: {}", value, err))
+}
+
+fn get_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn check_currency_code(name: &str, code: &str) -> Result<String> {
+    let code = code.trim().to_uppercase();
+
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(anyhow!(
+            "Invalid currency code {} in {}, expected 3 letters",
+            code,
+            name
+        ));
+    }
+
+    Ok(code)
+}
+
+/// Returns the URL with the password masked.
+fn check_database_url(url: &str) -> Result<String> {
+    let mut url = Url::parse(url).map_err(|err| anyhow!("Invalid DATABASE_URL: {}", err))?;
+
+    if !matches!(url.scheme(), "postgres" | "postgresql") {
+        return Err(anyhow!(
+            "Invalid DATABASE_URL scheme {}, expected postgres",
+            url.scheme()
+        ));
+    }
+
+    if url.password().is_some() {
+        url.set_password(Some(MASK))
+            .map_err(|_| anyhow!("Invalid DATABASE_URL"))?;
+    }
+
+    Ok(url.to_string())
+}
+
+fn check_kafka() -> Result<String> {
+    match (env::var("KAFKA_BROKERS"), env::var("KAFKA_TOPIC")) {
+        (Ok(brokers), Ok(topic)) => Ok(format!("{}, {}", brokers, topic)),
+        (Err(_), Err(_)) => Ok("(not set, Kafka sink is disabled)".to_string()),
+        _ => Err(anyhow!(
+            "KAFKA_BROKERS and KAFKA_TOPIC must be set together"
+        )),
+    }
+}
+
+fn describe_dates(dates: &HashMap<String, NaiveDate>) -> String {
+    let mut dates: Vec<_> = dates
+        .iter()
+        .map(|(code, date)| format!("{}:{}", code, date))
+        .collect();
+    dates.sort();
+
+    if dates.is_empty() {
+        "(none)".to_string()
+    } else {
+        dates.join(",")
+    }
+}
+
+fn describe(name: &str, value: impl Display) -> String {
+    if env::var(name).is_ok() {
+        value.to_string()
+    } else {
+        format!("{} (default)", value)
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::cli::CoverageArgs;
+use crate::config::{get_currencies, get_table_name};
+
+#[derive(Debug, Serialize)]
+struct Coverage {
+    start: NaiveDate,
+    end: NaiveDate,
+    expected: usize,
+    present: usize,
+    missing: Vec<NaiveDate>,
+}
+
+/// Reports which dates of the range have stored `X -> RUB` rates for every configured
+/// currency. Ingest stores every calendar date (CBR carries rates over weekends and
+/// holidays), so every date of the range is expected.
+pub async fn coverage(args: CoverageArgs, pool: &PgPool) -> Result<()> {
+    if args.start > args.end {
+        return Err(anyhow!("Start date must be before end date"));
+    }
+
+    let currencies = get_currencies()?;
+    let present_dates = get_present_dates(pool, args.start, args.end, &currencies).await?;
+
+    let mut expected = 0;
+    let mut missing = vec![];
+    let mut current_date = args.start;
+
+    while current_date <= args.end {
+        expected += 1;
+
+        if !present_dates.contains(&current_date) {
+            missing.push(current_date);
+        }
+
+        current_date = current_date
+            .succ_opt()
+            .ok_or(anyhow!("Can't get next date for {}", current_date))?;
+    }
+
+    let coverage = Coverage {
+        start: args.start,
+        end: args.end,
+        expected,
+        present: expected - missing.len(),
+        missing,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string(&coverage)?);
+    } else if coverage.missing.is_empty() {
+        println!("{}/{} present", coverage.present, coverage.expected);
+    } else {
+        let missing: Vec<String> = coverage
+            .missing
+            .iter()
+            .map(|date| date.to_string())
+            .collect();
+
+        println!(
+            "{}/{} present, missing {}",
+            coverage.present,
+            coverage.expected,
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn get_present_dates(
+    pool: &PgPool,
+    start: NaiveDate,
+    end: NaiveDate,
+    currencies: &[String],
+) -> Result<BTreeSet<NaiveDate>> {
+    let rows: Vec<(NaiveDate,)> = sqlx::query_as(&format!(
+        r#"
+            SELECT date
+            FROM {exchange_rates}
+            WHERE to_currency = 'RUB' AND date BETWEEN $1 AND $2 AND from_currency = ANY($3)
+            GROUP BY date
+            HAVING COUNT(DISTINCT from_currency) = $4
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(currencies)
+    .bind(currencies.len() as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(date,)| date).collect())
+}
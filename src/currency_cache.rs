@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use sqlx::{FromRow, PgPool};
+
+use crate::config::get_table_name;
+use crate::val_curs::Valute;
+
+/// Which feeds currency names are taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum FetchNames {
+    /// Only `name`, from the `CBR_LANG` feed
+    #[default]
+    Feed,
+    /// Also `name_ru` and `name_en`, fetching the other language's feed for every date
+    Both,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyNames {
+    pub ru: String,
+    pub en: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CurrencyMetadata {
+    cbr_id: String,
+    num_code: String,
+    name: String,
+}
+
+impl From<&Valute> for CurrencyMetadata {
+    fn from(valute: &Valute) -> Self {
+        CurrencyMetadata {
+            cbr_id: valute.id.clone(),
+            num_code: valute.num_code.clone(),
+            name: valute.name.clone(),
+        }
+    }
+}
+
+/// Stored currency metadata keyed by char code, loaded once per run so that
+/// unchanged currencies are not upserted again for every date.
+#[derive(Debug, Default)]
+pub struct CurrencyCache {
+    stored: HashMap<String, CurrencyMetadata>,
+    names: HashMap<String, CurrencyNames>,
+    /// First feed dates found by `discover-available-from`.
+    pub available_from: HashMap<String, NaiveDate>,
+    pub written: u64,
+    pub skipped: u64,
+}
+
+impl CurrencyCache {
+    pub async fn load(pool: &PgPool) -> Result<Self> {
+        let rows: Vec<CurrencyRow> = sqlx::query_as(&format!(
+            r#"
+                SELECT char_code, cbr_id, num_code, name, available_from, name_ru, name_en
+                FROM {currencies}
+            "#,
+            currencies = get_table_name("currencies")?,
+        ))
+        .fetch_all(pool)
+        .await?;
+
+        let available_from = rows
+            .iter()
+            .filter_map(|row| Some((row.char_code.clone(), row.available_from?)))
+            .collect();
+
+        let names = rows
+            .iter()
+            .filter_map(|row| {
+                Some((
+                    row.char_code.clone(),
+                    CurrencyNames {
+                        ru: row.name_ru.clone()?,
+                        en: row.name_en.clone()?,
+                    },
+                ))
+            })
+            .collect();
+
+        let stored = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.char_code,
+                    CurrencyMetadata {
+                        cbr_id: row.cbr_id,
+                        num_code: row.num_code,
+                        name: row.name,
+                    },
+                )
+            })
+            .collect();
+
+        Ok(CurrencyCache {
+            stored,
+            names,
+            available_from,
+            ..Default::default()
+        })
+    }
+
+    pub fn is_stored(&self, char_code: &str, valute: &Valute) -> bool {
+        self.stored
+            .get(char_code)
+            .is_some_and(|metadata| *metadata == CurrencyMetadata::from(valute))
+    }
+
+    pub fn set(&mut self, char_code: &str, valute: &Valute) {
+        self.stored
+            .insert(char_code.to_string(), CurrencyMetadata::from(valute));
+    }
+
+    /// The CBR ID (`R01235` for USD) last stored for the char code.
+    pub fn get_cbr_id(&self, char_code: &str) -> Option<&str> {
+        self.stored
+            .get(char_code)
+            .map(|metadata| metadata.cbr_id.as_str())
+    }
+
+    pub fn has_names(&self, char_code: &str, names: &CurrencyNames) -> bool {
+        self.names.get(char_code) == Some(names)
+    }
+
+    pub fn set_names(&mut self, char_code: &str, names: &CurrencyNames) {
+        self.names.insert(char_code.to_string(), names.clone());
+    }
+}
+
+#[derive(FromRow)]
+struct CurrencyRow {
+    char_code: String,
+    cbr_id: String,
+    num_code: String,
+    name: String,
+    available_from: Option<NaiveDate>,
+    name_ru: Option<String>,
+    name_en: Option<String>,
+}
@@ -0,0 +1,150 @@
+//! ECB reference rates, quoted against EUR. No command fetches them yet; the helpers
+//! are the bridge an ECB source would store its rates through.
+
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use rust_decimal::Decimal;
+
+use crate::val_curs::DecimalFormat;
+
+/// ECB writes `rate="1.0812"`: a decimal point and no digit grouping.
+pub const DECIMAL_FORMAT: DecimalFormat = DecimalFormat {
+    decimal_separator: '.',
+    group_separator: None,
+};
+
+/// Parses an ECB rate. A comma never means a decimal here, so `1,0812` fails instead of
+/// being read as CBR would.
+pub fn parse_rate(value: &str) -> Result<Decimal> {
+    DECIMAL_FORMAT
+        .parse(value)
+        .ok_or(anyhow!("Invalid ECB rate {}", value))
+}
+
+/// Converts ECB reference rates into the RUB-based rates stored by valut.
+///
+/// ECB quotes every currency against EUR: `eur_rates["USD"] = 1.0812` means
+/// 1 EUR = 1.0812 USD. The table stores `X -> RUB` as the number of roubles for one
+/// unit of X, so with a known `EUR -> RUB` rate:
+///
+/// ```text
+/// X -> RUB = (EUR -> RUB) / (EUR -> X)
+/// ```
+///
+/// `eur_rub` is taken as an `Option`, e.g. `cbr_rates.get("EUR")`, and must be positive:
+/// without it, or with a zero one, there is nothing to bridge through.
+///
+/// EUR itself maps to `eur_rub` unchanged, and an `RUB` entry in `eur_rates` is ignored
+/// because it would only restate the bridge. The division is done in `Decimal` with 28
+/// significant digits, so the arithmetic adds no meaningful error. The accuracy of the
+/// result depends on the inputs. ECB publishes 4-6 significant digits, and the bridge
+/// rate may come from CBR at a different fixing time. The derived rates can therefore
+/// differ from CBR's own quote for the same currency in the 4th-5th significant digit.
+/// Prefer CBR's direct quote when both exist.
+pub fn get_rub_rates_from_eur_base(
+    eur_rates: &HashMap<String, Decimal>,
+    eur_rub: Option<&Decimal>,
+) -> Result<HashMap<String, Decimal>> {
+    let eur_rub = eur_rub.ok_or(anyhow!("No EUR -> RUB rate to bridge ECB rates through"))?;
+
+    if *eur_rub <= Decimal::ZERO {
+        return Err(anyhow!(
+            "Can't bridge ECB rates through EUR -> RUB {}: it must be positive",
+            eur_rub
+        ));
+    }
+
+    let mut rates = HashMap::new();
+
+    for (currency, eur_rate) in eur_rates {
+        if currency == "RUB" || currency == "EUR" {
+            continue;
+        }
+
+        let rate = eur_rub.checked_div(*eur_rate).ok_or(anyhow!(
+            "Can't bridge {} -> RUB through EUR: {} / {} is undefined or out of range",
+            currency,
+            eur_rub,
+            eur_rate
+        ))?;
+
+        rates.insert(currency.clone(), rate);
+    }
+
+    rates.insert("EUR".to_string(), *eur_rub);
+
+    Ok(rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn decimal(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn eur_rates(rates: &[(&str, &str)]) -> HashMap<String, Decimal> {
+        rates
+            .iter()
+            .map(|(currency, rate)| (currency.to_string(), decimal(rate)))
+            .collect()
+    }
+
+    #[test]
+    fn usd_is_bridged_through_eur() {
+        let rates = get_rub_rates_from_eur_base(
+            &eur_rates(&[("USD", "1.08"), ("RUB", "99.5")]),
+            Some(&decimal("100")),
+        )
+        .unwrap();
+
+        // 100 / 1.08 с 28 значащими цифрами Decimal
+        assert_eq!(rates["USD"].to_string(), "92.59259259259259259259259259");
+        assert_eq!(rates["EUR"], decimal("100"));
+        assert!(!rates.contains_key("RUB"));
+    }
+
+    #[test]
+    fn eur_passes_through_unchanged() {
+        let rates =
+            get_rub_rates_from_eur_base(&eur_rates(&[("EUR", "1")]), Some(&decimal("98.2615")))
+                .unwrap();
+
+        assert_eq!(rates, eur_rates(&[("EUR", "98.2615")]));
+    }
+
+    #[test]
+    fn missing_or_zero_bridge_is_an_error() {
+        let usd = eur_rates(&[("USD", "1.08")]);
+
+        assert_eq!(
+            get_rub_rates_from_eur_base(&usd, None)
+                .unwrap_err()
+                .to_string(),
+            "No EUR -> RUB rate to bridge ECB rates through"
+        );
+        assert_eq!(
+            get_rub_rates_from_eur_base(&usd, Some(&Decimal::ZERO))
+                .unwrap_err()
+                .to_string(),
+            "Can't bridge ECB rates through EUR -> RUB 0: it must be positive"
+        );
+        assert!(
+            get_rub_rates_from_eur_base(&eur_rates(&[("USD", "0")]), Some(&decimal("100")))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn ecb_rate_with_a_decimal_comma_is_rejected() {
+        assert_eq!(parse_rate(" 1.0812 ").unwrap(), decimal("1.0812"));
+        assert_eq!(
+            parse_rate("1,0812").unwrap_err().to_string(),
+            "Invalid ECB rate 1,0812"
+        );
+    }
+}
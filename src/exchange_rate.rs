@@ -1,8 +1,62 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
+/// A row's `rate` per one unit of each currency. The RUB pairs of a `--keep-nominal-for`
+/// currency are stored for `nominal` units of it: `X -> RUB` is the price of that many
+/// units, so it is divided, and `RUB -> X` counts in them, so it is multiplied. Every
+/// other row has `nominal` 1.
+pub const PER_UNIT_RATE_SQL: &str =
+    "CASE WHEN from_currency = 'RUB' THEN rate * nominal ELSE rate / nominal END";
+
+/// `PER_UNIT_RATE_SQL` for a row already read; `None` on overflow.
+pub fn get_per_unit_rate(from_currency: &str, rate: &Decimal, nominal: i32) -> Option<Decimal> {
+    let nominal = Decimal::from(nominal);
+
+    if from_currency == "RUB" {
+        rate.checked_mul(nominal)
+    } else {
+        rate.checked_div(nominal)
+    }
+    .map(|rate| rate.normalize())
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct ExchangeRate {
     pub id: Uuid,
     pub rate: Decimal,
+    pub fetched_at: Option<DateTime<Utc>>,
+}
+
+/// How a stored pair is quoted from the rouble's side. Every row reads "one `from` costs
+/// `rate` of `to`"; the convention says whether that is CBR's own direct quote, its
+/// reciprocal, or a cross rate derived through RUB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteConvention {
+    /// `X -> RUB`: roubles per unit of the foreign currency, as CBR publishes it
+    Direct,
+    /// `RUB -> X`: units of the foreign currency per rouble
+    Indirect,
+    /// `X -> Y` without RUB, e.g. `USD -> EUR`
+    Cross,
+}
+
+impl QuoteConvention {
+    pub fn of(from_currency: &str, to_currency: &str) -> Self {
+        if to_currency == "RUB" {
+            QuoteConvention::Direct
+        } else if from_currency == "RUB" {
+            QuoteConvention::Indirect
+        } else {
+            QuoteConvention::Cross
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuoteConvention::Direct => "direct",
+            QuoteConvention::Indirect => "indirect",
+            QuoteConvention::Cross => "cross",
+        }
+    }
 }
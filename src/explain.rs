@@ -0,0 +1,274 @@
+use std::env;
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+use crate::cli::IngestArgs;
+use crate::config::{
+    get_cbr_lang, get_cbr_timezone, get_currencies, get_currency_aliases,
+    get_currency_available_from, get_currency_available_until, get_currency_baskets,
+    get_currency_indices, get_lookback_days, get_masked_connection_string, get_rate_rounding,
+    get_rate_scale, get_required_currencies, get_table_prefix,
+};
+use crate::currency_cache::FetchNames;
+use crate::{DateOrder, get_ingest_dates, get_today, get_url};
+
+/// Prints what `ingest` would do with these arguments and the environment, and where each
+/// value came from, without connecting to the database or CBR. Flags win over
+/// environment variables, which win over the defaults.
+pub async fn explain(args: &IngestArgs, today: Option<NaiveDate>) -> Result<()> {
+    let dates = get_ingest_dates(args, today)?;
+
+    print(
+        "today",
+        get_today(today)?,
+        &match today {
+            Some(_) => "--today or VALUT_NOW".to_string(),
+            None => format!("current date in CBR_TIMEZONE {}", get_cbr_timezone()?),
+        },
+    );
+    print(
+        "dates",
+        describe_dates(&dates, args.dates.is_empty()),
+        &get_dates_origin(args)?,
+    );
+    print(
+        "order",
+        match args.order {
+            DateOrder::Asc => "oldest first",
+            DateOrder::Desc => "newest first",
+        },
+        "--order",
+    );
+    print(
+        "currencies",
+        get_currencies()?.join(","),
+        &get_origin("CURRENCIES"),
+    );
+    print(
+        "required",
+        describe_list(get_required_currencies()?.into_iter()),
+        &get_origin("REQUIRED_CURRENCIES"),
+    );
+    print(
+        "missing currency",
+        if args.fail_on_missing {
+            "fails the run"
+        } else {
+            "skipped with a warning, unless required"
+        },
+        "--fail-on-missing",
+    );
+    print("base", "RUB", "CBR quotes every currency against RUB");
+    print(
+        "aliases",
+        describe_list(
+            get_currency_aliases()?
+                .iter()
+                .map(|(from, to)| format!("{}:{}", from, to)),
+        ),
+        &get_origin("CURRENCY_ALIASES"),
+    );
+    print(
+        "available from",
+        describe_list(
+            get_currency_available_from()?
+                .iter()
+                .map(|(code, date)| format!("{}:{}", code, date)),
+        ),
+        &get_origin("CURRENCY_AVAILABLE_FROM"),
+    );
+    print(
+        "available until",
+        describe_list(
+            get_currency_available_until()?
+                .iter()
+                .map(|(code, date)| format!("{}:{}", code, date)),
+        ),
+        &get_origin("CURRENCY_AVAILABLE_UNTIL"),
+    );
+    print(
+        "baskets",
+        describe_list(get_currency_baskets()?.keys().cloned()),
+        &get_origin("CURRENCY_BASKETS"),
+    );
+    print(
+        "indices",
+        describe_list(
+            get_currency_indices()?
+                .iter()
+                .map(|(code, index)| format!("{}:{}", code, index.base_date)),
+        ),
+        &get_origin("CURRENCY_INDICES"),
+    );
+    print(
+        "source",
+        get_url(
+            *dates.first().ok_or(anyhow!("No dates to fetch"))?,
+            get_cbr_lang()?,
+        )
+        .await?,
+        &format!(
+            "CBR_BASE_URL {}, CBR_LANG {}",
+            get_origin("CBR_BASE_URL"),
+            get_origin("CBR_LANG")
+        ),
+    );
+    print(
+        "feed",
+        if args.bulk_source {
+            "per-currency archives over the range, daily feed for uncovered dates"
+        } else {
+            "daily feed per date"
+        },
+        "--bulk-source",
+    );
+    print(
+        "backfill job",
+        match (&args.job, args.resume) {
+            (Some(job), true) => format!("{}, resumed after its last completed date", job),
+            (Some(job), false) => format!("{}, progress recorded from the first date", job),
+            (None, _) => "(none)".to_string(),
+        },
+        "--job or BACKFILL_JOB, --resume",
+    );
+    print(
+        "feed cache",
+        match &args.cache_dir {
+            Some(dir) => format!("parsed feeds reused from and kept in {}", dir.display()),
+            None => "(none)".to_string(),
+        },
+        "--cache-dir or FEED_CACHE_DIR",
+    );
+    print(
+        "names",
+        match args.fetch_names {
+            FetchNames::Feed => "name from the CBR_LANG feed",
+            FetchNames::Both => "name, name_ru and name_en, fetching both feeds",
+        },
+        "--fetch-names",
+    );
+    print(
+        "rounding",
+        match get_rate_scale()? {
+            Some(scale) => format!("{} decimal places, {}", scale, get_rate_rounding()?),
+            None => "full precision".to_string(),
+        },
+        &format!(
+            "RATE_SCALE {}, RATE_ROUNDING {}",
+            get_origin("RATE_SCALE"),
+            get_origin("RATE_ROUNDING")
+        ),
+    );
+    print(
+        "database",
+        get_masked_connection_string()?,
+        if env::var("DATABASE_URL").is_ok() {
+            "DATABASE_URL"
+        } else {
+            "POSTGRES_USER, POSTGRES_PASSWORD, DB_HOST, DB_PORT, POSTGRES_DB"
+        },
+    );
+    print(
+        "table prefix",
+        match get_table_prefix()? {
+            prefix if prefix.is_empty() => "(none)".to_string(),
+            prefix => prefix,
+        },
+        &get_origin("TABLE_PREFIX"),
+    );
+    print(
+        "mode",
+        if args.output_sql {
+            "print SQL"
+        } else if args.dry_run {
+            "dry run, nothing is written"
+        } else {
+            "write"
+        },
+        "--output-sql, --dry-run",
+    );
+    print(
+        "complete dates",
+        if args.skip_complete {
+            "skipped without fetching"
+        } else {
+            "fetched again for revisions"
+        },
+        "--skip-complete",
+    );
+    print(
+        "lock",
+        if args.wait_for_lock {
+            "wait for a concurrent run"
+        } else {
+            "exit if another run is in progress"
+        },
+        "--wait-for-lock",
+    );
+
+    Ok(())
+}
+
+fn print(name: &str, value: impl ToString, origin: &str) {
+    println!("{} = {} ({})", name, value.to_string(), origin);
+}
+
+fn get_origin(name: &str) -> String {
+    if env::var(name).is_ok() {
+        name.to_string()
+    } else {
+        "default".to_string()
+    }
+}
+
+fn get_dates_origin(args: &IngestArgs) -> Result<String> {
+    if args.date.is_some() {
+        return Ok("--date".to_string());
+    }
+
+    if !args.dates.is_empty() {
+        return Ok("--dates".to_string());
+    }
+
+    let start = match args.start {
+        Some(_) => "--start".to_string(),
+        None => format!(
+            "LOOKBACK_DAYS {} ({}) before today",
+            get_lookback_days()?,
+            get_origin("LOOKBACK_DAYS")
+        ),
+    };
+    let end = match args.end {
+        Some(_) => "--end",
+        None => "tomorrow",
+    };
+
+    Ok(format!("{} to {}, at most tomorrow", start, end))
+}
+
+/// `dates` is in the order ingest fetches them, newest first unless `--order asc`.
+fn describe_dates(dates: &[NaiveDate], is_range: bool) -> String {
+    match dates {
+        [first, .., last] if is_range => {
+            format!(
+                "{} to {}, {} dates",
+                first.min(last),
+                first.max(last),
+                dates.len()
+            )
+        }
+        _ => describe_list(dates.iter().map(|date| date.to_string())),
+    }
+}
+
+fn describe_list(items: impl Iterator<Item = String>) -> String {
+    let mut items: Vec<String> = items.collect();
+    items.sort();
+
+    if items.is_empty() {
+        "(none)".to_string()
+    } else {
+        items.join(",")
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use futures_util::{TryStreamExt, stream::BoxStream};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+use sqlx::{FromRow, PgPool};
+
+use crate::cli::ExportArgs;
+use crate::config::get_table_name;
+use crate::exchange_rate::get_per_unit_rate;
+use crate::get_effective_at;
+
+#[cfg(feature = "parquet")]
+mod parquet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+    /// InfluxDB line protocol
+    Influx,
+    /// Apache Parquet, written to --out
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Rate {
+    from_currency: String,
+    to_currency: String,
+    rate: Decimal,
+    date: NaiveDate,
+    /// Units of the currency `rate` is for: CBR's `Nominal` for a `--keep-nominal-for`
+    /// pair with RUB, 1 otherwise.
+    nominal: i32,
+}
+
+impl Rate {
+    fn get_per_unit_rate(&self) -> Result<Decimal> {
+        get_per_unit_rate(&self.from_currency, &self.rate, self.nominal).ok_or(anyhow!(
+            "Rate {} for {} units of {} -> {} is out of range per unit",
+            self.rate,
+            self.nominal,
+            self.from_currency,
+            self.to_currency
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Change {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RateDiff {
+    from_currency: String,
+    to_currency: String,
+    change: Change,
+    old_rate: Option<Decimal>,
+    new_rate: Option<Decimal>,
+    delta: Option<Decimal>,
+}
+
+pub async fn export(args: ExportArgs, pool: &PgPool) -> Result<()> {
+    let from_currency = args.from.map(|code| code.to_uppercase());
+    let to_currency = args.to.map(|code| code.to_uppercase());
+    let query = get_rates_query()?;
+    let mut out: Box<dyn Write> = match &args.out {
+        #[cfg(feature = "parquet")]
+        Some(_) if args.format == ExportFormat::Parquet => Box::new(io::sink()),
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).map_err(|err| {
+                anyhow!("Can't create {}: {}", path.display(), err)
+            })?))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    if let [old_date, new_date] = args.diff[..] {
+        let old_rates = get_rates(
+            pool,
+            &query,
+            old_date,
+            old_date,
+            from_currency.as_deref(),
+            to_currency.as_deref(),
+        )
+        .try_collect()
+        .await?;
+        let new_rates = get_rates(
+            pool,
+            &query,
+            new_date,
+            new_date,
+            from_currency.as_deref(),
+            to_currency.as_deref(),
+        )
+        .try_collect()
+        .await?;
+
+        write_diffs(&mut out, &get_diffs(old_rates, new_rates)?, args.format)?;
+        out.flush()?;
+
+        return Ok(());
+    }
+
+    let (Some(start), Some(end)) = (args.start, args.end) else {
+        return Err(anyhow!("Either --start and --end or --diff is required"));
+    };
+
+    if start > end {
+        return Err(anyhow!("Start date must be before end date"));
+    }
+
+    let rates = get_rates(
+        pool,
+        &query,
+        start,
+        end,
+        from_currency.as_deref(),
+        to_currency.as_deref(),
+    );
+
+    // Parquet пишется в файл через ArrowWriter, а не в общий буфер
+    #[cfg(feature = "parquet")]
+    if let (ExportFormat::Parquet, Some(path)) = (args.format, &args.out) {
+        return parquet::write_rates(path, rates).await;
+    }
+
+    write_rates(&mut out, rates, args.format).await?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Streams the rows from a cursor, so a range export holds one row at a time whatever
+/// its size.
+fn get_rates<'a>(
+    pool: &'a PgPool,
+    query: &'a str,
+    start: NaiveDate,
+    end: NaiveDate,
+    from_currency: Option<&'a str>,
+    to_currency: Option<&'a str>,
+) -> BoxStream<'a, sqlx::Result<Rate>> {
+    sqlx::query_as(query)
+        .bind(start)
+        .bind(end)
+        .bind(from_currency)
+        .bind(to_currency)
+        .fetch(pool)
+}
+
+/// Built once by the caller, since the streams returned by `get_rates` borrow it.
+fn get_rates_query() -> Result<String> {
+    Ok(format!(
+        r#"
+            SELECT from_currency, to_currency, rate, date, nominal
+            FROM {exchange_rates}
+            WHERE date BETWEEN $1 AND $2
+                AND ($3::text IS NULL OR from_currency = $3)
+                AND ($4::text IS NULL OR to_currency = $4)
+            ORDER BY date, from_currency, to_currency
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+}
+
+/// Rates are compared per unit, so a `--keep-nominal-for` pair stored per nominal on only
+/// one of the dates isn't a change. Pairs with the same rate on both dates are left out.
+fn get_diffs(old_rates: Vec<Rate>, new_rates: Vec<Rate>) -> Result<Vec<RateDiff>> {
+    let mut pairs: BTreeMap<(String, String), (Option<Decimal>, Option<Decimal>)> = BTreeMap::new();
+
+    for rate in old_rates {
+        let per_unit_rate = rate.get_per_unit_rate()?;
+        pairs
+            .entry((rate.from_currency, rate.to_currency))
+            .or_default()
+            .0 = Some(per_unit_rate);
+    }
+
+    for rate in new_rates {
+        let per_unit_rate = rate.get_per_unit_rate()?;
+        pairs
+            .entry((rate.from_currency, rate.to_currency))
+            .or_default()
+            .1 = Some(per_unit_rate);
+    }
+
+    Ok(pairs
+        .into_iter()
+        .filter_map(|((from_currency, to_currency), (old_rate, new_rate))| {
+            let change = match (old_rate, new_rate) {
+                (Some(old_rate), Some(new_rate)) if old_rate == new_rate => return None,
+                (Some(_), Some(_)) => Change::Changed,
+                (None, Some(_)) => Change::Added,
+                (Some(_), None) => Change::Removed,
+                (None, None) => return None,
+            };
+
+            Some(RateDiff {
+                from_currency,
+                to_currency,
+                change,
+                old_rate,
+                new_rate,
+                delta: old_rate
+                    .zip(new_rate)
+                    .map(|(old_rate, new_rate)| new_rate - old_rate),
+            })
+        })
+        .collect())
+}
+
+async fn write_rates(
+    out: &mut impl Write,
+    mut rates: BoxStream<'_, sqlx::Result<Rate>>,
+    format: ExportFormat,
+) -> Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "from,to,rate,date,nominal")?;
+            while let Some(rate) = rates.try_next().await? {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    rate.from_currency, rate.to_currency, rate.rate, rate.date, rate.nominal
+                )?;
+            }
+        }
+        ExportFormat::Json => {
+            // Same output as serializing a Vec, one element at a time
+            let mut serializer = serde_json::Serializer::pretty(&mut *out);
+            let mut seq = serializer.serialize_seq(None)?;
+            while let Some(rate) = rates.try_next().await? {
+                seq.serialize_element(&rate)?;
+            }
+            seq.end()?;
+            writeln!(out)?;
+        }
+        ExportFormat::Influx => {
+            while let Some(rate) = rates.try_next().await? {
+                write_influx_line(out, &rate)?;
+            }
+        }
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => {
+            return Err(anyhow!("--format parquet needs --out FILE"));
+        }
+    }
+
+    Ok(())
+}
+
+/// The rate is a float field next to the integer `nominal` it is for, and the timestamp is
+/// the row's `effective_at`, midnight of the date in `CBR_TIMEZONE`, in nanoseconds, e.g.
+/// `exchange_rate,from=USD,to=RUB rate=73.5,nominal=1i 1704056400000000000`.
+fn write_influx_line(out: &mut impl Write, rate: &Rate) -> Result<()> {
+    let value = rate
+        .rate
+        .to_f64()
+        .ok_or(anyhow!("Can't convert rate {} to float", rate.rate))?;
+    let timestamp = get_effective_at(&rate.date)?
+        .timestamp_nanos_opt()
+        .ok_or(anyhow!("Date {} is out of the timestamp range", rate.date))?;
+
+    writeln!(
+        out,
+        "exchange_rate,from={},to={} rate={:?},nominal={}i {}",
+        rate.from_currency, rate.to_currency, value, rate.nominal, timestamp
+    )?;
+
+    Ok(())
+}
+
+fn write_diffs(out: &mut impl Write, diffs: &[RateDiff], format: ExportFormat) -> Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "from,to,change,old_rate,new_rate,delta")?;
+            for diff in diffs {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    diff.from_currency,
+                    diff.to_currency,
+                    diff.change.as_str(),
+                    get_optional(diff.old_rate),
+                    get_optional(diff.new_rate),
+                    get_optional(diff.delta)
+                )?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, diffs)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Influx => {
+            return Err(anyhow!("--diff can't be exported in the influx format"));
+        }
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => {
+            return Err(anyhow!("--diff can't be exported in the parquet format"));
+        }
+    }
+
+    Ok(())
+}
+
+fn get_optional(value: Option<Decimal>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{StreamExt, stream};
+
+    use super::*;
+    use crate::test_support::{Env, TestDb};
+
+    /// Counts one byte instead of keeping the output, so the export of a large table isn't
+    /// held in memory by the test either.
+    struct CountingSink {
+        byte: u8,
+        count: usize,
+    }
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.count += buf.iter().filter(|byte| **byte == self.byte).count();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn csv_carries_the_nominal() {
+        let rates = vec![
+            Ok(Rate {
+                from_currency: "IRR".to_string(),
+                to_currency: "RUB".to_string(),
+                rate: Decimal::new(217213, 4),
+                date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                nominal: 10000,
+            }),
+            Ok(Rate {
+                from_currency: "USD".to_string(),
+                to_currency: "RUB".to_string(),
+                rate: Decimal::new(908423, 4),
+                date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                nominal: 1,
+            }),
+        ];
+        let mut out = vec![];
+
+        write_rates(&mut out, stream::iter(rates).boxed(), ExportFormat::Csv)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "from,to,rate,date,nominal\nIRR,RUB,21.7213,2024-03-01,10000\nUSD,RUB,90.8423,2024-03-01,1\n"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn large_range_is_streamed_in_full() {
+        const ROWS: i64 = 200_000;
+        let db = TestDb::start().await;
+        let _env = Env::set(&[("TABLE_PREFIX", None)]).await;
+
+        // 100 пар на 2000 дат, по одной строке на пару и дату
+        sqlx::query(
+            r#"
+                INSERT INTO exchange_rates (from_currency, to_currency, rate, raw_rate, date, effective_at)
+                SELECT
+                    'C' || lpad((n % 100)::text, 2, '0'),
+                    'RUB',
+                    n / 1000.0,
+                    n / 1000.0,
+                    DATE '2000-01-01' + (n / 100)::int,
+                    (DATE '2000-01-01' + (n / 100)::int)::timestamp AT TIME ZONE 'Europe/Moscow'
+                FROM generate_series(0, $1 - 1) AS n
+            "#,
+        )
+        .bind(ROWS)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let query = get_rates_query().unwrap();
+        let (start, end) = (
+            NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2010, 1, 1).unwrap(),
+        );
+
+        // Строка CSV кончается переводом строки, объект JSON открывается одной скобкой
+        for (format, byte, expected) in [
+            (ExportFormat::Csv, b'\n', ROWS as usize + 1),
+            (ExportFormat::Json, b'{', ROWS as usize),
+        ] {
+            let mut out = CountingSink { byte, count: 0 };
+            let rates = get_rates(&db.pool, &query, start, end, None, None);
+
+            write_rates(&mut out, rates, format).await.unwrap();
+
+            assert_eq!(out.count, expected, "{:?}", format);
+        }
+
+        db.close().await;
+    }
+
+    fn rate(from_currency: &str, to_currency: &str, rate: &str, nominal: i32) -> Rate {
+        Rate {
+            from_currency: from_currency.to_string(),
+            to_currency: to_currency.to_string(),
+            rate: rate.parse().unwrap(),
+            date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            nominal,
+        }
+    }
+
+    #[test]
+    fn influx_line_carries_the_nominal() {
+        let mut out = vec![];
+
+        write_influx_line(&mut out, &rate("HUF", "RUB", "24.9786", 100)).unwrap();
+
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .starts_with("exchange_rate,from=HUF,to=RUB rate=24.9786,nominal=100i ")
+        );
+    }
+
+    #[test]
+    fn diff_compares_rates_per_unit() {
+        let old_rates = vec![
+            rate("HUF", "RUB", "0.249786", 1),
+            rate("RUB", "HUF", "4.0034", 1),
+        ];
+        let new_rates = vec![
+            rate("HUF", "RUB", "24.9786", 100),
+            rate("RUB", "HUF", "0.040034", 100),
+        ];
+
+        assert!(get_diffs(old_rates, new_rates).unwrap().is_empty());
+
+        let diffs = get_diffs(
+            vec![rate("HUF", "RUB", "0.249786", 1)],
+            vec![rate("HUF", "RUB", "25.0786", 100)],
+        )
+        .unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].delta, Some("0.001".parse().unwrap()));
+    }
+}
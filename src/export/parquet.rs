@@ -0,0 +1,117 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use anyhow::{Result, anyhow};
+use arrow_array::{
+    ArrayRef, Date32Array, Decimal128Array, DictionaryArray, Int32Array, RecordBatch,
+    types::{Date32Type, Int32Type},
+};
+use arrow_schema::{DataType, Field, Schema};
+use futures_util::{TryStreamExt, stream::BoxStream};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use rust_decimal::Decimal;
+
+use super::Rate;
+
+/// Rows converted and handed to the writer at a time.
+const BATCH_ROWS: usize = 8192;
+/// Rows per row group; the writer buffers one row group before flushing it to the file.
+const ROW_GROUP_ROWS: usize = 65536;
+/// rust_decimal carries at most 28 fractional digits; with precision 38 that leaves 10
+/// integer digits, far above any stored rate.
+const RATE_PRECISION: u8 = 38;
+const RATE_SCALE: i8 = 28;
+
+/// Writes the rates to `path` as Parquet with typed columns: the currencies as
+/// dictionary-encoded strings, `rate` as `decimal(38, 28)`, `date` as `date32` and the
+/// `nominal` the rate is for as `int32`.
+pub async fn write_rates(path: &Path, mut rates: BoxStream<'_, sqlx::Result<Rate>>) -> Result<()> {
+    let schema = Arc::new(get_schema());
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_row_count(Some(ROW_GROUP_ROWS))
+        .build();
+    let file =
+        File::create(path).map_err(|err| anyhow!("Can't create {}: {}", path.display(), err))?;
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+
+    while let Some(rate) = rates.try_next().await? {
+        batch.push(rate);
+
+        if batch.len() == BATCH_ROWS {
+            writer.write(&get_record_batch(&schema, &batch)?)?;
+            batch.clear();
+        }
+    }
+
+    if !batch.is_empty() {
+        writer.write(&get_record_batch(&schema, &batch)?)?;
+    }
+
+    writer.close()?;
+
+    Ok(())
+}
+
+fn get_schema() -> Schema {
+    let currency = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+
+    Schema::new(vec![
+        Field::new("from_currency", currency.clone(), false),
+        Field::new("to_currency", currency, false),
+        Field::new(
+            "rate",
+            DataType::Decimal128(RATE_PRECISION, RATE_SCALE),
+            false,
+        ),
+        Field::new("date", DataType::Date32, false),
+        Field::new("nominal", DataType::Int32, false),
+    ])
+}
+
+fn get_record_batch(schema: &Arc<Schema>, rates: &[Rate]) -> Result<RecordBatch> {
+    let from_currency: DictionaryArray<Int32Type> = rates
+        .iter()
+        .map(|rate| rate.from_currency.as_str())
+        .collect();
+    let to_currency: DictionaryArray<Int32Type> =
+        rates.iter().map(|rate| rate.to_currency.as_str()).collect();
+    let rate = Decimal128Array::from(
+        rates
+            .iter()
+            .map(|rate| get_scaled_rate(&rate.rate))
+            .collect::<Result<Vec<_>>>()?,
+    )
+    .with_precision_and_scale(RATE_PRECISION, RATE_SCALE)?;
+    let date = Date32Array::from(
+        rates
+            .iter()
+            .map(|rate| Date32Type::from_naive_date(rate.date))
+            .collect::<Vec<_>>(),
+    );
+    let nominal = Int32Array::from(rates.iter().map(|rate| rate.nominal).collect::<Vec<_>>());
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(from_currency),
+        Arc::new(to_currency),
+        Arc::new(rate),
+        Arc::new(date),
+        Arc::new(nominal),
+    ];
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// The rate as an integer number of 1e-28 units.
+fn get_scaled_rate(rate: &Decimal) -> Result<i128> {
+    10i128
+        .checked_pow(RATE_SCALE as u32 - rate.scale())
+        .and_then(|factor| rate.mantissa().checked_mul(factor))
+        .filter(|value| value.unsigned_abs() < 10u128.pow(RATE_PRECISION as u32))
+        .ok_or(anyhow!(
+            "Rate {} doesn't fit decimal({}, {})",
+            rate,
+            RATE_PRECISION,
+            RATE_SCALE
+        ))
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::val_curs::ValCurs;
+
+/// Bumped whenever `Entry` or `ValCurs` changes, so older files are fetched again.
+const FORMAT_VERSION: u8 = 1;
+
+#[derive(BorshDeserialize)]
+struct Entry {
+    url: String,
+    fetched_at_micros: i64,
+    val_curs: ValCurs,
+}
+
+/// `--cache-dir`: daily feeds already fetched and parsed, in borsh, so repeated runs over
+/// the same dates skip both CBR and the XML parser. An entry is `DIR/SOURCE/DATE.bin`,
+/// SOURCE being the feed URL without its query (CBR_BASE_URL and CBR_LANG), so another
+/// source misses, and removing a file or a SOURCE directory invalidates it.
+#[derive(Debug)]
+pub struct FeedCache {
+    dir: PathBuf,
+}
+
+impl FeedCache {
+    pub fn new(dir: &Path) -> Self {
+        FeedCache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// The feed of `url` with the time it was fetched, `None` on a miss. An unreadable or
+    /// outdated entry is a miss too, logged, and is overwritten by the next fetch.
+    pub fn get(&self, date: NaiveDate, url: &str) -> Option<(ValCurs, DateTime<Utc>)> {
+        let path = self.get_path(date, url);
+
+        if !path.exists() {
+            return None;
+        }
+
+        match read_entry(&path, url) {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::warn!("Ignoring feed cache entry {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Writes through a temporary file, so a run interrupted midway leaves no partial
+    /// entry. A failed write is only logged: the cache is never needed to store a date.
+    pub fn put(&self, date: NaiveDate, url: &str, val_curs: &ValCurs, fetched_at: &DateTime<Utc>) {
+        let path = self.get_path(date, url);
+
+        if let Err(err) = write_entry(&path, url, val_curs, fetched_at) {
+            log::warn!("Can't write feed cache entry {}: {}", path.display(), err);
+        }
+    }
+
+    fn get_path(&self, date: NaiveDate, url: &str) -> PathBuf {
+        self.dir
+            .join(get_source_dir(url))
+            .join(format!("{}.bin", date))
+    }
+}
+
+fn read_entry(path: &Path, url: &str) -> Result<Option<(ValCurs, DateTime<Utc>)>> {
+    let bytes = fs::read(path)?;
+    let Some((version, bytes)) = bytes.split_first() else {
+        return Err(anyhow!("empty file"));
+    };
+
+    if *version != FORMAT_VERSION {
+        log::debug!(
+            "Feed cache entry {} has format {}, not {}",
+            path.display(),
+            version,
+            FORMAT_VERSION
+        );
+        return Ok(None);
+    }
+
+    let entry = Entry::try_from_slice(bytes)?;
+
+    // Разные URL могли дать одно имя каталога
+    if entry.url != url {
+        return Ok(None);
+    }
+
+    let fetched_at = DateTime::from_timestamp_micros(entry.fetched_at_micros)
+        .ok_or(anyhow!("invalid fetch time {}", entry.fetched_at_micros))?;
+
+    Ok(Some((entry.val_curs, fetched_at)))
+}
+
+fn write_entry(
+    path: &Path,
+    url: &str,
+    val_curs: &ValCurs,
+    fetched_at: &DateTime<Utc>,
+) -> Result<()> {
+    // Поля в порядке Entry, чтобы не копировать фид
+    let mut bytes = vec![FORMAT_VERSION];
+    url.serialize(&mut bytes)?;
+    fetched_at.timestamp_micros().serialize(&mut bytes)?;
+    val_curs.serialize(&mut bytes)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = path.with_extension("bin.tmp");
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// `https://www.cbr.ru/scripts/XML_daily.asp?date_req=...` -> `www.cbr.ru_scripts_XML_daily.asp`
+fn get_source_dir(url: &str) -> String {
+    let source = url.split_once('?').map_or(url, |(source, _)| source);
+    let source = source
+        .split_once("://")
+        .map_or(source, |(_, source)| source);
+
+    source
+        .chars()
+        .map(|char| {
+            if char.is_ascii_alphanumeric() || char == '.' || char == '-' {
+                char
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::cli::GoldenTestArgs;
+use crate::config::{
+    get_connection_string, get_currencies, get_currency_aliases, get_currency_baskets,
+    get_currency_indices, get_rate_rounding, get_rate_scale, get_required_currencies,
+    get_table_name, get_table_prefix,
+};
+use crate::currency_cache::CurrencyCache;
+use crate::val_curs::ValCurs;
+use crate::{
+    WriteMode, check_val_curs, get_curs_map, get_keep_nominal_for, migrate, store_val_curs,
+    update_stored_currencies,
+};
+
+/// The committed expectation: the configuration the rows depend on and every stored row.
+#[derive(Debug, Serialize, Deserialize)]
+struct Golden {
+    config: GoldenConfig,
+    rows: Vec<GoldenRow>,
+}
+
+/// Every setting that changes which rows are stored or their values.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct GoldenConfig {
+    currencies: Vec<String>,
+    rate_scale: Option<u32>,
+    rate_rounding: String,
+    keep_nominal_for: Vec<String>,
+    /// Legacy code -> code
+    currency_aliases: BTreeMap<String, String>,
+    /// Code -> `CUR*WEIGHT+CUR*WEIGHT`
+    currency_baskets: BTreeMap<String, String>,
+    /// Code -> `YYYY-MM-DD:CUR*WEIGHT+CUR*WEIGHT`
+    currency_indices: BTreeMap<String, String>,
+}
+
+impl GoldenConfig {
+    fn get() -> Result<Self> {
+        let mut keep_nominal_for = get_keep_nominal_for().to_vec();
+        keep_nominal_for.sort();
+
+        Ok(GoldenConfig {
+            currencies: get_currencies()?,
+            rate_scale: get_rate_scale()?,
+            rate_rounding: get_rate_rounding()?.to_string(),
+            keep_nominal_for,
+            currency_aliases: get_currency_aliases()?.into_iter().collect(),
+            currency_baskets: get_currency_baskets()?
+                .into_iter()
+                .map(|(code, components)| (code, describe_components(&components)))
+                .collect(),
+            currency_indices: get_currency_indices()?
+                .into_iter()
+                .map(|(code, index)| {
+                    let components = describe_components(&index.components);
+                    (code, format!("{}:{}", index.base_date, components))
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The settings as they are given, e.g. `CURRENCIES=USD,EUR RATE_SCALE=(unset) ...`.
+impl fmt::Display for GoldenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |items: &BTreeMap<String, String>| {
+            items
+                .iter()
+                .map(|(code, value)| format!("{}:{}", code, value))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        write!(
+            f,
+            "CURRENCIES={} RATE_SCALE={} RATE_ROUNDING={} KEEP_NOMINAL_FOR={} CURRENCY_ALIASES={} CURRENCY_BASKETS={} CURRENCY_INDICES={}",
+            self.currencies.join(","),
+            self.rate_scale
+                .map_or("(unset)".to_string(), |scale| scale.to_string()),
+            self.rate_rounding,
+            self.keep_nominal_for.join(","),
+            join(&self.currency_aliases),
+            join(&self.currency_baskets),
+            join(&self.currency_indices)
+        )
+    }
+}
+
+fn describe_components(components: &[(String, Decimal)]) -> String {
+    components
+        .iter()
+        .map(|(currency, weight)| format!("{}*{}", currency, weight))
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// A stored row without the columns that differ between runs (`id` and the timestamps of
+/// the write and the fetch).
+#[derive(Debug, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+struct GoldenRow {
+    date: NaiveDate,
+    from_currency: String,
+    to_currency: String,
+    rate: Decimal,
+    raw_rate: Decimal,
+    nominal: i32,
+    quote_convention: String,
+    source: String,
+    effective_at: DateTime<Utc>,
+    feed_date: Option<NaiveDate>,
+}
+
+/// Ingests the fixture feeds `DIR/YYYY-MM-DD.xml` through the same parse, derive and store
+/// code as `ingest`, into a schema of its own that is dropped afterwards, and compares the
+/// rows with the golden file. `--update` rewrites the file instead, so a change to the
+/// stored rates shows up as a diff of it in review.
+pub async fn golden_test(args: GoldenTestArgs) -> Result<()> {
+    // Зеркало и Kafka получили бы строки фикстур
+    for name in ["DATABASE_URL_SECONDARY", "KAFKA_BROKERS"] {
+        if env::var(name).is_ok() {
+            return Err(anyhow!(
+                "golden-test can't run with {} set: it would receive the fixture rates",
+                name
+            ));
+        }
+    }
+
+    if !get_table_prefix()?.is_empty() {
+        return Err(anyhow!(
+            "golden-test creates unprefixed tables and can't be used with TABLE_PREFIX"
+        ));
+    }
+
+    let config = GoldenConfig::get()?;
+    let golden = if args.update {
+        None
+    } else {
+        Some(read_golden(&args.golden, &config)?)
+    };
+    let feeds = get_feeds(&args.fixtures)?;
+
+    let schema = format!("valut_golden_{}", Uuid::new_v4().simple());
+    let admin_pool = PgPool::connect(&get_connection_string()?).await?;
+    admin_pool
+        .execute(format!("CREATE SCHEMA {}", schema).as_str())
+        .await?;
+
+    let result = ingest_feeds(&schema, &feeds).await;
+
+    if let Err(err) = admin_pool
+        .execute(format!("DROP SCHEMA {} CASCADE", schema).as_str())
+        .await
+    {
+        log::error!("Can't drop schema {}: {}", schema, err);
+    }
+
+    let rows = result?;
+
+    let Some(golden) = golden else {
+        let golden = Golden { config, rows };
+        fs::write(&args.golden, serde_json::to_string_pretty(&golden)? + "\n")?;
+        println!(
+            "Wrote {} rows of {} dates to {}",
+            golden.rows.len(),
+            feeds.len(),
+            args.golden.display()
+        );
+        return Ok(());
+    };
+
+    let differences = compare(&golden.rows, &rows);
+
+    if differences.is_empty() {
+        println!(
+            "OK: {} rows of {} dates match {}",
+            rows.len(),
+            feeds.len(),
+            args.golden.display()
+        );
+        return Ok(());
+    }
+
+    for difference in &differences {
+        println!("{}", difference);
+    }
+
+    Err(anyhow!(
+        "{} differences from {}; if the change is intended, rerun with --update and commit the file",
+        differences.len(),
+        args.golden.display()
+    ))
+}
+
+fn read_golden(path: &Path, config: &GoldenConfig) -> Result<Golden> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Can't read golden file {}: {}", path.display(), err))?;
+    let golden: Golden = serde_json::from_str(&text)
+        .map_err(|err| anyhow!("Invalid golden file {}: {}", path.display(), err))?;
+
+    // Другая конфигурация дала бы другие строки, а не регрессию
+    if golden.config != *config {
+        return Err(anyhow!(
+            "{} was written with {}, run with the same instead of {}",
+            path.display(),
+            golden.config,
+            config
+        ));
+    }
+
+    Ok(golden)
+}
+
+/// Fixture files sorted by date, named by the date they are stored for.
+fn get_feeds(dir: &Path) -> Result<Vec<(NaiveDate, PathBuf)>> {
+    let mut feeds = vec![];
+
+    for entry in fs::read_dir(dir)
+        .map_err(|err| anyhow!("Can't read fixtures {}: {}", dir.display(), err))?
+    {
+        let path = entry?.path();
+
+        if path.extension().is_none_or(|extension| extension != "xml") {
+            continue;
+        }
+
+        let date = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+            .ok_or(anyhow!(
+                "Fixture {} must be named YYYY-MM-DD.xml",
+                path.display()
+            ))?;
+        feeds.push((date, path));
+    }
+
+    if feeds.is_empty() {
+        return Err(anyhow!("No YYYY-MM-DD.xml fixtures in {}", dir.display()));
+    }
+
+    feeds.sort();
+
+    Ok(feeds)
+}
+
+async fn ingest_feeds(schema: &str, feeds: &[(NaiveDate, PathBuf)]) -> Result<Vec<GoldenRow>> {
+    let search_path = format!("SET search_path TO {}", schema);
+    let pool = PgPoolOptions::new()
+        .after_connect(move |connection, _| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                connection.execute(search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&get_connection_string()?)
+        .await?;
+
+    migrate::migrate(&pool).await?;
+
+    let currencies = get_currencies()?;
+    let required_currencies = get_required_currencies()?;
+    let aliases = get_currency_aliases()?;
+    let mut currency_cache = CurrencyCache::load(&pool).await?;
+
+    for (date, path) in feeds {
+        let text = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Can't read {}: {}", path.display(), err))?;
+        let val_curs: ValCurs = quick_xml::de::from_str(&text)
+            .map_err(|err| anyhow!("Can't parse {}: {}", path.display(), err))?;
+        check_val_curs(*date, &val_curs)?;
+
+        let exchange_rates = get_curs_map(&val_curs, &aliases, &currencies).await?;
+
+        update_stored_currencies(
+            &val_curs,
+            &aliases,
+            &currencies,
+            None,
+            &mut currency_cache,
+            &pool,
+            WriteMode::Execute,
+        )
+        .await?;
+
+        store_val_curs(
+            *date,
+            &val_curs,
+            &exchange_rates,
+            &Utc::now(),
+            &pool,
+            &currencies,
+            &required_currencies,
+            &currency_cache,
+            WriteMode::Execute,
+        )
+        .await?;
+    }
+
+    let rows = sqlx::query_as(&format!(
+        r#"
+            SELECT date, from_currency, to_currency, rate, raw_rate, nominal, quote_convention,
+                source, effective_at, feed_date
+            FROM {exchange_rates}
+            ORDER BY date, from_currency, to_currency
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .fetch_all(&pool)
+    .await?;
+
+    pool.close().await;
+
+    Ok(rows)
+}
+
+/// One line per row that is missing, unexpected or different, in date order.
+fn compare(expected: &[GoldenRow], actual: &[GoldenRow]) -> Vec<String> {
+    let key = |row: &GoldenRow| (row.date, row.from_currency.clone(), row.to_currency.clone());
+    let mut rows: BTreeMap<_, (Option<&GoldenRow>, Option<&GoldenRow>)> = BTreeMap::new();
+
+    for row in expected {
+        rows.entry(key(row)).or_default().0 = Some(row);
+    }
+
+    for row in actual {
+        rows.entry(key(row)).or_default().1 = Some(row);
+    }
+
+    rows.into_iter()
+        .filter_map(|((date, from_currency, to_currency), pair)| {
+            let name = format!("{} {} -> {}", date, from_currency, to_currency);
+
+            match pair {
+                (Some(expected), Some(actual)) if expected == actual => None,
+                (Some(expected), Some(actual)) => Some(format!(
+                    "changed {}: expected {}, got {}",
+                    name,
+                    serde_json::to_string(expected).unwrap_or_default(),
+                    serde_json::to_string(actual).unwrap_or_default()
+                )),
+                (Some(_), None) => Some(format!("missing {}", name)),
+                (None, Some(actual)) => Some(format!(
+                    "unexpected {}: {}",
+                    name,
+                    serde_json::to_string(actual).unwrap_or_default()
+                )),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Env;
+
+    #[test]
+    fn config_records_baskets_indices_and_aliases() {
+        let _env = Env::set_blocking(&[
+            ("CURRENCIES", Some("USD,EUR")),
+            ("RATE_SCALE", None),
+            ("RATE_ROUNDING", None),
+            ("CURRENCY_ALIASES", Some("DEM:EUR")),
+            ("CURRENCY_BASKETS", Some("BSK:USD*0.6+EUR*0.4")),
+            ("CURRENCY_INDICES", Some("NER:2024-01-02:USD*0.5+EUR*0.5")),
+        ]);
+
+        assert_eq!(
+            GoldenConfig::get().unwrap().to_string(),
+            "CURRENCIES=USD,EUR RATE_SCALE=(unset) RATE_ROUNDING=half_even KEEP_NOMINAL_FOR= CURRENCY_ALIASES=DEM:EUR CURRENCY_BASKETS=BSK:USD*0.6+EUR*0.4 CURRENCY_INDICES=NER:2024-01-02:USD*0.5+EUR*0.5"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use hyper_util::client::legacy::connect::HttpInfo;
+use reqwest::{Client, NoProxy, Proxy, Response, StatusCode, Url, header::CONTENT_TYPE};
+use serde::Serialize;
+
+use crate::config::{get_http_retries, get_http_user_agent};
+use crate::{RETRYDELAY_SEC, get_retry_delay, next_delay};
+
+#[derive(Debug, Default)]
+pub struct HttpOptions {
+    pub proxy: Option<Url>,
+    pub retry_all: bool,
+    /// Retries all requests of a run may make together (`--max-total-retries`)
+    pub max_total_retries: Option<u32>,
+    /// Speak HTTP/1.1 only, without negotiating HTTP/2 (`--http1-only`)
+    pub http1_only: bool,
+}
+
+static OPTIONS: OnceLock<HttpOptions> = OnceLock::new();
+static RETRIES_USED: AtomicU32 = AtomicU32::new(0);
+static CLIENT: OnceLock<Client> = OnceLock::new();
+/// How long the client's pool keeps an idle connection open. A connection idle for longer
+/// is closed, and its local port may later go to a new one.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// When a response last came over each connection seen, by local and remote address; a
+/// response over one seen within `POOL_IDLE_TIMEOUT` came over a reused connection.
+static CONNECTIONS: Mutex<Option<HashMap<(SocketAddr, SocketAddr), Instant>>> = Mutex::new(None);
+
+pub fn init(options: HttpOptions) {
+    OPTIONS.get_or_init(|| options);
+}
+
+fn options() -> &'static HttpOptions {
+    OPTIONS.get_or_init(HttpOptions::default)
+}
+
+/// Gives a new ingest run the whole `--max-total-retries` budget.
+pub fn reset_retry_budget() {
+    RETRIES_USED.store(0, Ordering::Relaxed);
+}
+
+/// Takes one retry from the run's budget; false once it is used up.
+fn take_retry(max_total_retries: u32) -> bool {
+    RETRIES_USED
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            (used < max_total_retries).then_some(used + 1)
+        })
+        .is_ok()
+}
+
+/// CBR answered 404: the date is out of the published range, so only that date is skipped.
+#[derive(Debug)]
+pub struct NotFound {
+    url: String,
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CBR has no data at {}", self.url)
+    }
+}
+
+impl Error for NotFound {}
+
+/// 5xx, 429, transport errors and HTML pages served with 200 during CBR maintenance are
+/// retried up to `HTTP_RETRIES` times, and no more than `--max-total-retries` times per run
+/// across all requests; 404 is
+/// `NotFound`; any other status fails the run. `--retry-all-http` retries every status.
+/// `file://` URLs are read from disk once, ignoring the query.
+pub async fn load_xml(url: &str) -> Result<String> {
+    if let Some(path) = get_file_path(url)? {
+        return load_file(url, &path).await;
+    }
+
+    let client = get_http_client()?;
+    let retries = get_http_retries()?;
+    let mut attempt = 0;
+    let mut delay_sec = RETRYDELAY_SEC;
+
+    loop {
+        let result = client.get(url).send().await;
+
+        if let Ok(response) = &result {
+            log_connection(url, response);
+        }
+
+        let err = match result {
+            Ok(response) if response.status().is_success() => {
+                let is_html_type = response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|value| value.to_lowercase().contains("html"));
+                // CBR отдаёт XML в windows-1251; если charset не указан в заголовке, используем его
+                let text = response.text_with_charset("windows-1251").await?;
+
+                if !is_html_type && !is_html(&text) {
+                    return Ok(text);
+                }
+
+                anyhow!("CBR returned HTML instead of XML, likely maintenance")
+            }
+
+            Ok(response) => {
+                let status = response.status();
+
+                if !options().retry_all && !is_retryable(status) {
+                    if status == StatusCode::NOT_FOUND {
+                        return Err(NotFound {
+                            url: url.to_string(),
+                        }
+                        .into());
+                    }
+
+                    return Err(anyhow!("Can't download the file: {}", status));
+                }
+
+                anyhow!("Can't download the file: {}", status)
+            }
+
+            Err(err) => anyhow!("Can't download the file: {}", err),
+        };
+
+        if attempt >= retries {
+            return Err(err);
+        }
+
+        if let Some(max_total_retries) = options().max_total_retries
+            && !take_retry(max_total_retries)
+        {
+            log::error!(
+                "Retry budget of {} retries for the run is exhausted, not retrying {}",
+                max_total_retries,
+                url
+            );
+            return Err(err.context(format!(
+                "Retry budget of {} retries for the run is exhausted",
+                max_total_retries
+            )));
+        }
+
+        attempt += 1;
+        let delay = get_retry_delay(delay_sec);
+        log::warn!(
+            "{} (retry {} of {} in {:.1?})",
+            err,
+            attempt,
+            retries,
+            delay
+        );
+        tokio::time::sleep(delay).await;
+        delay_sec = next_delay(delay_sec);
+    }
+}
+
+/// Sent once, without retries.
+pub async fn post_json(url: &Url, body: &impl Serialize) -> Result<()> {
+    let response = get_http_client()?
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(body)?)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+
+    Ok(())
+}
+
+fn get_file_path(url: &str) -> Result<Option<PathBuf>> {
+    let parsed = Url::parse(url).map_err(|err| anyhow!("Invalid URL {}: {}", url, err))?;
+
+    if parsed.scheme() != "file" {
+        return Ok(None);
+    }
+
+    let path = parsed
+        .to_file_path()
+        .map_err(|_| anyhow!("Invalid file URL {}", url))?;
+
+    Ok(Some(path))
+}
+
+/// A missing file is `NotFound`, like a 404. Unlike the HTTP path, the file must be UTF-8.
+async fn load_file(url: &str, path: &Path) -> Result<String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(text) => Ok(text),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(NotFound {
+            url: url.to_string(),
+        }
+        .into()),
+        Err(err) => Err(anyhow!("Can't read {}: {}", path.display(), err)),
+    }
+}
+
+fn is_html(text: &str) -> bool {
+    let start: String = text.trim_start().chars().take(9).collect();
+    let start = start.to_lowercase();
+
+    start.starts_with("<!doctype") || start.starts_with("<html")
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Logs at debug level the HTTP version of a response and whether its connection was
+/// opened for it or reused, kept alive from an earlier request (or, over HTTP/2, shared).
+fn log_connection(url: &str, response: &Response) {
+    let Some(info) = response.extensions().get::<HttpInfo>() else {
+        log::debug!("{} over {:?}", url, response.version());
+        return;
+    };
+
+    let mut connections = CONNECTIONS.lock().unwrap_or_else(|err| err.into_inner());
+    let is_new = see_connection(
+        connections.get_or_insert_with(HashMap::new),
+        (info.local_addr(), info.remote_addr()),
+        Instant::now(),
+    );
+
+    log::debug!(
+        "{} over {:?}, {} connection {} -> {}",
+        url,
+        response.version(),
+        if is_new { "new" } else { "reused" },
+        info.local_addr(),
+        info.remote_addr()
+    );
+}
+
+/// Records a response over `connection` at `now`; true if the connection is new. Entries
+/// idle for longer than `POOL_IDLE_TIMEOUT` are dropped first: the pool has closed those
+/// connections, so the map doesn't grow over a long-running daemon, and a new connection
+/// that got the same ephemeral port isn't taken for the old one.
+fn see_connection(
+    connections: &mut HashMap<(SocketAddr, SocketAddr), Instant>,
+    connection: (SocketAddr, SocketAddr),
+    now: Instant,
+) -> bool {
+    connections.retain(|_, last_seen| now.duration_since(*last_seen) <= POOL_IDLE_TIMEOUT);
+
+    connections.insert(connection, now).is_none()
+}
+
+/// One client for the whole process, so its pool keeps connections alive between
+/// requests. reqwest negotiates HTTP/2 over TLS through ALPN when the server offers it and
+/// falls back to HTTP/1.1; `--http1-only` skips the negotiation. Without `--proxy` reqwest
+/// takes the proxy from HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY itself.
+fn get_http_client() -> Result<Client> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client.clone());
+    }
+
+    let mut builder = Client::builder()
+        .gzip(true)
+        .deflate(true)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .user_agent(get_http_user_agent()?);
+
+    if let Some(proxy) = &options().proxy {
+        builder = builder.proxy(Proxy::all(proxy.clone())?.no_proxy(NoProxy::from_env()));
+    }
+
+    if options().http1_only {
+        builder = builder.http1_only();
+    }
+
+    // Client — это Arc, клон использует тот же пул соединений
+    let client = builder.build()?;
+
+    Ok(CLIENT.get_or_init(|| client).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Env, Feed, FeedServer};
+
+    const MAINTENANCE_PAGE: &str =
+        "<!DOCTYPE html>\n<html><body>Technical works are being carried out</body></html>";
+
+    #[test]
+    fn html_is_told_from_xml() {
+        assert!(is_html(MAINTENANCE_PAGE));
+        assert!(is_html("  \n<HTML><body></body></HTML>"));
+        assert!(is_html("<!doctype html>"));
+        assert!(!is_html(
+            r#"<?xml version="1.0" encoding="windows-1251"?><ValCurs/>"#
+        ));
+        assert!(!is_html("<ValCurs Date=\"01.03.2024\"/>"));
+        assert!(!is_html(""));
+    }
+
+    #[test]
+    fn connection_idle_past_the_pool_timeout_is_new_again() {
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let cbr: SocketAddr = "194.58.90.180:443".parse().unwrap();
+        let other: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let start = Instant::now();
+        let mut connections = HashMap::new();
+
+        assert!(see_connection(&mut connections, (local, cbr), start));
+        assert!(!see_connection(
+            &mut connections,
+            (local, cbr),
+            start + Duration::from_secs(60)
+        ));
+        // Тот же локальный порт к другому серверу — другое соединение
+        assert!(see_connection(
+            &mut connections,
+            (local, other),
+            start + Duration::from_secs(61)
+        ));
+
+        // Порт освободился и достался новому соединению к тому же серверу
+        let later = start + Duration::from_secs(61) + POOL_IDLE_TIMEOUT + Duration::from_secs(1);
+        assert!(see_connection(&mut connections, (local, cbr), later));
+        assert_eq!(connections.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn maintenance_page_with_status_200_is_retried() {
+        for content_type in ["text/html; charset=utf-8", "application/xml"] {
+            let feeds = FeedServer::start(vec![Feed {
+                date: "2024-03-01".parse().unwrap(),
+                content_type,
+                body: MAINTENANCE_PAGE.to_string(),
+            }])
+            .await;
+            let _env = Env::set(&[("HTTP_RETRIES", Some("1"))]).await;
+
+            let err = load_xml(&format!("{}XML_daily.asp?date_req=01/03/2024", feeds.url))
+                .await
+                .unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                "CBR returned HTML instead of XML, likely maintenance"
+            );
+            assert_eq!(feeds.requests(), 2, "{}", content_type);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use sqlx::{Connection, Pool, Postgres};
+
+use crate::cli::ImportArgs;
+use crate::exchange_rate::QuoteConvention;
+use crate::{RunSummary, WriteMode, WriteSummary, get_db_pool, lock_ingest, set_exchange_rate};
+
+/// `nominal` was added later; files without it are per unit.
+const HEADERS: [&str; 2] = ["from,to,rate,date", "from,to,rate,date,nominal"];
+
+struct Row {
+    line: usize,
+    from_currency: String,
+    to_currency: String,
+    rate: Decimal,
+    date: NaiveDate,
+    nominal: u32,
+}
+
+/// Loads `from,to,rate,date[,nominal]` rows, the format `export --format csv` writes.
+pub async fn import(args: ImportArgs) -> Result<()> {
+    let content = fs::read_to_string(&args.file)
+        .map_err(|err| anyhow!("Can't read {}: {}", args.file.display(), err))?;
+
+    let mut rows = vec![];
+    let mut errors = vec![];
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || (index == 0 && HEADERS.contains(&line)) {
+            continue;
+        }
+
+        match parse_row(index + 1, line) {
+            Ok(row) => rows.push(row),
+            Err(err) => errors.push(format!("line {}: {}", index + 1, err)),
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+
+        return Err(anyhow!(
+            "{} of {} rows in {} are invalid",
+            errors.len(),
+            errors.len() + rows.len(),
+            args.file.display()
+        ));
+    }
+
+    if args.dry_run {
+        println!("{} rows are valid", rows.len());
+        return Ok(());
+    }
+
+    let checkpoint_path = get_checkpoint_path(&args.file);
+    let last_line = if args.resume {
+        read_checkpoint(&checkpoint_path)?
+    } else {
+        0
+    };
+
+    if last_line > 0 {
+        println!("Resuming after line {}", last_line);
+    }
+
+    let pool = get_db_pool().await?;
+    let lock = lock_ingest(&pool, args.wait_for_lock).await?;
+    let result = store_rows(&rows, last_line, &checkpoint_path, &pool).await;
+
+    lock.close().await?;
+
+    let summary = result?;
+
+    println!(
+        "Rates imported: {} inserted, {} updated, {} unchanged",
+        summary.inserted, summary.updated, summary.unchanged
+    );
+
+    RunSummary::new(summary).log();
+
+    Ok(())
+}
+
+/// Stores the rows after `last_line`, checkpointing each; the checkpoint is removed once
+/// all are stored.
+async fn store_rows(
+    rows: &[Row],
+    last_line: usize,
+    checkpoint_path: &Path,
+    pool: &Pool<Postgres>,
+) -> Result<WriteSummary> {
+    let mut summary = WriteSummary::default();
+
+    for row in rows.iter().filter(|row| row.line > last_line) {
+        let currency = if row.from_currency == "RUB" {
+            &row.to_currency
+        } else {
+            &row.from_currency
+        };
+
+        summary.add(
+            currency,
+            set_exchange_rate(
+                &row.date,
+                &row.from_currency,
+                &row.to_currency,
+                &row.rate,
+                row.nominal,
+                QuoteConvention::of(&row.from_currency, &row.to_currency),
+                None,
+                None,
+                pool,
+                WriteMode::Execute,
+            )
+            .await?,
+        );
+
+        write_checkpoint(checkpoint_path, row.line)?;
+    }
+
+    if checkpoint_path.exists() {
+        fs::remove_file(checkpoint_path)
+            .map_err(|err| anyhow!("Can't remove {}: {}", checkpoint_path.display(), err))?;
+    }
+
+    Ok(summary)
+}
+
+/// `rates.csv` is checkpointed to `rates.csv.checkpoint`.
+fn get_checkpoint_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Line number of the last stored row; `0` without a checkpoint.
+fn read_checkpoint(path: &Path) -> Result<usize> {
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Can't read {}: {}", path.display(), err))?;
+
+    content.trim().parse().map_err(|err| {
+        anyhow!(
+            "Invalid checkpoint {} in {}: {}",
+            content,
+            path.display(),
+            err
+        )
+    })
+}
+
+/// Written to a temporary file and renamed over the checkpoint, so an interrupted write
+/// leaves the previous checkpoint intact.
+fn write_checkpoint(path: &Path, line: usize) -> Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+
+    fs::write(&temporary_path, line.to_string())
+        .and_then(|_| fs::rename(&temporary_path, path))
+        .map_err(|err| anyhow!("Can't write {}: {}", path.display(), err))
+}
+
+fn parse_row(line_number: usize, line: &str) -> Result<Row> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+    let (from_currency, to_currency, rate, date, nominal) = match fields[..] {
+        [from_currency, to_currency, rate, date] => (from_currency, to_currency, rate, date, None),
+        [from_currency, to_currency, rate, date, nominal] => {
+            (from_currency, to_currency, rate, date, Some(nominal))
+        }
+        _ => return Err(anyhow!("expected 4 or 5 fields, found {}", fields.len())),
+    };
+
+    let from_currency = parse_currency(from_currency)?;
+    let to_currency = parse_currency(to_currency)?;
+
+    if from_currency == to_currency {
+        return Err(anyhow!("from and to are both {}", from_currency));
+    }
+
+    let rate = Decimal::from_str(rate).map_err(|err| anyhow!("invalid rate {}: {}", rate, err))?;
+
+    if rate <= Decimal::ZERO {
+        return Err(anyhow!("rate {} is not positive", rate));
+    }
+
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|err| anyhow!("invalid date {}: {}", date, err))?;
+
+    let nominal = match nominal {
+        Some(nominal) => nominal
+            .parse::<u32>()
+            .ok()
+            .filter(|nominal| *nominal > 0)
+            .ok_or(anyhow!(
+                "invalid nominal {}, expected a positive integer",
+                nominal
+            ))?,
+        None => 1,
+    };
+
+    // Только пары с RUB хранятся за номинал (--keep-nominal-for), кросс-курсы всегда за единицу
+    if nominal != 1 && from_currency != "RUB" && to_currency != "RUB" {
+        return Err(anyhow!(
+            "nominal {} for {} -> {}, only a pair with RUB can have a nominal",
+            nominal,
+            from_currency,
+            to_currency
+        ));
+    }
+
+    Ok(Row {
+        line: line_number,
+        from_currency,
+        to_currency,
+        rate,
+        date,
+        nominal,
+    })
+}
+
+fn parse_currency(code: &str) -> Result<String> {
+    let code = code.to_uppercase();
+
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(anyhow!(
+            "invalid currency code {}, expected 3 letters",
+            code
+        ));
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_without_nominal_is_per_unit() {
+        let row = parse_row(2, "usd,RUB,91.3,2024-03-01").unwrap();
+
+        assert_eq!(row.line, 2);
+        assert_eq!(
+            (row.from_currency.as_str(), row.to_currency.as_str()),
+            ("USD", "RUB")
+        );
+        assert_eq!(row.rate, Decimal::from_str("91.3").unwrap());
+        assert_eq!(row.date, NaiveDate::from_ymd_opt(2024, 3, 1).unwrap());
+        assert_eq!(row.nominal, 1);
+    }
+
+    #[test]
+    fn row_keeps_the_nominal_of_a_rub_pair() {
+        let row = parse_row(2, "IRR,RUB,21.7213,2024-03-01,10000").unwrap();
+        assert_eq!(row.nominal, 10000);
+
+        let row = parse_row(3, "RUB,IRR,0.046,2024-03-01,10000").unwrap();
+        assert_eq!(row.nominal, 10000);
+    }
+
+    #[test]
+    fn invalid_nominal_is_rejected() {
+        for line in [
+            "IRR,RUB,21.7213,2024-03-01,0",
+            "IRR,RUB,21.7213,2024-03-01,-1",
+            "IRR,RUB,21.7213,2024-03-01,ten",
+            "USD,EUR,0.92,2024-03-01,100",
+            "IRR,RUB,21.7213,2024-03-01,10000,1",
+        ] {
+            assert!(parse_row(1, line).is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    env,
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDate;
+use rdkafka::{
+    ClientConfig,
+    producer::{FutureProducer, FutureRecord, Producer},
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::WriteOutcome;
+
+struct Sink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RateEvent<'a> {
+    from: &'a str,
+    to: &'a str,
+    rate: String,
+    date: &'a NaiveDate,
+    outcome: WriteOutcome,
+}
+
+static SINK: OnceLock<Option<Sink>> = OnceLock::new();
+
+/// Longer than `message.timeout.ms`, so every queued event is delivered or failed by then.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events enqueued whose delivery result hasn't been logged yet.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends the event in the background; delivery failures are only logged.
+pub fn publish(
+    from_currency: &str,
+    to_currency: &str,
+    rate: &Decimal,
+    date: &NaiveDate,
+    outcome: WriteOutcome,
+) {
+    let Some(sink) = SINK.get_or_init(get_sink) else {
+        return;
+    };
+
+    let event = RateEvent {
+        from: from_currency,
+        to: to_currency,
+        rate: rate.to_string(),
+        date,
+        outcome,
+    };
+    let payload = match serde_json::to_string(&event) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("Can't serialize Kafka event {:?}: {}", event, err);
+            return;
+        }
+    };
+    let key = format!("{}:{}:{}", from_currency, to_currency, date);
+    let record = FutureRecord::to(&sink.topic).key(&key).payload(&payload);
+
+    match sink.producer.send_result(record) {
+        Ok(delivery) => {
+            PENDING.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                match delivery.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err((err, _))) => log::error!("Can't publish Kafka event {}: {}", key, err),
+                    Err(_) => log::error!("Kafka event {} was dropped before delivery", key),
+                }
+                PENDING.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+
+        Err((err, _)) => log::error!("Can't enqueue Kafka event {}: {}", key, err),
+    }
+}
+
+/// Waits up to `FLUSH_TIMEOUT` for the events still queued, so that exiting right after a
+/// run doesn't drop them, and logs how many were never delivered.
+pub async fn flush() {
+    let Some(Some(sink)) = SINK.get() else {
+        return;
+    };
+
+    // flush блокирует поток до опустошения очереди, поэтому не в потоке рантайма
+    let producer = sink.producer.clone();
+    match tokio::task::spawn_blocking(move || producer.flush(FLUSH_TIMEOUT)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::error!("Can't flush Kafka producer: {}", err),
+        Err(err) => log::error!("Can't flush Kafka producer: {}", err),
+    }
+
+    // Даём фоновым задачам залогировать результат доставки каждого события
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while PENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let undelivered = sink.producer.in_flight_count();
+    if undelivered > 0 {
+        log::error!(
+            "{} Kafka event(s) not delivered within {:?} at shutdown, they are lost",
+            undelivered,
+            FLUSH_TIMEOUT
+        );
+    }
+}
+
+fn get_sink() -> Option<Sink> {
+    let brokers = env::var("KAFKA_BROKERS").ok()?;
+    let topic = env::var("KAFKA_TOPIC").ok()?;
+
+    match ClientConfig::new()
+        .set("bootstrap.servers", &brokers)
+        .set("message.timeout.ms", "5000")
+        .create()
+    {
+        Ok(producer) => {
+            log::info!("Publishing rate changes to Kafka topic {}", topic);
+            Some(Sink { producer, topic })
+        }
+
+        Err(err) => {
+            log::error!("Can't create Kafka producer for {}: {}", brokers, err);
+            None
+        }
+    }
+}
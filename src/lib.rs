@@ -0,0 +1,3614 @@
+//! Loads the official Central Bank of Russia exchange rates into Postgres. The `valut`
+//! binary is `run_cli`; a service embedding the crate can refresh one pair with
+//! `refresh_pair`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use bulk::BulkFeed;
+use chrono::{DateTime, Days, NaiveDate, Timelike, Utc};
+use clap::{Parser, ValueEnum};
+use feed_cache::FeedCache;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{Connection, PgConnection, PgPool, Pool, Postgres};
+use tokio::signal::unix::{SignalKind, signal};
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+use val_curs::{FeedError, NominalRate, ParsedRate, PartialFeed, ValCurs, Valute};
+
+use crate::cli::{Cli, Command, ConfigCheckArgs, IngestArgs, RecomputeCrossArgs};
+use crate::config::{
+    CbrLang, get_cbr_base_url, get_cbr_lang, get_cbr_timezone, get_connection_string,
+    get_currencies, get_currency_aliases, get_currency_available_from,
+    get_currency_available_until, get_currency_baskets, get_currency_indices,
+    get_db_acquire_retries, get_db_connect_retries, get_lookback_days, get_min_fetched_dates,
+    get_rate_rounding, get_rate_scale, get_required_currencies, get_retry_jitter_seed,
+    get_table_name,
+};
+use crate::currency_cache::{CurrencyCache, CurrencyNames, FetchNames};
+use crate::exchange_rate::{ExchangeRate, QuoteConvention};
+
+mod audit;
+mod available_from;
+mod backfill;
+mod bulk;
+mod cli;
+mod config;
+mod coverage;
+mod currency_cache;
+pub mod ecb;
+mod exchange_rate;
+mod explain;
+mod export;
+mod feed_cache;
+mod golden;
+mod http;
+mod import;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod logging;
+mod metrics;
+mod migrate;
+mod progress;
+mod run_log;
+mod sample;
+mod scale_check;
+mod schema_check;
+mod secondary;
+#[cfg(feature = "server")]
+mod server;
+mod sql_script;
+mod stats;
+#[cfg(test)]
+mod test_support;
+mod trail;
+mod val_curs;
+mod verify;
+mod webhook;
+mod wide;
+
+const DELAY_SEC: u64 = 60 * 20;
+const RETRYDELAY_SEC: u64 = 5;
+const INGEST_LOCK_KEY: i64 = 0x76616c7574; // "valut"
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WriteMode {
+    Execute,
+    OutputSql,
+    DryRun,
+}
+
+/// Order ingest fetches and stores the dates in.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum DateOrder {
+    /// Oldest first, e.g. to build history in order for consumers of the Kafka events
+    Asc,
+    /// Newest first, so the most recent rates are there soonest
+    #[default]
+    Desc,
+}
+
+impl DateOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateOrder::Asc => "asc",
+            DateOrder::Desc => "desc",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RunOptions {
+    mode: WriteMode,
+    wait_for_lock: bool,
+    reason: Option<String>,
+    maintain_wide: bool,
+    progress: bool,
+    fetch_names: FetchNames,
+    fail_on_missing: bool,
+    skip_complete: bool,
+    bulk_source: bool,
+    cache_dir: Option<PathBuf>,
+    job: Option<String>,
+    resume: bool,
+    /// `--order` of the dates, stored with the job's progress
+    order: DateOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum WriteOutcome {
+    Inserted,
+    Updated,
+    Unchanged,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct CurrencySummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub errors: usize,
+}
+
+impl CurrencySummary {
+    fn add(&mut self, outcome: WriteOutcome) {
+        match outcome {
+            WriteOutcome::Inserted => self.inserted += 1,
+            WriteOutcome::Updated => self.updated += 1,
+            WriteOutcome::Unchanged => self.unchanged += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &CurrencySummary) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.errors += other.errors;
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct WriteSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub errors: usize,
+    /// The same counts split by currency; RUB pairs count towards the other currency,
+    /// cross pairs towards the `from` currency.
+    pub currencies: BTreeMap<String, CurrencySummary>,
+}
+
+impl WriteSummary {
+    fn add(&mut self, currency: &str, outcome: WriteOutcome) {
+        match outcome {
+            WriteOutcome::Inserted => self.inserted += 1,
+            WriteOutcome::Updated => self.updated += 1,
+            WriteOutcome::Unchanged => self.unchanged += 1,
+        }
+
+        self.currencies
+            .entry(currency.to_string())
+            .or_default()
+            .add(outcome);
+    }
+
+    fn add_error(&mut self, currency: &str) {
+        self.errors += 1;
+        self.currencies
+            .entry(currency.to_string())
+            .or_default()
+            .errors += 1;
+    }
+
+    fn merge(&mut self, other: &WriteSummary) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.errors += other.errors;
+
+        for (currency, summary) in &other.currencies {
+            self.currencies
+                .entry(currency.clone())
+                .or_default()
+                .merge(summary);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+struct RunSummary {
+    run_id: &'static str,
+    #[serde(flatten)]
+    writes: WriteSummary,
+}
+
+impl RunSummary {
+    fn new(writes: WriteSummary) -> Self {
+        RunSummary {
+            run_id: logging::run_id(),
+            writes,
+        }
+    }
+
+    fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) if self.writes.errors > 0 => log::warn!("Run summary: {}", json),
+            Ok(json) => log::info!("Run summary: {}", json),
+            Err(err) => log::error!("Can't serialize run summary {:?}: {}", self, err),
+        }
+    }
+
+    /// Prints `SUMMARY {...}` as one line of stdout, for wrapper scripts to `tail -1`.
+    fn print(&self) -> Result<()> {
+        println!("SUMMARY {}", serde_json::to_string(self)?);
+
+        Ok(())
+    }
+}
+
+/// The `valut` command line: parses the arguments and runs the command, or the daemon
+/// without one.
+pub async fn run_cli() -> Result<()> {
+    dotenvy::dotenv().ok();
+
+    let cli = Cli::parse();
+    let writes_to_db = cli.writes_to_db();
+
+    logging::init(
+        cli.log_format,
+        cli.log_file.clone().map(|path| logging::LogFileOptions {
+            path,
+            max_bytes: cli.log_file_max_mb * 1024 * 1024,
+            keep: cli.log_file_keep,
+        }),
+    )?;
+
+    init_retry_jitter(!cli.no_jitter)?;
+    init_keep_nominal_for(&cli.keep_nominal_for);
+
+    http::init(http::HttpOptions {
+        proxy: cli.proxy,
+        retry_all: cli.retry_all_http,
+        max_total_retries: cli.max_total_retries,
+        http1_only: cli.http1_only,
+    });
+
+    #[cfg(not(feature = "server"))]
+    if cli.read_through {
+        log::warn!("--read-through is set, but valut was built without the server feature");
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    if std::env::var("KAFKA_BROKERS").is_ok() {
+        log::warn!("KAFKA_BROKERS is set, but valut was built without the kafka feature");
+    }
+
+    // До проверки масштаба rate в get_db_pool: её проблему может исправить как раз миграция
+    if cli.migrate && writes_to_db {
+        migrate::migrate(&connect_db().await?).await?;
+    } else if cli.migrate {
+        log::info!("Not applying migrations: the command doesn't write to the database");
+    }
+
+    let result = match cli.command {
+        None => run(cli.today, cli.read_through).await,
+        #[cfg(feature = "server")]
+        Some(Command::Serve) => serve(cli.today, cli.read_through).await,
+        Some(Command::Ingest(args)) => ingest(args, cli.today).await,
+        Some(Command::RecomputeCross(args)) => recompute_cross(args).await,
+        Some(Command::ConfigCheck(args)) => config_check(args).await,
+        Some(Command::Export(args)) => export::export(args, &get_db_pool().await?).await,
+        Some(Command::Import(args)) => import::import(args).await,
+        Some(Command::DiscoverAvailableFrom(args)) => {
+            available_from::discover(args, cli.today).await
+        }
+        Some(Command::Audit(args)) => audit::audit(args).await,
+        Some(Command::Coverage(args)) => coverage::coverage(args, &get_db_pool().await?).await,
+        Some(Command::VerifyAll(args)) => verify::verify_all(args, &get_db_pool().await?).await,
+        Some(Command::Sample(args)) => sample::sample(args, cli.today).await,
+        Some(Command::Trail(args)) => trail::trail(args, &get_db_pool().await?).await,
+        Some(Command::Stats(args)) => stats::stats(args, &get_db_pool().await?).await,
+        Some(Command::GoldenTest(args)) => golden::golden_test(args).await,
+        Some(Command::SchemaCheck) => schema_check::schema_check(&get_db_pool().await?).await,
+    };
+
+    #[cfg(feature = "kafka")]
+    kafka::flush().await;
+
+    result
+}
+
+/// `--db` adds the checks that need the database after the offline ones pass.
+async fn config_check(args: ConfigCheckArgs) -> Result<()> {
+    config::check()?;
+
+    if args.db {
+        match scale_check::describe(&PgPool::connect(&get_connection_string()?).await?).await {
+            Ok(value) => println!("[ OK ] exchange_rates scale = {}", value),
+            Err(err) => {
+                println!("[FAIL] exchange_rates scale: {}", err);
+                return Err(anyhow!("Configuration check failed: 1 problem(s)"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "server"), allow(unused_variables))]
+async fn run(today: Option<NaiveDate>, read_through: bool) -> Result<()> {
+    #[cfg(feature = "server")]
+    server::start_server(today, read_through).await?;
+
+    log::info!("Valut started");
+
+    tokio::select! {
+        _ = async {
+            main_loop(today).await;
+
+            #[allow(unreachable_code)]
+            Ok::<(), anyhow::Error>(())
+        } => {},
+
+        _ = shutdown_signal() => {
+        },
+    };
+
+    log::info!("Valut ended");
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn serve(today: Option<NaiveDate>, read_through: bool) -> Result<()> {
+    server::start_server(today, read_through).await?;
+
+    log::info!("Valut server started");
+
+    shutdown_signal().await?;
+
+    log::info!("Valut server ended");
+
+    Ok(())
+}
+
+async fn ingest(args: IngestArgs, today: Option<NaiveDate>) -> Result<()> {
+    if args.explain {
+        return explain::explain(&args, today).await;
+    }
+
+    let mode = if args.output_sql {
+        WriteMode::OutputSql
+    } else if args.dry_run {
+        WriteMode::DryRun
+    } else {
+        WriteMode::Execute
+    };
+
+    let options = RunOptions {
+        mode,
+        wait_for_lock: args.wait_for_lock,
+        reason: args.reason.clone(),
+        maintain_wide: args.maintain_wide,
+        progress: args.progress,
+        fetch_names: args.fetch_names,
+        fail_on_missing: args.fail_on_missing,
+        skip_complete: args.skip_complete,
+        bulk_source: args.bulk_source,
+        cache_dir: args.cache_dir.clone(),
+        job: args.job.clone(),
+        resume: args.resume,
+        order: args.order,
+    };
+
+    if mode == WriteMode::OutputSql {
+        println!("BEGIN;");
+    }
+
+    let result = ingest_dates(&args, today, &options).await;
+
+    if let Some(webhook_url) = &args.webhook_url {
+        webhook::notify(webhook_url, &result).await;
+    }
+
+    let summary = RunSummary::new(result?);
+    summary.log();
+
+    // С --output-sql stdout — это SQL-скрипт, сводку в него не дописываем
+    if mode == WriteMode::OutputSql {
+        println!("COMMIT;");
+    } else if !args.no_summary {
+        summary.print()?;
+    }
+
+    Ok(())
+}
+
+async fn ingest_dates(
+    args: &IngestArgs,
+    today: Option<NaiveDate>,
+    options: &RunOptions,
+) -> Result<WriteSummary> {
+    store_dates(&get_ingest_dates(args, today)?, options).await
+}
+
+/// The dates in the `--order` they are fetched in.
+fn get_ingest_dates(args: &IngestArgs, today: Option<NaiveDate>) -> Result<Vec<NaiveDate>> {
+    let mut dates = get_newest_first_dates(args, today)?;
+
+    if args.order == DateOrder::Asc {
+        dates.reverse();
+    }
+
+    Ok(dates)
+}
+
+/// CBR publishes the next day's rates in the afternoon, so tomorrow is the latest date
+/// with data. Later dates are dropped with a warning, or fail the run with
+/// `--strict-future`.
+fn get_newest_first_dates(args: &IngestArgs, today: Option<NaiveDate>) -> Result<Vec<NaiveDate>> {
+    let today = get_today(today)?;
+    let latest_date = today
+        .checked_add_days(Days::new(1))
+        .ok_or(anyhow::anyhow!("Can't get next date for {}", today))?;
+
+    if let Some(date) = args.date {
+        limit_future_dates(vec![date], latest_date, args.strict_future)
+    } else if args.dates.is_empty() {
+        let (default_start, default_end) = get_default_window(today)?;
+        let start_date = args.start.unwrap_or(default_start);
+        let mut end_date = args.end.unwrap_or(default_end);
+
+        if end_date > latest_date {
+            if args.strict_future {
+                return Err(anyhow!(
+                    "End date {} is after {}, the latest date CBR can have published",
+                    end_date,
+                    latest_date
+                ));
+            }
+
+            if start_date > latest_date {
+                return Err(anyhow!(
+                    "Every requested date is after {}, the latest date CBR can have published",
+                    latest_date
+                ));
+            }
+
+            log::warn!(
+                "End date {} is after {}, fetching up to {}",
+                end_date,
+                latest_date,
+                latest_date
+            );
+            end_date = latest_date;
+        }
+
+        get_range_dates(start_date, end_date)
+    } else {
+        let mut dates = limit_future_dates(args.dates.clone(), latest_date, args.strict_future)?;
+        dates.sort_by(|a, b| b.cmp(a));
+        dates.dedup();
+
+        Ok(dates)
+    }
+}
+
+fn limit_future_dates(
+    dates: Vec<NaiveDate>,
+    latest_date: NaiveDate,
+    strict: bool,
+) -> Result<Vec<NaiveDate>> {
+    let (dates, future_dates): (Vec<_>, Vec<_>) =
+        dates.into_iter().partition(|date| *date <= latest_date);
+
+    if future_dates.is_empty() {
+        return Ok(dates);
+    }
+
+    let future_dates: Vec<String> = future_dates.iter().map(|date| date.to_string()).collect();
+
+    if strict || dates.is_empty() {
+        return Err(anyhow!(
+            "Dates after {}, the latest date CBR can have published, requested: {}",
+            latest_date,
+            future_dates.join(", ")
+        ));
+    }
+
+    log::warn!(
+        "Skipping dates after {}: {}",
+        latest_date,
+        future_dates.join(", ")
+    );
+
+    Ok(dates)
+}
+
+async fn recompute_cross(args: RecomputeCrossArgs) -> Result<()> {
+    if args.start > args.end {
+        return Err(anyhow::anyhow!("Start date must be before end date"));
+    }
+
+    let pool = get_db_pool().await?;
+    let lock = lock_ingest(&pool, args.wait_for_lock).await?;
+    let result = recompute_locked_cross(&args, &pool).await;
+
+    lock.close().await?;
+
+    let summary = result?;
+
+    println!(
+        "Cross rates recomputed: {} inserted, {} updated, {} unchanged",
+        summary.inserted, summary.updated, summary.unchanged
+    );
+
+    Ok(())
+}
+
+async fn recompute_locked_cross(
+    args: &RecomputeCrossArgs,
+    pool: &Pool<Postgres>,
+) -> Result<WriteSummary> {
+    let currencies = get_currencies()?;
+    let mut summary = WriteSummary::default();
+    let mut current_date = args.start;
+
+    while current_date <= args.end {
+        let base_rates = get_stored_base_rates(&current_date, &currencies, pool).await?;
+
+        if base_rates.len() < currencies.len() {
+            log::warn!(
+                "Only {} of {} base rates are stored at {}",
+                base_rates.len(),
+                currencies.len(),
+                current_date
+            );
+        }
+
+        summary
+            .merge(&store_cross_rates(&current_date, &base_rates, pool, WriteMode::Execute).await?);
+
+        current_date = current_date
+            .succ_opt()
+            .ok_or(anyhow::anyhow!("Can't get next date for {}", current_date))?;
+    }
+
+    Ok(summary)
+}
+
+async fn main_loop(today: Option<NaiveDate>) {
+    let mut retry_count = 0;
+    let mut delay_sec = 0;
+    let mut retry_delay = Duration::ZERO;
+    let mut last_execution = DateTime::<Utc>::MIN_UTC;
+    let mut last_try = DateTime::<Utc>::MIN_UTC;
+
+    loop {
+        let next_execution = last_execution + Duration::from_secs(DELAY_SEC);
+        let next_try = last_try + retry_delay;
+        let now = Utc::now();
+
+        if (now >= next_execution
+            || last_execution.date_naive() != now.date_naive()
+            || last_execution.hour() != now.hour())
+            && now >= next_try
+        {
+            last_try = Utc::now();
+
+            match execute(today).await {
+                Ok(_) => {
+                    retry_count = 0;
+                    delay_sec = 0;
+                    retry_delay = Duration::ZERO;
+                    last_execution = Utc::now();
+                    last_try = DateTime::<Utc>::MIN_UTC;
+                }
+
+                Err(err) => {
+                    log::error!("Error executing task: {:?}", err);
+                    retry_count += 1;
+                    delay_sec = match delay_sec {
+                        0 => RETRYDELAY_SEC,
+                        n => next_delay(n),
+                    };
+                    retry_delay = get_retry_delay(delay_sec);
+                    log::warn!(
+                        "Hourly run failed {} time(s) in a row, retrying in {:.1?}",
+                        retry_count,
+                        retry_delay
+                    );
+                }
+            }
+        };
+
+        if retry_count > 10 {
+            log::error!("Max retries exceeded");
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => {
+            log::info!("SIGTERM received; starting forced shutdown");
+        }
+        _ = sigint.recv() => {
+            log::info!("SIGINT received; starting forced shutdown");
+        }
+    }
+    Ok(())
+}
+
+async fn execute(today: Option<NaiveDate>) -> Result<()> {
+    let (start_date, end_date) = get_default_window(get_today(today)?)?;
+
+    // Демон дожидается разовых запусков ingest, а не падает
+    let options = RunOptions {
+        mode: WriteMode::Execute,
+        wait_for_lock: true,
+        reason: None,
+        maintain_wide: false,
+        progress: false,
+        fetch_names: FetchNames::Feed,
+        fail_on_missing: false,
+        skip_complete: false,
+        bulk_source: false,
+        cache_dir: None,
+        job: None,
+        resume: false,
+        order: DateOrder::Desc,
+    };
+    let writes = iterate(start_date, end_date, &options).await?;
+
+    RunSummary::new(writes).log();
+
+    Ok(())
+}
+
+/// The current date in `CBR_TIMEZONE`, so that around midnight "today" follows CBR's
+/// calendar rather than UTC's.
+fn get_today(today: Option<NaiveDate>) -> Result<NaiveDate> {
+    match today {
+        Some(today) => Ok(today),
+        None => Ok(Utc::now().with_timezone(&get_cbr_timezone()?).date_naive()),
+    }
+}
+
+fn get_default_window(today: NaiveDate) -> Result<(NaiveDate, NaiveDate)> {
+    let start_date = today
+        .checked_sub_days(Days::new(get_lookback_days()?))
+        .ok_or(anyhow::anyhow!("Can't get previous date for {}", today))?;
+    let end_date = today
+        .checked_add_days(Days::new(1))
+        .ok_or(anyhow::anyhow!("Can't get next date for {}", today))?;
+
+    Ok((start_date, end_date))
+}
+
+/// Every calendar date in the range is fetched and stored. CBR answers a request for a
+/// weekend or holiday with the last rates in effect, so those dates carry the previous
+/// business day's values forward; there is no separate skip mode.
+/// Both ends are inclusive, so `start_date == end_date` stores exactly that date.
+async fn iterate(
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    options: &RunOptions,
+) -> Result<WriteSummary> {
+    store_dates(&get_range_dates(start_date, end_date)?, options).await
+}
+
+/// Newest date first.
+fn get_range_dates(start_date: NaiveDate, end_date: NaiveDate) -> Result<Vec<NaiveDate>> {
+    if start_date > end_date {
+        return Err(anyhow::anyhow!("Start date must be before end date"));
+    }
+
+    let mut dates = vec![];
+    let mut current_date = end_date;
+
+    while current_date >= start_date {
+        dates.push(current_date);
+
+        current_date = current_date
+            .pred_opt()
+            .ok_or(anyhow::anyhow!("Can't get pred date for {}", current_date))?;
+    }
+
+    Ok(dates)
+}
+
+/// Holds the ingest advisory lock for the whole run, so overlapping runs don't write the
+/// same dates at once, and records runs that write in `run_log`. With `--resume` the dates
+/// the job already completed are dropped under the lock, so a run started meanwhile can't
+/// move the job's progress between the read and the run.
+async fn store_dates(dates: &[NaiveDate], options: &RunOptions) -> Result<WriteSummary> {
+    http::reset_retry_budget();
+    let pool = get_db_pool().await?;
+    let lock = lock_ingest(&pool, options.wait_for_lock).await?;
+    let all_dates = dates;
+    let dates = match (&options.job, options.resume) {
+        (Some(job), true) => backfill::resume(&pool, job, dates, options.order).await?,
+        _ => dates,
+    };
+
+    if dates.is_empty() {
+        log::info!("Nothing to resume: the job already completed every date");
+        lock.close().await?;
+        return Ok(WriteSummary::default());
+    }
+
+    let run_log_id = match options.mode {
+        WriteMode::Execute => Some(run_log::start(&pool, dates, options.reason.as_deref()).await?),
+        WriteMode::OutputSql | WriteMode::DryRun => None,
+    };
+
+    if options.progress {
+        progress::start(dates.len());
+    }
+
+    let mut result = store_locked_dates(dates, all_dates, options, &pool).await;
+
+    progress::finish();
+
+    if options.maintain_wide && options.mode == WriteMode::Execute && result.is_ok() {
+        // Ошибка обновления витрины тоже попадает в run_log
+        if let Err(err) = wide::refresh(&pool, dates, &get_currencies()?).await {
+            result = Err(err);
+        }
+    }
+
+    if let Some(run_log_id) = run_log_id {
+        run_log::finish(&pool, &run_log_id, &result).await?;
+    }
+
+    lock.close().await?;
+
+    result
+}
+
+/// First dates of the currencies; `CURRENCY_AVAILABLE_FROM` wins over the ones found by
+/// `discover-available-from`.
+fn get_available_from(currency_cache: &CurrencyCache) -> Result<HashMap<String, NaiveDate>> {
+    let mut available_from = currency_cache.available_from.clone();
+    available_from.extend(get_currency_available_from()?);
+
+    Ok(available_from)
+}
+
+/// For `--skip-complete`: whether every currency published at the date has a stored CBR
+/// rate against RUB fetched on or after the date. A rate fetched the day before, when CBR
+/// publishes tomorrow's rates, may still be revised, so that date is fetched once more.
+async fn is_complete_date(
+    date: &NaiveDate,
+    currencies: &[String],
+    currency_cache: &CurrencyCache,
+    pool: &Pool<Postgres>,
+) -> Result<bool> {
+    let available_from = get_available_from(currency_cache)?;
+    let available_until = get_currency_available_until()?;
+
+    let expected: Vec<&String> = currencies
+        .iter()
+        .filter(|currency| {
+            available_from
+                .get(*currency)
+                .is_none_or(|first_date| first_date <= date)
+                && available_until
+                    .get(*currency)
+                    .is_none_or(|last_date| date <= last_date)
+        })
+        .collect();
+
+    let (stored,): (i64,) = sqlx::query_as(&format!(
+        r#"
+            SELECT COUNT(DISTINCT from_currency)
+            FROM {exchange_rates}
+            WHERE date = $1 AND to_currency = 'RUB' AND from_currency = ANY($2)
+                AND source = 'cbr' AND fetched_at >= $1::date
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(date)
+    .bind(&expected)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(stored as usize == expected.len())
+}
+
+/// Dates CBR has no data for are skipped, but a run with fewer than `MIN_FETCHED_DATES`
+/// fetched dates fails instead of looking healthy with nothing written. With
+/// `--fail-on-missing` every currency is required, so a partial feed fails the run too.
+/// With `--job` every date done, stored or skipped, is recorded as the job's progress over
+/// `all_dates`, the run's range before `--resume` dropped any.
+async fn store_locked_dates(
+    dates: &[NaiveDate],
+    all_dates: &[NaiveDate],
+    options: &RunOptions,
+    pool: &Pool<Postgres>,
+) -> Result<WriteSummary> {
+    let currencies = get_currencies()?;
+    let required_currencies = if options.fail_on_missing {
+        currencies.clone()
+    } else {
+        get_required_currencies()?
+    };
+    let min_fetched_dates = get_min_fetched_dates()?.min(dates.len());
+    let mut currency_cache = CurrencyCache::load(pool).await?;
+    let mut summary = WriteSummary::default();
+    let mut fetched_dates = 0;
+    let bulk_feed = match (options.bulk_source, dates.iter().min(), dates.iter().max()) {
+        (true, Some(start), Some(end)) => {
+            Some(BulkFeed::fetch(*start, *end, &currencies, &currency_cache).await?)
+        }
+        _ => None,
+    };
+    let feed_cache = options.cache_dir.as_deref().map(FeedCache::new);
+
+    for date in dates {
+        if options.skip_complete
+            && is_complete_date(date, &currencies, &currency_cache, pool).await?
+        {
+            log::info!(
+                "Skipping {}: every currency is already stored, fetched on or after it",
+                date
+            );
+            fetched_dates += 1;
+            record_backfill_progress(date, all_dates, options, pool).await?;
+            progress::advance(date);
+            continue;
+        }
+
+        let available_from = get_available_from(&currency_cache)?;
+        let bulk_val_curs = match &bulk_feed {
+            Some(bulk_feed) => bulk_feed
+                .get_val_curs(*date, &currencies, &available_from)?
+                .map(|val_curs| (val_curs, bulk_feed.fetched_at)),
+            None => None,
+        };
+        let result = match bulk_val_curs {
+            Some((val_curs, fetched_at)) => {
+                log::debug!("Storing {} from the CBR archives", date);
+                store_bulk_date(
+                    *date,
+                    &val_curs,
+                    &fetched_at,
+                    pool,
+                    &currencies,
+                    &required_currencies,
+                    &currency_cache,
+                    options.mode,
+                )
+                .await
+            }
+            None => {
+                store_date(
+                    *date,
+                    pool,
+                    &currencies,
+                    &required_currencies,
+                    &mut currency_cache,
+                    options.mode,
+                    options.fetch_names,
+                    feed_cache.as_ref(),
+                )
+                .await
+            }
+        };
+
+        match result {
+            Ok(writes) => {
+                fetched_dates += 1;
+                summary.merge(&writes);
+            }
+            Err(err) if err.is::<http::NotFound>() => log::warn!("Skipping {}: {}", date, err),
+            Err(err) => return Err(err),
+        }
+
+        record_backfill_progress(date, all_dates, options, pool).await?;
+        progress::advance(date);
+    }
+
+    if fetched_dates < min_fetched_dates {
+        return Err(anyhow!(
+            "CBR had data for {} of {} dates, MIN_FETCHED_DATES is {}",
+            fetched_dates,
+            dates.len(),
+            min_fetched_dates
+        ));
+    }
+
+    log::info!(
+        "Currency metadata: {} written, {} unchanged",
+        currency_cache.written,
+        currency_cache.skipped
+    );
+
+    Ok(summary)
+}
+
+async fn record_backfill_progress(
+    date: &NaiveDate,
+    all_dates: &[NaiveDate],
+    options: &RunOptions,
+    pool: &Pool<Postgres>,
+) -> Result<()> {
+    match (&options.job, options.mode) {
+        (Some(job), WriteMode::Execute) => {
+            backfill::record(pool, job, date, all_dates, options.order).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Without `wait`, another run (or server refetch) holds the ingest lock.
+#[derive(Debug)]
+pub struct IngestLocked;
+
+impl fmt::Display for IngestLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Another run in progress; use --wait-for-lock to wait for it"
+        )
+    }
+}
+
+impl std::error::Error for IngestLocked {}
+
+/// The lock lives on a connection taken out of the pool: closing it, including on an early
+/// return, releases the lock.
+async fn lock_ingest(pool: &Pool<Postgres>, wait: bool) -> Result<PgConnection> {
+    let mut connection = pool.acquire().await?.detach();
+
+    if wait {
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(INGEST_LOCK_KEY)
+            .execute(&mut connection)
+            .await?;
+    } else {
+        let (locked,): (bool,) = sqlx::query_as("SELECT pg_try_advisory_lock($1)")
+            .bind(INGEST_LOCK_KEY)
+            .fetch_one(&mut connection)
+            .await?;
+
+        if !locked {
+            return Err(IngestLocked.into());
+        }
+    }
+
+    Ok(connection)
+}
+
+/// Refetches the date for the server under the ingest lock, so it doesn't write the date
+/// at the same time as an ingest run. A request doesn't wait for a run, which may take
+/// hours: it fails with `IngestLocked` instead.
+#[cfg(feature = "server")]
+async fn reingest_date(date: NaiveDate) -> Result<RunSummary> {
+    let pool = get_db_pool().await?;
+    let lock = lock_ingest(&pool, false).await?;
+    let result = store_server_date(date, &pool).await;
+
+    lock.close().await?;
+
+    Ok(RunSummary::new(result?))
+}
+
+#[cfg(feature = "server")]
+async fn store_server_date(date: NaiveDate, pool: &Pool<Postgres>) -> Result<WriteSummary> {
+    let currencies = get_currencies()?;
+    let mut currency_cache = CurrencyCache::load(pool).await?;
+
+    store_date(
+        date,
+        pool,
+        &currencies,
+        &get_required_currencies()?,
+        &mut currency_cache,
+        WriteMode::Execute,
+        FetchNames::Feed,
+        None,
+    )
+    .await
+}
+
+/// Refetches the date from `source` and rewrites only the `from -> to` pair, for a service
+/// embedding valut that suspects one pair; either side may be RUB, otherwise the pair is a
+/// cross rate of the two RUB rates. CBR (`cbr`) is the only source. It holds the ingest
+/// lock meanwhile and fails with `IngestLocked` instead of waiting while a run holds it.
+pub async fn refresh_pair(
+    pool: &PgPool,
+    source: &str,
+    date: NaiveDate,
+    from_currency: &str,
+    to_currency: &str,
+) -> Result<WriteSummary> {
+    if source != "cbr" {
+        return Err(anyhow!("Unknown source {}, expected cbr", source));
+    }
+
+    let lock = lock_ingest(pool, false).await?;
+    let result = refresh_locked_pair(pool, date, from_currency, to_currency).await;
+
+    lock.close().await?;
+
+    result
+}
+
+async fn refresh_locked_pair(
+    pool: &PgPool,
+    date: NaiveDate,
+    from_currency: &str,
+    to_currency: &str,
+) -> Result<WriteSummary> {
+    let val_curs = get_val_curs(date).await?;
+    let fetched_at = Utc::now();
+    let currencies: Vec<String> = [from_currency, to_currency]
+        .into_iter()
+        .filter(|currency| *currency != "RUB")
+        .map(String::from)
+        .collect();
+    let aliases = get_currency_aliases()?;
+    let exchange_rates = get_curs_map(&val_curs, &aliases, &currencies).await?;
+    let nominal_rates = get_nominal_rates(&val_curs, &aliases)?;
+    let get_base_rate = |currency: &str| {
+        exchange_rates.get(currency).ok_or(anyhow!(
+            "There is not val_cur for {} at {}",
+            currency,
+            date
+        ))
+    };
+
+    let get_rub_rate = |currency: &str| match nominal_rates.get(currency) {
+        Some(nominal_rate) => Ok((&nominal_rate.value, nominal_rate.nominal)),
+        None => get_base_rate(currency).map(|rate| (rate, 1)),
+    };
+
+    let (raw_rate, nominal) = match (from_currency, to_currency) {
+        (from_currency, to_currency) if from_currency == to_currency => {
+            return Err(anyhow!(
+                "Pair {} -> {} is not stored",
+                from_currency,
+                to_currency
+            ));
+        }
+        (from_currency, "RUB") => {
+            let (rate, nominal) = get_rub_rate(from_currency)?;
+            (*rate, nominal)
+        }
+        ("RUB", to_currency) => {
+            let (rate, nominal) = get_rub_rate(to_currency)?;
+            (get_reverse_rate(rate, "RUB", to_currency, &date)?, nominal)
+        }
+        (from_currency, to_currency) => (
+            get_cross_rate(
+                get_base_rate(from_currency)?,
+                get_base_rate(to_currency)?,
+                from_currency,
+                to_currency,
+                &date,
+            )?,
+            1,
+        ),
+    };
+
+    let outcome = set_exchange_rate(
+        &date,
+        &from_currency.to_string(),
+        &to_currency.to_string(),
+        &raw_rate,
+        nominal,
+        QuoteConvention::of(from_currency, to_currency),
+        Some(&fetched_at),
+        val_curs.get_date(),
+        pool,
+        WriteMode::Execute,
+    )
+    .await?;
+
+    let mut summary = WriteSummary::default();
+    summary.add(
+        if from_currency == "RUB" {
+            to_currency
+        } else {
+            from_currency
+        },
+        outcome,
+    );
+
+    Ok(summary)
+}
+
+#[cfg(feature = "server")]
+async fn reingest_pair(
+    date: NaiveDate,
+    from_currency: &str,
+    to_currency: &str,
+) -> Result<RunSummary> {
+    let pool = get_db_pool().await?;
+
+    Ok(RunSummary::new(
+        refresh_pair(&pool, "cbr", date, from_currency, to_currency).await?,
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn store_date(
+    date: NaiveDate,
+    pool: &Pool<Postgres>,
+    currencies: &Vec<String>,
+    required_currencies: &[String],
+    currency_cache: &mut CurrencyCache,
+    mode: WriteMode,
+    fetch_names: FetchNames,
+    feed_cache: Option<&FeedCache>,
+) -> Result<WriteSummary> {
+    let lang = get_cbr_lang()?;
+    let (val_curs, fetched_at) = get_cached_val_curs(date, lang, feed_cache).await?;
+    let aliases = get_currency_aliases()?;
+    let exchange_rates = get_curs_map(&val_curs, &aliases, currencies).await?;
+    let names = match fetch_names {
+        FetchNames::Feed => None,
+        FetchNames::Both => Some(get_currency_names(date, lang, &val_curs).await?),
+    };
+
+    update_stored_currencies(
+        &val_curs,
+        &aliases,
+        currencies,
+        names.as_ref(),
+        currency_cache,
+        pool,
+        mode,
+    )
+    .await?;
+
+    store_val_curs(
+        date,
+        &val_curs,
+        &exchange_rates,
+        &fetched_at,
+        pool,
+        currencies,
+        required_currencies,
+        currency_cache,
+        mode,
+    )
+    .await
+}
+
+/// Stores a date of the `--bulk-source` archives. They have no names or num codes, so the
+/// stored currency metadata is left as it is.
+#[allow(clippy::too_many_arguments)]
+async fn store_bulk_date(
+    date: NaiveDate,
+    val_curs: &ValCurs,
+    fetched_at: &DateTime<Utc>,
+    pool: &Pool<Postgres>,
+    currencies: &Vec<String>,
+    required_currencies: &[String],
+    currency_cache: &CurrencyCache,
+    mode: WriteMode,
+) -> Result<WriteSummary> {
+    // Архив запрашивается по CBR ID, так что коды в нём уже канонические
+    let exchange_rates = get_curs_map(val_curs, &HashMap::new(), currencies).await?;
+
+    store_val_curs(
+        date,
+        val_curs,
+        &exchange_rates,
+        fetched_at,
+        pool,
+        currencies,
+        required_currencies,
+        currency_cache,
+        mode,
+    )
+    .await
+}
+
+/// Stores the rates of a fetched daily feed, or of one rebuilt from the `--bulk-source`
+/// archives, with `exchange_rates` already parsed from it by `get_curs_map`.
+#[allow(clippy::too_many_arguments)]
+async fn store_val_curs(
+    date: NaiveDate,
+    val_curs: &ValCurs,
+    exchange_rates: &HashMap<String, Decimal>,
+    fetched_at: &DateTime<Utc>,
+    pool: &Pool<Postgres>,
+    currencies: &Vec<String>,
+    required_currencies: &[String],
+    currency_cache: &CurrencyCache,
+    mode: WriteMode,
+) -> Result<WriteSummary> {
+    let aliases = get_currency_aliases()?;
+    let available_from = get_available_from(currency_cache)?;
+    let nominal_rates = get_nominal_rates(val_curs, &aliases)?;
+    let retries = get_db_acquire_retries()?;
+    let mut attempt = 0;
+    let mut delay_sec = RETRYDELAY_SEC;
+
+    // Запись идемпотентна: при повторе уже записанные курсы окажутся неизменными. Повторяем
+    // только её: с --output-sql повтор второй раз напечатал бы уже выведенные команды, а
+    // --dry-run ничего не пишет, и ждать свободного соединения ему незачем
+    loop {
+        let result = update_stored_exchange_rates(
+            &date,
+            exchange_rates,
+            &nominal_rates,
+            &available_from,
+            fetched_at,
+            val_curs.get_date(),
+            pool,
+            currencies,
+            required_currencies,
+            mode,
+        )
+        .await;
+
+        match result {
+            Err(err)
+                if mode == WriteMode::Execute && attempt < retries && is_pool_timeout(&err) =>
+            {
+                attempt += 1;
+                let delay = get_retry_delay(delay_sec);
+                log::warn!(
+                    "Can't acquire a database connection for {} (retry {} of {} in {:.1?}): {}",
+                    date,
+                    attempt,
+                    retries,
+                    delay,
+                    err
+                );
+                tokio::time::sleep(delay).await;
+                delay_sec = next_delay(delay_sec);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether the pool ran out of connections for `acquire_timeout`, as opposed to a failed
+/// query or a lost connection.
+fn is_pool_timeout(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::PoolTimedOut)
+    )
+}
+
+/// Only the valutes of `currencies` (or their aliases) are parsed, so a malformed rate
+/// of a currency we don't store doesn't fail the date. A stored currency whose rate is a
+/// number too large for `Decimal` is logged and left out, like a currency missing from the
+/// feed, which fails the date only for `REQUIRED_CURRENCIES`; any other malformed rate
+/// fails it. CBR doesn't list RUB, but a `RUB` valute (or one aliased to RUB) is ignored
+/// rather than stored as an identity `RUB -> RUB` rate.
+async fn get_curs_map(
+    val_curs: &ValCurs,
+    aliases: &HashMap<String, String>,
+    currencies: &[String],
+) -> Result<HashMap<String, Decimal>> {
+    let mut rates = vec![];
+
+    for valute in &val_curs.valute {
+        let code = aliases.get(&valute.char_code).unwrap_or(&valute.char_code);
+
+        // RUB — база всех курсов: его Valute дал бы строку RUB -> RUB, которой не должно быть
+        if code == "RUB" {
+            log::warn!(
+                "Ignoring Valute {} in the CBR feed: RUB is the base, not a quoted currency",
+                valute.char_code
+            );
+            continue;
+        }
+
+        if !currencies.contains(code) {
+            continue;
+        }
+
+        match ParsedRate::try_from(valute) {
+            Ok(rate) => rates.push(rate),
+            Err(err) if err.is_out_of_range() => {
+                log::warn!("Skipping {}: {}", valute.char_code, err);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let mut map = HashMap::new();
+
+    for ParsedRate { char_code, rate } in rates {
+        match aliases.get(&char_code) {
+            // Если в фиде есть и старый, и новый код, приоритет у нового
+            Some(canonical_code) => {
+                map.entry(canonical_code.clone()).or_insert(rate);
+            }
+            None => {
+                map.insert(char_code, rate);
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+/// `Value` and `Nominal` of the `--keep-nominal-for` currencies that CBR quotes per more
+/// than one unit, e.g. 10000 IRR; their RUB pairs are stored for that many units, so a
+/// tiny per-unit rate is not cut off by `RATE_SCALE`.
+fn get_nominal_rates(
+    val_curs: &ValCurs,
+    aliases: &HashMap<String, String>,
+) -> Result<HashMap<String, NominalRate>> {
+    let keep_nominal_for = get_keep_nominal_for();
+    let mut map = HashMap::new();
+
+    for valute in &val_curs.valute {
+        let code = aliases.get(&valute.char_code).unwrap_or(&valute.char_code);
+
+        if !keep_nominal_for.contains(code) {
+            continue;
+        }
+
+        let nominal_rate = match NominalRate::try_from(valute) {
+            Ok(nominal_rate) => nominal_rate,
+            // Валюту уже пропустил get_curs_map
+            Err(err) if err.is_out_of_range() => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if nominal_rate.nominal == 1 {
+            continue;
+        }
+
+        // Как в get_curs_map: если в фиде есть и старый, и новый код, приоритет у нового
+        if code == &valute.char_code {
+            map.insert(code.clone(), nominal_rate);
+        } else {
+            map.entry(code.clone()).or_insert(nominal_rate);
+        }
+    }
+
+    Ok(map)
+}
+
+/// Russian and English names by feed char code, fetching the feed of the language
+/// `val_curs` is not in.
+async fn get_currency_names(
+    date: NaiveDate,
+    lang: CbrLang,
+    val_curs: &ValCurs,
+) -> Result<HashMap<String, CurrencyNames>> {
+    let (other_lang, ru_is_other) = match lang {
+        CbrLang::Ru => (CbrLang::En, false),
+        CbrLang::En => (CbrLang::Ru, true),
+    };
+    let other_val_curs = get_val_curs_in(date, other_lang).await?;
+    let other_names: HashMap<&String, &String> = other_val_curs
+        .valute
+        .iter()
+        .map(|valute| (&valute.char_code, &valute.name))
+        .collect();
+
+    Ok(val_curs
+        .valute
+        .iter()
+        .filter_map(|valute| {
+            let other_name = other_names.get(&valute.char_code)?.to_string();
+            let (ru, en) = if ru_is_other {
+                (other_name, valute.name.clone())
+            } else {
+                (valute.name.clone(), other_name)
+            };
+
+            Some((valute.char_code.clone(), CurrencyNames { ru, en }))
+        })
+        .collect())
+}
+
+async fn get_val_curs(date: NaiveDate) -> Result<ValCurs> {
+    get_val_curs_in(date, get_cbr_lang()?).await
+}
+
+/// The feed of `date` from `--cache-dir` when it has it, with the time it was first
+/// fetched, otherwise fetched and cached. A date that hasn't begun in CBR_TIMEZONE isn't
+/// cached: its feed can still change.
+async fn get_cached_val_curs(
+    date: NaiveDate,
+    lang: CbrLang,
+    feed_cache: Option<&FeedCache>,
+) -> Result<(ValCurs, DateTime<Utc>)> {
+    let Some(feed_cache) = feed_cache else {
+        let val_curs = get_val_curs_in(date, lang).await?;
+        return Ok((val_curs, Utc::now()));
+    };
+
+    let url = get_url(date, lang).await?;
+
+    if let Some((val_curs, fetched_at)) = feed_cache.get(date, &url) {
+        log::debug!("Feed of {} from the cache, fetched at {}", date, fetched_at);
+        return Ok((val_curs, fetched_at));
+    }
+
+    let val_curs = get_val_curs_in(date, lang).await?;
+    let fetched_at = Utc::now();
+
+    if fetched_at.with_timezone(&get_cbr_timezone()?).date_naive() >= date {
+        feed_cache.put(date, &url, &val_curs, &fetched_at);
+    }
+
+    Ok((val_curs, fetched_at))
+}
+
+async fn get_val_curs_in(date: NaiveDate, lang: CbrLang) -> Result<ValCurs> {
+    let url = get_url(date, lang).await?;
+    let text = http::load_xml(&url).await?;
+    let val_curs: ValCurs = quick_xml::de::from_str(&text)?;
+
+    check_val_curs(date, &val_curs)?;
+    log_feed_date_lag(date, &val_curs);
+
+    Ok(val_curs)
+}
+
+/// An empty feed or a malformed CharCode fails the date. A rate that doesn't parse is only
+/// logged here: it fails the date later, in `get_curs_map`, if the currency is stored.
+fn check_val_curs(date: NaiveDate, val_curs: &ValCurs) -> Result<()> {
+    let mut errors = vec![];
+
+    for err in val_curs.validate() {
+        match err {
+            FeedError::InvalidRate(_) => log::warn!("CBR feed at {}: {}", date, err),
+            _ => errors.push(err.to_string()),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "Invalid CBR feed at {}: {}",
+            date,
+            errors.join("; ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Days between the requested date and the feed's `Date`, to see when CBR publishes; also
+/// the `valut_feed_date_lag_days` gauge of `/metrics`.
+fn log_feed_date_lag(date: NaiveDate, val_curs: &ValCurs) {
+    let Some(feed_date) = &val_curs.date else {
+        log::debug!("Feed for {} has no Date attribute", date);
+        return;
+    };
+
+    match NaiveDate::parse_from_str(feed_date, "%d.%m.%Y") {
+        Ok(feed_date) => {
+            let lag = (date - feed_date).num_days();
+            metrics::set_feed_date_lag(lag);
+            log::debug!(
+                "Feed for {} is dated {}, lag {} day(s)",
+                date,
+                feed_date,
+                lag
+            );
+        }
+        Err(err) => log::debug!("Invalid feed Date {} for {}: {}", feed_date, date, err),
+    }
+}
+
+/// CBR expects a zero-padded `DD/MM/YYYY` date, e.g. `date_req=29/02/2024` or
+/// `date_req=05/01/2024`; chrono formatting does not depend on the locale.
+async fn get_url(date: NaiveDate, lang: CbrLang) -> Result<String> {
+    let page = match lang {
+        CbrLang::Ru => "XML_daily.asp",
+        CbrLang::En => "XML_daily_eng.asp",
+    };
+
+    let mut url = get_cbr_base_url()?.join(page)?;
+    url.set_query(Some(&format!("date_req={}", date.format("%d/%m/%Y"))));
+
+    Ok(url.to_string())
+}
+
+async fn update_stored_currencies(
+    val_curs: &ValCurs,
+    aliases: &HashMap<String, String>,
+    currencies: &[String],
+    names: Option<&HashMap<String, CurrencyNames>>,
+    currency_cache: &mut CurrencyCache,
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<()> {
+    for valute in &val_curs.valute {
+        let char_code = aliases.get(&valute.char_code).unwrap_or(&valute.char_code);
+
+        if !currencies.contains(char_code) {
+            continue;
+        }
+
+        if currency_cache.is_stored(char_code, valute) {
+            currency_cache.skipped += 1;
+        } else {
+            set_currency(char_code, valute, pool, mode).await?;
+            currency_cache.set(char_code, valute);
+            currency_cache.written += 1;
+        }
+
+        if let Some(names) = names.and_then(|names| names.get(&valute.char_code))
+            && !currency_cache.has_names(char_code, names)
+        {
+            set_currency_names(char_code, names, pool, mode).await?;
+            currency_cache.set_names(char_code, names);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs after `set_currency`, so the row exists.
+async fn set_currency_names(
+    char_code: &str,
+    names: &CurrencyNames,
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<()> {
+    match mode {
+        WriteMode::OutputSql => {
+            println!(
+                "{}",
+                sql_script::update_currency_names(&get_table_name("currencies")?, char_code, names)
+            );
+            return Ok(());
+        }
+        WriteMode::DryRun => return Ok(()),
+        WriteMode::Execute => {}
+    }
+
+    let result = sqlx::query(&format!(
+        r#"
+            UPDATE {currencies}
+            SET name_ru = $2, name_en = $3, updated_at = NOW()
+            WHERE char_code = $1 AND (name_ru, name_en) IS DISTINCT FROM ($2, $3)
+        "#,
+        currencies = get_table_name("currencies")?,
+    ))
+    .bind(char_code)
+    .bind(&names.ru)
+    .bind(&names.en)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        log::info!(
+            "Currency names stored: {} = {} / {}",
+            char_code,
+            names.ru,
+            names.en
+        );
+    }
+
+    Ok(())
+}
+
+async fn set_currency(
+    char_code: &str,
+    valute: &Valute,
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<()> {
+    match mode {
+        WriteMode::OutputSql => {
+            println!(
+                "{}",
+                sql_script::upsert_currency(&get_table_name("currencies")?, char_code, valute)
+            );
+            return Ok(());
+        }
+        WriteMode::DryRun => return Ok(()),
+        WriteMode::Execute => {}
+    }
+
+    let result = sqlx::query(&format!(
+        r#"
+            INSERT INTO {currencies} AS currencies (char_code, cbr_id, num_code, name, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, NOW(), NOW())
+            ON CONFLICT (char_code) DO UPDATE
+            SET cbr_id = EXCLUDED.cbr_id, num_code = EXCLUDED.num_code, name = EXCLUDED.name, updated_at = NOW()
+            WHERE (currencies.cbr_id, currencies.num_code, currencies.name)
+                IS DISTINCT FROM (EXCLUDED.cbr_id, EXCLUDED.num_code, EXCLUDED.name)
+        "#,
+        currencies = get_table_name("currencies")?,
+    ))
+    .bind(char_code)
+    .bind(&valute.id)
+    .bind(&valute.num_code)
+    .bind(&valute.name)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        log::info!(
+            "Currency stored: {} ({}, {}) = {}",
+            char_code,
+            valute.id,
+            valute.num_code,
+            valute.name
+        );
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn update_stored_exchange_rates(
+    date: &NaiveDate,
+    exchange_rates: &HashMap<String, Decimal>,
+    nominal_rates: &HashMap<String, NominalRate>,
+    available_from: &HashMap<String, NaiveDate>,
+    fetched_at: &DateTime<Utc>,
+    feed_date: Option<NaiveDate>,
+    pool: &Pool<Postgres>,
+    currencies: &Vec<String>,
+    required_currencies: &[String],
+    mode: WriteMode,
+) -> Result<WriteSummary> {
+    let available_until = get_currency_available_until()?;
+    let mut summary = WriteSummary::default();
+    let mut rates = vec![];
+    let mut base_rates = vec![];
+    let mut nominals = HashMap::new();
+
+    for currency in currencies {
+        // get_currencies не пропускает RUB, но пара RUB -> RUB не должна появиться ни при каком списке
+        if currency == "RUB" {
+            log::warn!(
+                "Skipping RUB at {}: it is the base every rate is quoted against, not a target",
+                date
+            );
+            continue;
+        }
+
+        if let Some(first_date) = available_from.get(currency)
+            && date < first_date
+        {
+            log::debug!(
+                "Skipping {} at {}: published only since {}",
+                currency,
+                date,
+                first_date
+            );
+            continue;
+        }
+
+        if let Some(last_date) = available_until.get(currency)
+            && date > last_date
+        {
+            log::info!(
+                "Skipping {} at {}: deprecated, published only until {}",
+                currency,
+                date,
+                last_date
+            );
+            continue;
+        }
+
+        // Фид загружен и разобран, но валюты в нём нет: пропускаем её, если она не обязательная
+        let Some(rate) = exchange_rates.get(currency) else {
+            let partial_feed = PartialFeed {
+                date: *date,
+                currency: currency.clone(),
+                present: exchange_rates.len(),
+            };
+
+            if required_currencies.contains(currency) {
+                return Err(partial_feed.into());
+            }
+
+            log::warn!("{}, skipping it", partial_feed);
+            summary.add_error(currency);
+            continue;
+        };
+        // Кросс-курсы и корзины всегда считаются от курса за единицу
+        base_rates.push((currency.clone(), *rate));
+
+        let rate = match nominal_rates.get(currency) {
+            Some(nominal_rate) => {
+                nominals.insert(currency.clone(), nominal_rate.nominal);
+                &nominal_rate.value
+            }
+            None => rate,
+        };
+        rates.extend(get_rub_pair_rates(currency, rate, date)?);
+    }
+
+    rates.extend(get_cross_rates(date, &base_rates)?);
+
+    for (basket, components) in get_currency_baskets()? {
+        let Some(rate) = get_basket_rate(&components, &base_rates) else {
+            log::warn!(
+                "Skipping basket {} at {}: not every component rate is available",
+                basket,
+                date
+            );
+            continue;
+        };
+        rates.extend(get_rub_pair_rates(&basket, &rate, date)?);
+    }
+
+    for (code, index) in get_currency_indices()? {
+        let index_base_rates = if index.base_date == *date {
+            base_rates.clone()
+        } else {
+            let components: Vec<String> = index
+                .components
+                .iter()
+                .map(|(currency, _)| currency.clone())
+                .collect();
+
+            get_stored_base_rates(&index.base_date, &components, pool).await?
+        };
+
+        let Some(rate) = get_index_rate(&index.components, &base_rates, &index_base_rates) else {
+            log::warn!(
+                "Skipping index {} at {}: not every component rate is available at {} and at the base date {}",
+                code,
+                date,
+                date,
+                index.base_date
+            );
+            continue;
+        };
+        // Индекс — не валюта, обратную пару RUB -> индекс не храним
+        rates.push((code, "RUB".to_string(), rate));
+    }
+
+    summary.merge(
+        &store_rates(
+            date,
+            &rates,
+            &nominals,
+            Some(fetched_at),
+            feed_date,
+            pool,
+            mode,
+        )
+        .await?,
+    );
+
+    Ok(summary)
+}
+
+/// `sum(weight * rate)` of the components' RUB rates.
+fn get_basket_rate(
+    components: &[(String, Decimal)],
+    base_rates: &[(String, Decimal)],
+) -> Option<Decimal> {
+    components
+        .iter()
+        .map(|(currency, weight)| {
+            base_rates
+                .iter()
+                .find(|(base_currency, _)| base_currency == currency)
+                .and_then(|(_, rate)| weight.checked_mul(*rate))
+        })
+        .try_fold(Decimal::ZERO, |total, value| total.checked_add(value?))
+}
+
+/// `100 * sum(weight * base_rate / rate)` of the components' RUB rates, an arithmetic
+/// nominal effective exchange rate: 100 at the base date, above 100 when the RUB buys more
+/// of the components than it did then.
+fn get_index_rate(
+    components: &[(String, Decimal)],
+    rates: &[(String, Decimal)],
+    base_rates: &[(String, Decimal)],
+) -> Option<Decimal> {
+    let get_rate = |rates: &[(String, Decimal)], currency: &String| {
+        rates
+            .iter()
+            .find(|(rate_currency, _)| rate_currency == currency)
+            .map(|(_, rate)| *rate)
+    };
+
+    components
+        .iter()
+        .map(|(currency, weight)| {
+            get_rate(base_rates, currency)?
+                .checked_div(get_rate(rates, currency)?)?
+                .checked_mul(*weight)
+        })
+        .try_fold(Decimal::ZERO, |total, value| total.checked_add(value?))?
+        .checked_mul(Decimal::ONE_HUNDRED)
+}
+
+/// Stores every `from -> to` combination of the given currencies, derived from their
+/// rates against RUB. Nothing is fetched, so the rows get no `fetched_at` or `feed_date`.
+async fn store_cross_rates(
+    date: &NaiveDate,
+    base_rates: &[(String, Decimal)],
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<WriteSummary> {
+    store_rates(
+        date,
+        &get_cross_rates(date, base_rates)?,
+        &HashMap::new(),
+        None,
+        None,
+        pool,
+        mode,
+    )
+    .await
+}
+
+/// CBR quotes every currency against RUB, so each cross rate pivots through it:
+/// `from -> to = (from -> RUB) / (to -> RUB)`. Every ordered pair is stored, so a reader
+/// with any base (USD, say) finds `EUR -> GBP` already derived from the same RUB rates.
+fn get_cross_rates(
+    date: &NaiveDate,
+    base_rates: &[(String, Decimal)],
+) -> Result<Vec<(String, String, Decimal)>> {
+    let mut rates = vec![];
+
+    for (from_currency, from_rate) in base_rates {
+        for (to_currency, to_rate) in base_rates {
+            if from_currency == to_currency {
+                continue;
+            }
+
+            let rate = get_cross_rate(from_rate, to_rate, from_currency, to_currency, date)?;
+
+            rates.push((from_currency.clone(), to_currency.clone(), rate));
+        }
+    }
+
+    Ok(rates)
+}
+
+/// Loads the date's stored rates once and only calls `set_exchange_rate` for pairs that
+/// are new or differ, so an unchanged re-run of a date does no per-pair queries.
+/// `nominals` holds the currencies whose RUB pairs are quoted per more than one unit.
+async fn store_rates(
+    date: &NaiveDate,
+    rates: &[(String, String, Decimal)],
+    nominals: &HashMap<String, u32>,
+    fetched_at: Option<&DateTime<Utc>>,
+    feed_date: Option<NaiveDate>,
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<WriteSummary> {
+    let mut summary = WriteSummary::default();
+    let stored_rates = get_stored_rates(date, pool).await?;
+
+    for (from_currency, to_currency, raw_rate) in rates {
+        // Пары с RUB учитываем за второй валютой, кросс-курсы — за исходной
+        let currency = if from_currency == "RUB" {
+            to_currency
+        } else {
+            from_currency
+        };
+        let stored_rate = stored_rates.get(&(from_currency.clone(), to_currency.clone()));
+        let nominal = if from_currency == "RUB" || to_currency == "RUB" {
+            nominals.get(currency).copied().unwrap_or(1)
+        } else {
+            1
+        };
+
+        let outcome = if stored_rate == Some(&get_rounded_rate(raw_rate)?) {
+            WriteOutcome::Unchanged
+        } else {
+            set_exchange_rate(
+                date,
+                from_currency,
+                to_currency,
+                raw_rate,
+                nominal,
+                QuoteConvention::of(from_currency, to_currency),
+                fetched_at,
+                feed_date,
+                pool,
+                mode,
+            )
+            .await?
+        };
+
+        summary.add(currency, outcome);
+    }
+
+    if summary.unchanged == rates.len() {
+        log::debug!("Exchange rates at {} are unchanged, nothing written", date);
+    }
+
+    Ok(summary)
+}
+
+async fn get_stored_rates(
+    date: &NaiveDate,
+    pool: &Pool<Postgres>,
+) -> Result<HashMap<(String, String), Decimal>> {
+    let rows: Vec<(String, String, Decimal)> = sqlx::query_as(&format!(
+        r#"
+            SELECT from_currency, to_currency, rate
+            FROM {exchange_rates}
+            WHERE date = $1
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(date)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(from_currency, to_currency, rate)| ((from_currency, to_currency), rate))
+        .collect())
+}
+
+fn get_cross_rate(
+    from_rate: &Decimal,
+    to_rate: &Decimal,
+    from_currency: &str,
+    to_currency: &str,
+    date: &NaiveDate,
+) -> Result<Decimal> {
+    from_rate.checked_div(*to_rate).ok_or(anyhow!(
+        "Can't compute cross rate for {} -> {} at {}: {} / {} is undefined or out of range",
+        from_currency,
+        to_currency,
+        date,
+        from_rate,
+        to_rate
+    ))
+}
+
+/// Stored `X -> RUB` rates of the currencies at the date, per one unit of `X` even when
+/// stored per `--keep-nominal-for` nominal.
+async fn get_stored_base_rates(
+    date: &NaiveDate,
+    currencies: &[String],
+    pool: &Pool<Postgres>,
+) -> Result<Vec<(String, Decimal)>> {
+    let rows: Vec<(String, Decimal)> = sqlx::query_as(&format!(
+        r#"
+            SELECT from_currency, rate / nominal
+            FROM {exchange_rates}
+            WHERE to_currency = 'RUB' AND date = $1 AND from_currency = ANY($2)
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(date)
+    .bind(currencies)
+    .fetch_all(pool)
+    .await?;
+
+    let rows: HashMap<String, Decimal> = rows.into_iter().collect();
+
+    Ok(currencies
+        .iter()
+        .filter_map(|currency| rows.get(currency).map(|rate| (currency.clone(), *rate)))
+        .collect())
+}
+
+/// The two stored rows of a CBR quote. CBR quotes RUB per unit of `currency` (USD
+/// 92.2628 means 1 USD = 92.2628 RUB), and a stored `from -> to` rate always reads
+/// "1 `from` = `rate` `to`", so the quote is stored unchanged as `currency -> RUB` and its
+/// reciprocal as `RUB -> currency`. Swapping them would put USD -> RUB near 0.011
+/// instead of near 90.
+fn get_rub_pair_rates(
+    currency: &str,
+    quote: &Decimal,
+    date: &NaiveDate,
+) -> Result<[(String, String, Decimal); 2]> {
+    let rub = "RUB".to_string();
+    let reverse_rate = get_reverse_rate(quote, &rub, currency, date)?;
+
+    Ok([
+        (currency.to_string(), rub.clone(), *quote),
+        (rub, currency.to_string(), reverse_rate),
+    ])
+}
+
+/// `1 / rate`, the rate of `from_currency -> to_currency` when `rate` is the opposite pair.
+/// rust_decimal keeps 28-29 significant digits, so the reverse of a rate between 1e-6 and
+/// 1e6 (every CBR rate so far) keeps at least 22 of them in `raw_rate`.
+fn get_reverse_rate(
+    rate: &Decimal,
+    from_currency: &str,
+    to_currency: &str,
+    date: &NaiveDate,
+) -> Result<Decimal> {
+    Decimal::ONE.checked_div(*rate).ok_or(anyhow!(
+        "Can't compute reverse rate for {} -> {} at {}: 1 / {} is undefined or out of range",
+        from_currency,
+        to_currency,
+        date,
+        rate
+    ))
+}
+
+/// A stored rate fetched later than `fetched_at` is kept, so a stale replay can't
+/// overwrite fresher data; without `fetched_at` on either side the last write wins.
+/// A write without `fetched_at` or `feed_date`, such as `import`, keeps the stored ones.
+/// `date` is the calendar date the rate is in force on; `feed_date` is the feed's own
+/// `Date`, the date CBR set the rate on, `None` when the rate didn't come from a feed.
+#[allow(clippy::too_many_arguments)]
+async fn set_exchange_rate(
+    date: &NaiveDate,
+    from_currency: &String,
+    to_currency: &String,
+    raw_rate: &Decimal,
+    nominal: u32,
+    convention: QuoteConvention,
+    fetched_at: Option<&DateTime<Utc>>,
+    feed_date: Option<NaiveDate>,
+    pool: &Pool<Postgres>,
+    mode: WriteMode,
+) -> Result<WriteOutcome> {
+    let rate = &get_rounded_rate(raw_rate)?;
+
+    let exchange_rate: Option<ExchangeRate> = sqlx::query_as(&format!(
+        r#"
+            SELECT id, rate, fetched_at
+            FROM {exchange_rates}
+            WHERE from_currency = $1 AND to_currency = $2 AND date = $3
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(date)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(exchange_rate) = exchange_rate {
+        if exchange_rate.rate != *rate {
+            if let (Some(stored_fetched_at), Some(fetched_at)) =
+                (exchange_rate.fetched_at, fetched_at)
+                && stored_fetched_at > *fetched_at
+            {
+                log::warn!(
+                    "Keeping {} -> {} at {} = {} fetched at {}, newer than {} fetched at {}",
+                    from_currency,
+                    to_currency,
+                    date,
+                    exchange_rate.rate,
+                    stored_fetched_at,
+                    rate,
+                    fetched_at
+                );
+                return Ok(WriteOutcome::Unchanged);
+            }
+
+            match mode {
+                WriteMode::OutputSql => {
+                    println!(
+                        "{}",
+                        sql_script::update_rate(
+                            &get_table_name("exchange_rates")?,
+                            &exchange_rate.id,
+                            rate,
+                            raw_rate,
+                            nominal,
+                            fetched_at,
+                            feed_date
+                        )
+                    );
+                    return Ok(WriteOutcome::Updated);
+                }
+                WriteMode::DryRun => {
+                    println!(
+                        "{} {} -> {}: {} -> {}",
+                        date, from_currency, to_currency, exchange_rate.rate, rate
+                    );
+                    return Ok(WriteOutcome::Updated);
+                }
+                WriteMode::Execute => {}
+            }
+
+            // Условие повторяется в UPDATE на случай параллельной записи после SELECT
+            let result = sqlx::query(&format!(
+                r#"
+                    UPDATE {exchange_rates}
+                    SET rate = $1, raw_rate = $2, fetched_at = COALESCE($4, fetched_at), nominal = $5, feed_date = COALESCE($6, feed_date), updated_at = NOW()
+                    WHERE id = $3
+                        AND (fetched_at IS NULL OR $4::timestamptz IS NULL OR fetched_at <= $4)
+                "#,
+                exchange_rates = get_table_name("exchange_rates")?,
+            ))
+            .bind(rate)
+            .bind(raw_rate)
+            .bind(exchange_rate.id)
+            .bind(fetched_at)
+            .bind(nominal as i32)
+            .bind(feed_date)
+            .execute(pool)
+            .await?;
+
+            if result.rows_affected() == 0 {
+                log::warn!(
+                    "Keeping {} -> {} at {}: a newer fetch was stored meanwhile",
+                    from_currency,
+                    to_currency,
+                    date
+                );
+                return Ok(WriteOutcome::Unchanged);
+            }
+
+            log::info!(
+                "Exchange rate updated: {} -> {} at {} = {}",
+                from_currency,
+                to_currency,
+                date,
+                rate
+            );
+
+            secondary::mirror_rate(
+                from_currency,
+                to_currency,
+                rate,
+                raw_rate,
+                nominal,
+                convention,
+                date,
+                &get_effective_at(date)?,
+                get_source(from_currency, to_currency)?,
+                fetched_at,
+                feed_date,
+            )
+            .await;
+
+            #[cfg(feature = "kafka")]
+            kafka::publish(
+                from_currency,
+                to_currency,
+                rate,
+                date,
+                WriteOutcome::Updated,
+            );
+
+            return Ok(WriteOutcome::Updated);
+        }
+
+        Ok(WriteOutcome::Unchanged)
+    } else {
+        let effective_at = get_effective_at(date)?;
+        let source = get_source(from_currency, to_currency)?;
+
+        match mode {
+            WriteMode::OutputSql => {
+                println!(
+                    "{}",
+                    sql_script::insert_rate(
+                        &get_table_name("exchange_rates")?,
+                        from_currency,
+                        to_currency,
+                        rate,
+                        raw_rate,
+                        nominal,
+                        convention,
+                        date,
+                        &effective_at,
+                        source,
+                        fetched_at,
+                        feed_date
+                    )
+                );
+                return Ok(WriteOutcome::Inserted);
+            }
+            WriteMode::DryRun => {
+                println!(
+                    "{} {} -> {}: new {}",
+                    date, from_currency, to_currency, rate
+                );
+                return Ok(WriteOutcome::Inserted);
+            }
+            WriteMode::Execute => {}
+        }
+
+        // Строку пары и даты могли вставить после SELECT, тогда она обновляется при том же
+        // условии, что и в UPDATE выше; xmax = 0 только у вставленной строки
+        let inserted: Option<bool> = sqlx::query_scalar(&format!(
+            r#"
+                INSERT INTO {exchange_rates} AS exchange_rates (from_currency, to_currency, rate, raw_rate, nominal, quote_convention, date, effective_at, source, fetched_at, feed_date, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NOW(), NOW())
+                ON CONFLICT (from_currency, to_currency, date) DO UPDATE
+                SET rate = EXCLUDED.rate, raw_rate = EXCLUDED.raw_rate, nominal = EXCLUDED.nominal, quote_convention = EXCLUDED.quote_convention, effective_at = EXCLUDED.effective_at, source = EXCLUDED.source, fetched_at = COALESCE(EXCLUDED.fetched_at, exchange_rates.fetched_at), feed_date = COALESCE(EXCLUDED.feed_date, exchange_rates.feed_date), updated_at = NOW()
+                WHERE exchange_rates.rate <> EXCLUDED.rate
+                    AND (exchange_rates.fetched_at IS NULL OR EXCLUDED.fetched_at IS NULL OR exchange_rates.fetched_at <= EXCLUDED.fetched_at)
+                RETURNING xmax = 0
+            "#,
+            exchange_rates = get_table_name("exchange_rates")?,
+        ))
+        .bind(from_currency)
+        .bind(to_currency)
+        .bind(rate)
+        .bind(raw_rate)
+        .bind(nominal as i32)
+        .bind(convention.as_str())
+        .bind(date)
+        .bind(effective_at)
+        .bind(source)
+        .bind(fetched_at)
+        .bind(feed_date)
+        .fetch_optional(pool)
+        .await?;
+
+        let outcome = match inserted {
+            Some(true) => {
+                log::info!(
+                    "Exchange rate added: {} -> {} at {} = {}",
+                    from_currency,
+                    to_currency,
+                    date,
+                    rate
+                );
+                WriteOutcome::Inserted
+            }
+            Some(false) => {
+                log::info!(
+                    "Exchange rate updated: {} -> {} at {} = {}",
+                    from_currency,
+                    to_currency,
+                    date,
+                    rate
+                );
+                WriteOutcome::Updated
+            }
+            None => {
+                log::info!(
+                    "Keeping {} -> {} at {}: the same or a newer fetch was stored meanwhile",
+                    from_currency,
+                    to_currency,
+                    date
+                );
+                return Ok(WriteOutcome::Unchanged);
+            }
+        };
+
+        secondary::mirror_rate(
+            from_currency,
+            to_currency,
+            rate,
+            raw_rate,
+            nominal,
+            convention,
+            date,
+            &effective_at,
+            source,
+            fetched_at,
+            feed_date,
+        )
+        .await;
+
+        #[cfg(feature = "kafka")]
+        kafka::publish(from_currency, to_currency, rate, date, outcome);
+
+        Ok(outcome)
+    }
+}
+
+/// `basket` for pairs of a `CURRENCY_BASKETS` code, `index` for a `CURRENCY_INDICES` code,
+/// `cbr` for everything else.
+fn get_source(from_currency: &str, to_currency: &str) -> Result<&'static str> {
+    let baskets = get_currency_baskets()?;
+    let indices = get_currency_indices()?;
+
+    if baskets.contains_key(from_currency) || baskets.contains_key(to_currency) {
+        Ok("basket")
+    } else if indices.contains_key(from_currency) || indices.contains_key(to_currency) {
+        Ok("index")
+    } else {
+        Ok("cbr")
+    }
+}
+
+/// Rounds to `RATE_SCALE` places the `RATE_ROUNDING` way and drops trailing zeros, so
+/// `73.50` and `73.5000` are stored the same way. Reverse and cross rates are rounded the
+/// same way as CBR's own.
+fn get_rounded_rate(raw_rate: &Decimal) -> Result<Decimal> {
+    let rate = match get_rate_scale()? {
+        Some(scale) => raw_rate.round_dp_with_strategy(scale, get_rate_rounding()?.strategy()),
+        None => *raw_rate,
+    };
+
+    Ok(rate.normalize())
+}
+
+/// CBR sets rates for a calendar date in Moscow, so a rate takes effect at midnight in
+/// `CBR_TIMEZONE`, with the offset in force on that date: Moscow was UTC+4 in 2011-2014
+/// and in the summers before, as the `effective_at` migration's backfill has it too.
+fn get_effective_at(date: &NaiveDate) -> Result<DateTime<Utc>> {
+    let timezone = get_cbr_timezone()?;
+
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(timezone).earliest())
+        .map(|midnight| midnight.with_timezone(&Utc))
+        .ok_or(anyhow!("Can't get effective time for {}", date))
+}
+
+async fn get_db_pool() -> Result<Pool<Postgres>> {
+    let pool = connect_db().await?;
+    scale_check::check_once(&pool).await?;
+
+    Ok(pool)
+}
+
+/// `get_db_pool` without the scale check, for `--migrate`, which runs before it.
+async fn connect_db() -> Result<Pool<Postgres>> {
+    let connection_string = get_connection_string()?;
+    let retries = get_db_connect_retries()?;
+    let mut attempt = 0;
+    let mut delay_sec = RETRYDELAY_SEC;
+
+    loop {
+        match PgPool::connect(&connection_string).await {
+            Ok(pool) => return Ok(pool),
+
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                let delay = get_retry_delay(delay_sec);
+                log::warn!(
+                    "Can't connect to the database (retry {} of {} in {:.1?}): {}",
+                    attempt,
+                    retries,
+                    delay,
+                    err
+                );
+                tokio::time::sleep(delay).await;
+                delay_sec = next_delay(delay_sec);
+            }
+
+            Err(err) => {
+                return Err(anyhow!(
+                    "Can't connect to the database after {} attempts: {}",
+                    attempt + 1,
+                    err
+                ));
+            }
+        }
+    }
+}
+
+static KEEP_NOMINAL_FOR: OnceLock<Vec<String>> = OnceLock::new();
+
+fn init_keep_nominal_for(currencies: &[String]) {
+    let currencies = currencies
+        .iter()
+        .map(|currency| currency.trim().to_uppercase())
+        .filter(|currency| !currency.is_empty())
+        .collect();
+
+    KEEP_NOMINAL_FOR.set(currencies).ok();
+}
+
+fn get_keep_nominal_for() -> &'static [String] {
+    KEEP_NOMINAL_FOR
+        .get()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+static RETRY_JITTER: OnceLock<Option<Mutex<StdRng>>> = OnceLock::new();
+
+/// `enabled = false` (`--no-jitter`) keeps the plain backoff; `RETRY_JITTER_SEED` makes
+/// the jitter sequence reproducible.
+fn init_retry_jitter(enabled: bool) -> Result<()> {
+    let rng = if enabled {
+        Some(Mutex::new(match get_retry_jitter_seed()? {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        }))
+    } else {
+        None
+    };
+
+    RETRY_JITTER.get_or_init(|| rng);
+
+    Ok(())
+}
+
+/// Full jitter: a random wait between 0 and `delay_sec`, so that instances failing at the
+/// same time don't retry CBR or the database in lockstep.
+fn get_retry_delay(delay_sec: u64) -> Duration {
+    let delay = Duration::from_secs(delay_sec);
+
+    match RETRY_JITTER.get_or_init(|| None) {
+        Some(rng) => {
+            let mut rng = rng.lock().unwrap_or_else(|err| err.into_inner());
+            delay.mul_f64(rng.random_range(0.0..=1.0))
+        }
+        None => delay,
+    }
+}
+
+fn next_delay(value: u64) -> u64 {
+    let phi = (1.0 + 5.0_f64.sqrt()) / 2.0;
+    (phi * (value as f64)).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Env, Feed, FeedServer, TestDb, ingest_vars};
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    fn decimal(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn ingest_args(args: &[&str]) -> IngestArgs {
+        match Cli::try_parse_from([&["valut", "ingest"], args].concat())
+            .unwrap()
+            .command
+        {
+            Some(Command::Ingest(args)) => args,
+            command => panic!("Parsed {:?}", command),
+        }
+    }
+
+    fn run_options() -> RunOptions {
+        RunOptions {
+            mode: WriteMode::Execute,
+            wait_for_lock: false,
+            reason: None,
+            maintain_wide: false,
+            progress: false,
+            fetch_names: FetchNames::Feed,
+            fail_on_missing: false,
+            skip_complete: false,
+            bulk_source: false,
+            cache_dir: None,
+            job: None,
+            resume: false,
+            order: DateOrder::Desc,
+        }
+    }
+
+    async fn get_stored_dates(pool: &PgPool) -> Vec<NaiveDate> {
+        sqlx::query_scalar("SELECT DISTINCT date FROM exchange_rates ORDER BY date")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn only_commands_that_write_migrate() {
+        let writes_to_db = |args: &[&str]| {
+            Cli::try_parse_from([&["valut"], args].concat())
+                .unwrap()
+                .writes_to_db()
+        };
+
+        assert!(writes_to_db(&[]));
+        assert!(writes_to_db(&["ingest", "--date", "2024-03-01"]));
+        assert!(writes_to_db(&["import", "--file", "rates.csv"]));
+        assert!(!writes_to_db(&["config-check"]));
+        assert!(!writes_to_db(&["sample"]));
+        assert!(!writes_to_db(&["dump-feed"]));
+        assert!(!writes_to_db(&["schema-check"]));
+    }
+
+    #[test]
+    fn reverse_rate_of_zero_names_pair_and_date() {
+        let err = get_reverse_rate(&Decimal::ZERO, "RUB", "USD", &date("2024-03-01")).unwrap_err();
+
+        assert!(
+            err.to_string().contains("RUB -> USD at 2024-03-01"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn reverse_rate_of_extreme_rates() {
+        let date = date("2024-03-01");
+
+        assert_eq!(
+            get_reverse_rate(&decimal("0.000001"), "RUB", "IRR", &date).unwrap(),
+            decimal("1000000")
+        );
+        assert_eq!(
+            get_reverse_rate(&decimal("1000000"), "RUB", "XAU", &date).unwrap(),
+            decimal("0.000001")
+        );
+        // Самый маленький Decimal ещё обращается без переполнения
+        assert_eq!(
+            get_reverse_rate(&Decimal::new(1, 28), "RUB", "XXX", &date).unwrap(),
+            Decimal::from_i128_with_scale(10_i128.pow(28), 0)
+        );
+    }
+
+    #[test]
+    fn cross_rate_overflow_and_zero_are_errors() {
+        let date = date("2024-03-01");
+
+        let err =
+            get_cross_rate(&Decimal::MAX, &Decimal::new(1, 28), "AAA", "BBB", &date).unwrap_err();
+        assert!(
+            err.to_string().contains("AAA -> BBB at 2024-03-01"),
+            "{}",
+            err
+        );
+
+        let err = get_cross_rate(&decimal("90"), &Decimal::ZERO, "USD", "EUR", &date).unwrap_err();
+        assert!(
+            err.to_string().contains("USD -> EUR at 2024-03-01"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn cross_rates_fail_on_the_first_undefined_pair() {
+        let base_rates = vec![
+            ("USD".to_string(), decimal("90")),
+            ("ZZZ".to_string(), Decimal::ZERO),
+        ];
+
+        assert!(get_cross_rates(&date("2024-03-01"), &base_rates).is_err());
+    }
+
+    #[test]
+    fn range_dates_are_newest_first_and_include_the_weekend() {
+        // Пятница — понедельник: суббота и воскресенье запрашиваются наравне с рабочими днями
+        assert_eq!(
+            get_range_dates(date("2024-03-01"), date("2024-03-04")).unwrap(),
+            vec![
+                date("2024-03-04"),
+                date("2024-03-03"),
+                date("2024-03-02"),
+                date("2024-03-01"),
+            ]
+        );
+    }
+
+    #[test]
+    fn order_sets_the_direction_of_ingest_dates() {
+        let range = ["--start", "2024-03-01", "--end", "2024-03-03"];
+        let today = Some(date("2024-03-05"));
+        let newest_first = vec![date("2024-03-03"), date("2024-03-02"), date("2024-03-01")];
+        let oldest_first = vec![date("2024-03-01"), date("2024-03-02"), date("2024-03-03")];
+
+        assert_eq!(
+            get_ingest_dates(&ingest_args(&range), today).unwrap(),
+            newest_first
+        );
+        assert_eq!(
+            get_ingest_dates(
+                &ingest_args(&[&range[..], &["--order", "desc"]].concat()),
+                today
+            )
+            .unwrap(),
+            newest_first
+        );
+        assert_eq!(
+            get_ingest_dates(
+                &ingest_args(&[&range[..], &["--order", "asc"]].concat()),
+                today
+            )
+            .unwrap(),
+            oldest_first
+        );
+        assert_eq!(
+            get_ingest_dates(
+                &ingest_args(&["--dates", "2024-03-02,2024-03-01", "--order", "asc"]),
+                today
+            )
+            .unwrap(),
+            [date("2024-03-01"), date("2024-03-02")]
+        );
+    }
+
+    #[test]
+    fn single_day_range_is_that_date() {
+        assert_eq!(
+            get_range_dates(date("2024-02-29"), date("2024-02-29")).unwrap(),
+            vec![date("2024-02-29")]
+        );
+    }
+
+    #[test]
+    fn range_with_start_after_end_fails() {
+        assert!(get_range_dates(date("2024-03-02"), date("2024-03-01")).is_err());
+    }
+
+    #[test]
+    fn effective_at_follows_moscow_offset_of_the_date() {
+        let _env = Env::set_blocking(&[("CBR_TIMEZONE", None)]);
+        let effective_at = |value| get_effective_at(&date(value)).unwrap().to_rfc3339();
+
+        assert_eq!(effective_at("2024-03-01"), "2024-02-29T21:00:00+00:00");
+        // UTC+4 круглый год
+        assert_eq!(effective_at("2012-06-01"), "2012-05-31T20:00:00+00:00");
+        // Летнее время до 2011 года
+        assert_eq!(effective_at("2010-07-01"), "2010-06-30T20:00:00+00:00");
+        assert_eq!(effective_at("2010-01-15"), "2010-01-14T21:00:00+00:00");
+    }
+
+    #[test]
+    fn effective_at_uses_cbr_timezone() {
+        let _env = Env::set_blocking(&[("CBR_TIMEZONE", Some("UTC"))]);
+
+        assert_eq!(
+            get_effective_at(&date("2024-03-01")).unwrap().to_rfc3339(),
+            "2024-03-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn basket_and_index_overflow_is_none() {
+        let components = vec![("USD".to_string(), decimal("2"))];
+        let huge = vec![("USD".to_string(), Decimal::MAX)];
+        let zero = vec![("USD".to_string(), Decimal::ZERO)];
+
+        assert_eq!(get_basket_rate(&components, &huge), None);
+        assert_eq!(get_index_rate(&components, &zero, &huge), None);
+        assert_eq!(
+            get_index_rate(&components, &huge, &zero),
+            Some(Decimal::ZERO)
+        );
+    }
+
+    #[tokio::test]
+    async fn url_zero_pads_the_date() {
+        let _env = Env::set(&[("CBR_BASE_URL", None)]).await;
+
+        for (value, url) in [
+            (
+                "2024-01-05",
+                "https://cbr.ru/scripts/XML_daily.asp?date_req=05/01/2024",
+            ),
+            (
+                "2024-02-29",
+                "https://cbr.ru/scripts/XML_daily.asp?date_req=29/02/2024",
+            ),
+            (
+                "2023-12-31",
+                "https://cbr.ru/scripts/XML_daily.asp?date_req=31/12/2023",
+            ),
+            (
+                "1999-09-09",
+                "https://cbr.ru/scripts/XML_daily.asp?date_req=09/09/1999",
+            ),
+        ] {
+            assert_eq!(get_url(date(value), CbrLang::Ru).await.unwrap(), url);
+        }
+    }
+
+    #[tokio::test]
+    async fn url_follows_cbr_lang() {
+        let _env = Env::set(&[("CBR_BASE_URL", None), ("CBR_LANG", Some("en"))]).await;
+
+        assert_eq!(
+            get_url(date("2024-03-01"), get_cbr_lang().unwrap())
+                .await
+                .unwrap(),
+            "https://cbr.ru/scripts/XML_daily_eng.asp?date_req=01/03/2024"
+        );
+    }
+
+    #[tokio::test]
+    async fn url_joins_a_file_base() {
+        let _env = Env::set(&[("CBR_BASE_URL", Some("file:///var/lib/valut/feeds"))]).await;
+
+        assert_eq!(
+            get_url(date("2024-03-01"), CbrLang::Ru).await.unwrap(),
+            "file:///var/lib/valut/feeds/XML_daily.asp?date_req=01/03/2024"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn ingest_of_one_date_fetches_and_stores_only_it() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![
+            Feed::fixture("2024-03-01"),
+            Feed::fixture("2024-03-02"),
+        ])
+        .await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+
+        let args = ingest_args(&["--date", "2024-03-01"]);
+        let today = Some(date("2024-03-05"));
+        assert_eq!(
+            get_ingest_dates(&args, today).unwrap(),
+            [date("2024-03-01")]
+        );
+
+        let summary = ingest_dates(&args, today, &run_options()).await.unwrap();
+
+        assert_eq!(feeds.requests(), 1);
+        assert_eq!(get_stored_dates(&db.pool).await, [date("2024-03-01")]);
+        assert_eq!(summary.errors, 0);
+        assert!(summary.inserted > 0);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn trailing_zeros_of_a_rate_are_not_an_update() {
+        let db = TestDb::start().await;
+        let args = ingest_args(&["--date", "2024-03-01"]);
+        let today = Some(date("2024-03-05"));
+
+        for (value, inserted, unchanged) in [("73,5000", 2, 0), ("73,50", 0, 2)] {
+            let feeds =
+                FeedServer::start(vec![Feed::rates("2024-03-01", &[("USD", "1", value)])]).await;
+            let _env = Env::set(&ingest_vars(&db, &feeds, "USD")).await;
+
+            let summary = ingest_dates(&args, today, &run_options()).await.unwrap();
+
+            assert_eq!(
+                (summary.inserted, summary.updated, summary.unchanged),
+                (inserted, 0, unchanged),
+                "{}",
+                value
+            );
+        }
+
+        let rate: Decimal = sqlx::query_scalar(
+            "SELECT rate FROM exchange_rates WHERE from_currency = 'USD' AND to_currency = 'RUB'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(rate.to_string(), "73.5");
+
+        db.close().await;
+    }
+
+    #[test]
+    fn rounded_rate_drops_trailing_zeros() {
+        let _env = Env::set_blocking(&[("RATE_SCALE", None), ("RATE_ROUNDING", None)]);
+
+        assert_eq!(
+            get_rounded_rate(&decimal("73.5000")).unwrap().to_string(),
+            "73.5"
+        );
+        assert_eq!(
+            get_rounded_rate(&decimal("73.5000")).unwrap(),
+            get_rounded_rate(&decimal("73.50")).unwrap()
+        );
+    }
+
+    #[test]
+    fn rate_rounding_modes_differ_at_midpoints() {
+        for (rounding, expected) in [
+            (None, ["1.234", "1.236"]),
+            (Some("half_even"), ["1.234", "1.236"]),
+            (Some("half_up"), ["1.235", "1.236"]),
+            (Some("truncate"), ["1.234", "1.235"]),
+        ] {
+            let _env = Env::set_blocking(&[("RATE_SCALE", Some("3")), ("RATE_ROUNDING", rounding)]);
+
+            assert_eq!(
+                ["1.2345", "1.2355"]
+                    .map(|value| get_rounded_rate(&decimal(value)).unwrap().to_string()),
+                expected,
+                "{:?}",
+                rounding
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn unchanged_rerun_writes_nothing() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![Feed::fixture("2024-03-01")]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR,CNY")).await;
+        let args = ingest_args(&["--date", "2024-03-01"]);
+        let today = Some(date("2024-03-05"));
+        // xmin меняется при любом UPDATE строки, даже с теми же значениями
+        let get_versions = || {
+            sqlx::query_scalar::<_, String>(
+                "SELECT id::text || ':' || xmin::text FROM exchange_rates ORDER BY id",
+            )
+            .fetch_all(&db.pool)
+        };
+
+        let first = ingest_dates(&args, today, &run_options()).await.unwrap();
+        let versions = get_versions().await.unwrap();
+        let second = ingest_dates(&args, today, &run_options()).await.unwrap();
+
+        assert!(first.inserted > 0);
+        assert_eq!(
+            (second.inserted, second.updated, second.unchanged),
+            (0, 0, first.inserted)
+        );
+        assert_eq!(get_versions().await.unwrap(), versions);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn raw_rate_keeps_the_scale_of_the_feed() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![Feed::rates(
+            "2024-03-01",
+            &[
+                ("USD", "1", "90,8000"),
+                ("EUR", "1", "0,00002345678901234567"),
+            ],
+        )])
+        .await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+
+        ingest_dates(
+            &ingest_args(&["--date", "2024-03-01"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            r#"
+                SELECT from_currency, raw_rate::text, rate::text
+                FROM exchange_rates
+                WHERE to_currency = 'RUB'
+                ORDER BY from_currency
+            "#,
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                (
+                    "EUR".to_string(),
+                    "0.00002345678901234567".to_string(),
+                    "0.00002345678901234567".to_string()
+                ),
+                ("USD".to_string(), "90.8000".to_string(), "90.8".to_string()),
+            ]
+        );
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn partial_feed_fails_only_with_fail_on_missing() {
+        let db = TestDb::start().await;
+        let feeds =
+            FeedServer::start(vec![Feed::rates("2024-03-01", &[("USD", "1", "90,8423")])]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+        let today = Some(date("2024-03-05"));
+        let fail_on_missing = RunOptions {
+            fail_on_missing: true,
+            ..run_options()
+        };
+
+        // 2024-03-02 сервер не отдаёт: эта дата, загружаемая первой, пропускается и с
+        // --fail-on-missing, а неполный фид 2024-03-01 — нет
+        let err = ingest_dates(
+            &ingest_args(&["--dates", "2024-03-01,2024-03-02"]),
+            today,
+            &fail_on_missing,
+        )
+        .await
+        .unwrap_err();
+        let partial_feed = err.downcast_ref::<PartialFeed>().unwrap();
+        assert_eq!(
+            (
+                partial_feed.date,
+                partial_feed.currency.as_str(),
+                partial_feed.present
+            ),
+            (date("2024-03-01"), "EUR", 1)
+        );
+        assert!(get_stored_dates(&db.pool).await.is_empty());
+
+        let summary = ingest_dates(
+            &ingest_args(&["--dates", "2024-03-01,2024-03-02"]),
+            today,
+            &run_options(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.currencies["EUR"].errors, 1);
+        assert_eq!(summary.currencies["USD"].inserted, 2);
+        assert_eq!(get_stored_dates(&db.pool).await, [date("2024-03-01")]);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn pool_timeout_is_retried_only_when_writing() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![]).await;
+        let mut vars = ingest_vars(&db, &feeds, "USD");
+        vars.push(("DB_ACQUIRE_RETRIES", Some("1")));
+        let _env = Env::set(&vars).await;
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(200))
+            .connect(&db.url)
+            .await
+            .unwrap();
+        let currency_cache = CurrencyCache::load(&pool).await.unwrap();
+        let val_curs: ValCurs =
+            quick_xml::de::from_str(&Feed::rates("2024-03-01", &[("USD", "1", "90,8423")]).body)
+                .unwrap();
+        let exchange_rates = HashMap::from([("USD".to_string(), decimal("90.8423"))]);
+        let fetched_at = Utc::now();
+        let currencies = vec!["USD".to_string()];
+        let store = |mode| {
+            store_val_curs(
+                date("2024-03-01"),
+                &val_curs,
+                &exchange_rates,
+                &fetched_at,
+                &pool,
+                &currencies,
+                &[],
+                &currency_cache,
+                mode,
+            )
+        };
+
+        // Единственное соединение пула занято, --dry-run сразу не дожидается его
+        let connection = pool.acquire().await.unwrap();
+        let started = std::time::Instant::now();
+        let err = store(WriteMode::DryRun).await.unwrap_err();
+        assert!(is_pool_timeout(&err), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(RETRYDELAY_SEC));
+
+        // Запись повторяется через RETRYDELAY_SEC, соединение к тому времени свободно
+        let release = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(connection);
+        };
+        let (summary, ()) = tokio::join!(store(WriteMode::Execute), release);
+        assert_eq!(summary.unwrap().inserted, 2);
+
+        pool.close().await;
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn feed_date_is_the_date_of_the_feed() {
+        let db = TestDb::start().await;
+        // В воскресенье ЦБ отдаёт курсы, установленные в субботу
+        let saturday = Feed::rates("2024-03-02", &[("USD", "1", "90,8423")]);
+        let feeds = FeedServer::start(vec![Feed::xml("2024-03-03", &saturday.body)]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD")).await;
+
+        ingest_dates(
+            &ingest_args(&["--date", "2024-03-03"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+
+        let dates: Vec<(NaiveDate, Option<NaiveDate>)> =
+            sqlx::query_as("SELECT DISTINCT date, feed_date FROM exchange_rates")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(dates, [(date("2024-03-03"), Some(date("2024-03-02")))]);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn import_over_an_ingested_rate_keeps_its_fetch() {
+        let db = TestDb::start().await;
+        let saturday = Feed::rates("2024-03-02", &[("USD", "1", "90,8423")]);
+        let feeds = FeedServer::start(vec![Feed::xml("2024-03-03", &saturday.body)]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD")).await;
+        let get_usd_rub = || {
+            sqlx::query_as::<_, (Decimal, Option<DateTime<Utc>>, Option<NaiveDate>)>(
+                "SELECT rate, fetched_at, feed_date FROM exchange_rates WHERE from_currency = 'USD' AND to_currency = 'RUB'",
+            )
+            .fetch_one(&db.pool)
+        };
+
+        ingest_dates(
+            &ingest_args(&["--date", "2024-03-03"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+        let (_, fetched_at, feed_date) = get_usd_rub().await.unwrap();
+        assert!(fetched_at.is_some());
+
+        let file = std::env::temp_dir().join(format!("valut-import-{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "from,to,rate,date\nUSD,RUB,91.5,2024-03-03\n").unwrap();
+        let imported = import::import(cli::ImportArgs {
+            file: file.clone(),
+            dry_run: false,
+            resume: false,
+            wait_for_lock: false,
+        })
+        .await;
+        std::fs::remove_file(&file).unwrap();
+        imported.unwrap();
+
+        assert_eq!(
+            get_usd_rub().await.unwrap(),
+            (decimal("91.5"), fetched_at, feed_date)
+        );
+        assert_eq!(feed_date, Some(date("2024-03-02")));
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn import_and_recompute_cross_take_the_ingest_lock() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+        let file = std::env::temp_dir().join(format!("valut-import-{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "USD,RUB,91.5,2024-03-01\nEUR,RUB,99.5,2024-03-01\n").unwrap();
+        let import = |wait_for_lock| {
+            import::import(cli::ImportArgs {
+                file: file.clone(),
+                dry_run: false,
+                resume: false,
+                wait_for_lock,
+            })
+        };
+        let recompute = |wait_for_lock| {
+            recompute_cross(RecomputeCrossArgs {
+                start: date("2024-03-01"),
+                end: date("2024-03-01"),
+                wait_for_lock,
+            })
+        };
+
+        let lock = lock_ingest(&db.pool, false).await.unwrap();
+        let imported = import(false).await;
+        let recomputed = recompute(false).await;
+        for result in [&imported, &recomputed] {
+            let err = result.as_ref().unwrap_err();
+            assert!(err.is::<IngestLocked>(), "{}", err);
+        }
+
+        // С --wait-for-lock запись дожидается, пока замок отпустят
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            lock.close().await.unwrap();
+        };
+        let (imported, ()) = tokio::join!(import(true), release);
+        let imported = imported.and(recompute(true).await);
+        std::fs::remove_file(&file).unwrap();
+        imported.unwrap();
+
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM exchange_rates")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 4);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn concurrent_ingests_leave_one_row_per_pair() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![
+            Feed::fixture("2024-03-01"),
+            Feed::fixture("2024-03-02"),
+        ])
+        .await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR,CNY")).await;
+        let dates = [date("2024-03-01")];
+        let count_pairs = || {
+            sqlx::query_as::<_, (i64, i64)>(
+                r#"
+                    SELECT count(*), count(DISTINCT (from_currency, to_currency, date))
+                    FROM exchange_rates
+                "#,
+            )
+            .fetch_one(&db.pool)
+        };
+
+        // Без ожидания второй запуск либо упирается в замок, либо начинается после первого
+        let no_wait = run_options();
+        let (first, second) =
+            tokio::join!(store_dates(&dates, &no_wait), store_dates(&dates, &no_wait));
+        let results = [first, second];
+        assert!(results.iter().any(Result::is_ok));
+        for result in &results {
+            if let Err(err) = result {
+                assert!(err.is::<IngestLocked>(), "{}", err);
+            }
+        }
+        assert_eq!(count_pairs().await.unwrap(), (12, 12));
+
+        let wait = RunOptions {
+            wait_for_lock: true,
+            ..run_options()
+        };
+        let (first, second) = tokio::join!(store_dates(&dates, &wait), store_dates(&dates, &wait));
+        let summaries = [first.unwrap(), second.unwrap()];
+        assert!(summaries.iter().all(|summary| summary.inserted == 0));
+        assert_eq!(count_pairs().await.unwrap(), (12, 12));
+
+        // Запись мимо замка вставляет те же пары той же даты, что и ingest; дублей не даёт
+        // уникальный индекс
+        let dates = [date("2024-03-02")];
+        let write_unlocked = async {
+            for (from_currency, to_currency) in [
+                ("USD", "RUB"),
+                ("RUB", "USD"),
+                ("EUR", "USD"),
+                ("USD", "EUR"),
+            ] {
+                set_exchange_rate(
+                    &dates[0],
+                    &from_currency.to_string(),
+                    &to_currency.to_string(),
+                    &decimal("1.5"),
+                    1,
+                    QuoteConvention::of(from_currency, to_currency),
+                    None,
+                    None,
+                    &db.pool,
+                    WriteMode::Execute,
+                )
+                .await?;
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+        let (ingested, written) = tokio::join!(store_dates(&dates, &no_wait), write_unlocked);
+        ingested.unwrap();
+        written.unwrap();
+        assert_eq!(count_pairs().await.unwrap(), (24, 24));
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn deprecated_currency_is_skipped_after_its_last_date() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![
+            Feed::rates(
+                "2024-02-29",
+                &[("USD", "1", "90,8423"), ("EUR", "1", "98,2615")],
+            ),
+            Feed::rates("2024-03-01", &[("USD", "1", "90,8423")]),
+        ])
+        .await;
+        let mut vars = ingest_vars(&db, &feeds, "USD,EUR");
+        vars.push(("CURRENCY_AVAILABLE_UNTIL", Some("EUR:2024-02-29")));
+        let _env = Env::set(&vars).await;
+
+        let summary = ingest_dates(
+            &ingest_args(&["--start", "2024-02-29", "--end", "2024-03-01"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+
+        // Отсутствие EUR в фиде после даты вывода — не ошибка
+        assert_eq!(summary.errors, 0);
+        let rows: Vec<(NaiveDate, String, String)> = sqlx::query_as(
+            r#"
+                SELECT date, from_currency, to_currency
+                FROM exchange_rates
+                WHERE 'EUR' IN (from_currency, to_currency)
+                ORDER BY date, from_currency, to_currency
+            "#,
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert!(
+            rows.iter()
+                .all(|(stored_date, _, _)| *stored_date == date("2024-02-29"))
+        );
+        assert_eq!(rows.len(), 4);
+        assert_eq!(get_stored_dates(&db.pool).await.len(), 2);
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn rub_among_the_currencies_is_skipped() {
+        let db = TestDb::start().await;
+        let feeds =
+            FeedServer::start(vec![Feed::rates("2024-03-01", &[("USD", "1", "90,8423")])]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,RUB")).await;
+
+        assert_eq!(get_currencies().unwrap(), ["USD"]);
+
+        let summary = ingest_dates(
+            &ingest_args(&["--date", "2024-03-01"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((summary.inserted, summary.errors), (2, 0));
+        assert!(!summary.currencies.contains_key("RUB"));
+        let pairs: Vec<(String, String)> = sqlx::query_as(
+            "SELECT from_currency, to_currency FROM exchange_rates ORDER BY from_currency",
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            pairs,
+            [
+                ("RUB".to_string(), "USD".to_string()),
+                ("USD".to_string(), "RUB".to_string()),
+            ]
+        );
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn resume_refuses_another_order() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![
+            Feed::fixture("2024-03-01"),
+            Feed::fixture("2024-03-02"),
+        ])
+        .await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD")).await;
+        let today = Some(date("2024-03-05"));
+        let ingest = |order: &'static str| {
+            let options = RunOptions {
+                job: Some("history".to_string()),
+                resume: true,
+                order: DateOrder::from_str(order, false).unwrap(),
+                ..run_options()
+            };
+            let args = ingest_args(&[
+                "--start",
+                "2024-03-01",
+                "--end",
+                "2024-03-02",
+                "--order",
+                order,
+            ]);
+            async move { ingest_dates(&args, today, &options).await }
+        };
+
+        ingest("asc").await.unwrap();
+        let date_order: Option<String> =
+            sqlx::query_scalar("SELECT date_order FROM backfill_progress WHERE job = 'history'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(date_order.as_deref(), Some("asc"));
+
+        assert_eq!(
+            ingest("desc").await.unwrap_err().to_string(),
+            "Job history ran with --order asc, can't resume it with --order desc"
+        );
+        assert_eq!(ingest("asc").await.unwrap().inserted, 0);
+
+        sqlx::query("UPDATE backfill_progress SET date_order = NULL")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            ingest("asc").await.unwrap_err().to_string(),
+            "Job history was stored without its --order, can't resume it; run it without --resume"
+        );
+
+        db.close().await;
+    }
+
+    #[test]
+    fn empty_feed_fails_the_date() {
+        let val_curs = ValCurs {
+            date: Some("01.01.2100".to_string()),
+            valute: vec![],
+        };
+
+        assert_eq!(
+            check_val_curs(date("2100-01-01"), &val_curs)
+                .unwrap_err()
+                .to_string(),
+            "Invalid CBR feed at 2100-01-01: Empty feed: no Valute elements"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn refresh_pair_writes_only_that_pair() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![Feed::fixture("2024-03-01")]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR,CNY")).await;
+
+        let summary = refresh_pair(&db.pool, "cbr", date("2024-03-01"), "USD", "EUR")
+            .await
+            .unwrap();
+
+        assert_eq!((summary.inserted, summary.updated), (1, 0));
+        assert_eq!(summary.currencies["USD"].inserted, 1);
+
+        let rows: Vec<(String, String, Decimal)> =
+            sqlx::query_as("SELECT from_currency, to_currency, rate FROM exchange_rates")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            rows,
+            [(
+                "USD".to_string(),
+                "EUR".to_string(),
+                get_rounded_rate(&(decimal("90.8423") / decimal("98.2615"))).unwrap()
+            )]
+        );
+
+        let err = refresh_pair(&db.pool, "ecb", date("2024-03-01"), "USD", "EUR")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown source ecb, expected cbr");
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn rates_per_nominal_are_read_per_unit() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![]).await;
+        let mut vars = ingest_vars(&db, &feeds, "HUF");
+        vars.push(("RATE_SCALE", Some("4")));
+        let _env = Env::set(&vars).await;
+        let date = date("2024-03-01");
+        // RATE_SCALE=4 срезал бы курс за единицу до 0.2498, за 100 форинтов он хранится точно
+        let nominal_rates = HashMap::from([(
+            "HUF".to_string(),
+            NominalRate {
+                char_code: "HUF".to_string(),
+                value: decimal("24.9786"),
+                nominal: 100,
+            },
+        )]);
+
+        update_stored_exchange_rates(
+            &date,
+            &HashMap::from([("HUF".to_string(), decimal("0.249786"))]),
+            &nominal_rates,
+            &HashMap::new(),
+            &Utc::now(),
+            Some(date),
+            &db.pool,
+            &vec!["HUF".to_string()],
+            &[],
+            WriteMode::Execute,
+        )
+        .await
+        .unwrap();
+        wide::refresh(&db.pool, &[date], &["HUF".to_string()])
+            .await
+            .unwrap();
+
+        let rows: Vec<(String, Decimal, i32)> = sqlx::query_as(
+            "SELECT from_currency, rate, nominal FROM exchange_rates ORDER BY from_currency",
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("HUF".to_string(), decimal("24.9786"), 100),
+                ("RUB".to_string(), decimal("0.04"), 100),
+            ]
+        );
+
+        let wide_rate: Decimal =
+            sqlx::query_scalar("SELECT huf_rub FROM exchange_rates_wide WHERE date = $1")
+                .bind(date)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(wide_rate.normalize(), decimal("0.249786"));
+
+        let per_unit_rates: Vec<Decimal> = sqlx::query_scalar(&format!(
+            "SELECT {} FROM exchange_rates ORDER BY from_currency",
+            exchange_rate::PER_UNIT_RATE_SQL
+        ))
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            per_unit_rates
+                .into_iter()
+                .map(|rate| rate.normalize())
+                .collect::<Vec<_>>(),
+            [decimal("0.249786"), decimal("4")]
+        );
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    async fn cbr_quote_is_stored_as_currency_to_rub() {
+        let val_curs: ValCurs = quick_xml::de::from_str(
+            &std::fs::read_to_string("golden/feeds/2024-03-01.xml").unwrap(),
+        )
+        .unwrap();
+        let date = date("2024-03-01");
+        let exchange_rates = get_curs_map(&val_curs, &HashMap::new(), &["USD".to_string()])
+            .await
+            .unwrap();
+
+        let [
+            (from_currency, to_currency, rate),
+            (reverse_from, reverse_to, reverse_rate),
+        ] = get_rub_pair_rates("USD", &exchange_rates["USD"], &date).unwrap();
+
+        // Перевёрнутая пара дала бы USD -> RUB около 0.011
+        assert_eq!(
+            (from_currency.as_str(), to_currency.as_str()),
+            ("USD", "RUB")
+        );
+        assert!(
+            (decimal("70")..=decimal("100")).contains(&rate),
+            "USD -> RUB {}",
+            rate
+        );
+        assert_eq!((reverse_from.as_str(), reverse_to.as_str()), ("RUB", "USD"));
+        assert_eq!(reverse_rate, Decimal::ONE / rate);
+    }
+
+    #[tokio::test]
+    async fn rate_out_of_range_skips_only_that_currency() {
+        let feed = Feed::rates(
+            "2024-03-01",
+            &[
+                ("USD", "1", "90,8423"),
+                ("EUR", "1", "99999999999999999999999999999999999999,0"),
+            ],
+        );
+        let val_curs: ValCurs = quick_xml::de::from_str(&feed.body).unwrap();
+
+        let exchange_rates = get_curs_map(
+            &val_curs,
+            &HashMap::new(),
+            &["USD".to_string(), "EUR".to_string()],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            exchange_rates,
+            HashMap::from([("USD".to_string(), decimal("90.8423"))])
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn stored_rates_are_consistent() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![Feed::fixture("2024-03-01")]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+
+        ingest_dates(
+            &ingest_args(&["--date", "2024-03-01"]),
+            Some(date("2024-03-05")),
+            &run_options(),
+        )
+        .await
+        .unwrap();
+
+        let rows: Vec<(String, String, Decimal, String, NaiveDate)> = sqlx::query_as(
+            "SELECT from_currency, to_currency, rate, source, date FROM exchange_rates",
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        let rates: HashMap<(String, String), Decimal> = rows
+            .iter()
+            .map(|(from_currency, to_currency, rate, _, _)| {
+                ((from_currency.clone(), to_currency.clone()), *rate)
+            })
+            .collect();
+        let rate = |from_currency: &str, to_currency: &str| {
+            rates[&(from_currency.to_string(), to_currency.to_string())]
+        };
+
+        assert_eq!(rows.len(), 6);
+        for (from_currency, to_currency, _, source, stored_date) in &rows {
+            assert_eq!(
+                (source.as_str(), *stored_date),
+                ("cbr", date("2024-03-01")),
+                "{} -> {}",
+                from_currency,
+                to_currency
+            );
+        }
+        assert_eq!(rate("USD", "RUB"), decimal("90.8423"));
+        assert_eq!(rate("EUR", "RUB"), decimal("98.2615"));
+        for currency in ["USD", "EUR"] {
+            assert_eq!(
+                rate("RUB", currency),
+                get_rounded_rate(&(Decimal::ONE / rate(currency, "RUB"))).unwrap()
+            );
+        }
+        assert_eq!(
+            rate("USD", "EUR"),
+            get_rounded_rate(&(rate("USD", "RUB") / rate("EUR", "RUB"))).unwrap()
+        );
+        assert_eq!(
+            rate("EUR", "USD"),
+            get_rounded_rate(&(rate("EUR", "RUB") / rate("USD", "RUB"))).unwrap()
+        );
+
+        db.close().await;
+    }
+
+    #[tokio::test]
+    async fn rub_in_the_feed_is_not_a_rate() {
+        let feed = Feed::rates(
+            "2024-03-01",
+            &[
+                ("USD", "1", "90,8423"),
+                ("RUB", "1", "1"),
+                ("RUR", "1", "1"),
+            ],
+        );
+        let val_curs: ValCurs = quick_xml::de::from_str(&feed.body).unwrap();
+        let aliases = HashMap::from([("RUR".to_string(), "RUB".to_string())]);
+
+        let exchange_rates =
+            get_curs_map(&val_curs, &aliases, &["USD".to_string(), "RUB".to_string()])
+                .await
+                .unwrap();
+
+        assert_eq!(
+            exchange_rates,
+            HashMap::from([("USD".to_string(), decimal("90.8423"))])
+        );
+    }
+
+    #[test]
+    fn cross_rates_agree_for_a_usd_based_reader() {
+        let date = date("2024-03-01");
+        let base_rates = vec![
+            ("USD".to_string(), decimal("90.8423")),
+            ("EUR".to_string(), decimal("98.2615")),
+            ("GBP".to_string(), decimal("114.9876")),
+        ];
+        let rates: HashMap<(String, String), Decimal> = get_cross_rates(&date, &base_rates)
+            .unwrap()
+            .into_iter()
+            .map(|(from_currency, to_currency, rate)| ((from_currency, to_currency), rate))
+            .collect();
+        let rate = |from_currency: &str, to_currency: &str| {
+            rates[&(from_currency.to_string(), to_currency.to_string())]
+        };
+
+        // Все упорядоченные пары, без пар валюты с собой
+        assert_eq!(rates.len(), 6);
+        assert_eq!(rate("EUR", "GBP"), decimal("98.2615") / decimal("114.9876"));
+        assert_eq!(rate("GBP", "EUR"), decimal("114.9876") / decimal("98.2615"));
+
+        // Читатель с базой USD пересчитывает EUR -> GBP через USD и получает тот же курс
+        let via_usd = rate("EUR", "USD") / rate("GBP", "USD");
+        assert_eq!(via_usd.round_dp(20), rate("EUR", "GBP").round_dp(20));
+        assert_eq!(
+            (rate("EUR", "USD") * rate("USD", "GBP")).round_dp(20),
+            rate("EUR", "GBP").round_dp(20)
+        );
+    }
+}
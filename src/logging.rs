@@ -0,0 +1,177 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{Result, anyhow};
+
+use clap::ValueEnum;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::progress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// Short random ID of this invocation, attached to every log line and run summary.
+pub fn run_id() -> &'static str {
+    RUN_ID.get_or_init(|| Uuid::new_v4().simple().to_string()[..8].to_string())
+}
+
+/// `--log-file` and its rotation settings.
+#[derive(Debug, Clone)]
+pub struct LogFileOptions {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub keep: u32,
+}
+
+pub fn init(format: LogFormat, log_file: Option<LogFileOptions>) -> Result<()> {
+    let run_id = run_id();
+    let mut builder = env_logger::Builder::from_default_env();
+
+    match format {
+        LogFormat::Text => builder.format(move |buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {} run={}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                run_id,
+                record.args()
+            )
+        }),
+
+        LogFormat::Json => builder.format(move |buf, record| {
+            let line = json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "run_id": run_id,
+                "message": record.args().to_string(),
+            });
+
+            writeln!(buf, "{}", line)
+        }),
+    };
+
+    let file = log_file.map(RotatingFile::open).transpose()?;
+
+    builder
+        .target(env_logger::Target::Pipe(Box::new(LogSink { file })))
+        .init();
+
+    Ok(())
+}
+
+/// Writes every line to stderr, through the progress bar, and to the `--log-file` if given,
+/// in the same format.
+struct LogSink {
+    file: Option<RotatingFile>,
+}
+
+impl Write for LogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress::LogWriter.write_all(buf)?;
+
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        progress::LogWriter.flush()?;
+
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends to `path` until a line would take it past `max_bytes`, then renames it to
+/// `path.1`, shifting older files up to `path.{keep}`, and starts a new one. A line
+/// longer than `max_bytes` still goes to a file of its own.
+struct RotatingFile {
+    options: LogFileOptions,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(options: LogFileOptions) -> Result<Self> {
+        let file = open_append(&options.path)
+            .map_err(|err| anyhow!("Can't open log file {}: {}", options.path.display(), err))?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            options,
+            file,
+            size,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.options.keep).rev() {
+            rename_if_exists(
+                &get_rotated_path(&self.options.path, index),
+                &get_rotated_path(&self.options.path, index + 1),
+            )?;
+        }
+
+        rename_if_exists(&self.options.path, &get_rotated_path(&self.options.path, 1))?;
+
+        self.file = open_append(&self.options.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.options.max_bytes {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn get_rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+
+    PathBuf::from(rotated)
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+/// No feed fetched yet, so the gauge is left out rather than reported as 0.
+const UNSET: i64 = i64::MIN;
+
+static FEED_DATE_LAG_DAYS: AtomicI64 = AtomicI64::new(UNSET);
+
+/// Days the `Date` of the last fetched CBR feed is behind the requested date.
+pub fn set_feed_date_lag(days: i64) {
+    FEED_DATE_LAG_DAYS.store(days, Ordering::Relaxed);
+}
+
+/// The gauges in the Prometheus text format, for `/metrics`.
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub fn render() -> String {
+    let mut text = String::new();
+    let lag = FEED_DATE_LAG_DAYS.load(Ordering::Relaxed);
+
+    if lag != UNSET {
+        // Запись в String не падает
+        let _ = writeln!(
+            text,
+            "# HELP valut_feed_date_lag_days Days the Date of the last fetched CBR feed is behind the requested date\n\
+             # TYPE valut_feed_date_lag_days gauge\n\
+             valut_feed_date_lag_days {}",
+            lag
+        );
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lag_is_rendered_once_set() {
+        assert_eq!(render(), "");
+
+        set_feed_date_lag(2);
+
+        assert_eq!(
+            render(),
+            "# HELP valut_feed_date_lag_days Days the Date of the last fetched CBR feed is behind the requested date\n\
+             # TYPE valut_feed_date_lag_days gauge\n\
+             valut_feed_date_lag_days 2\n"
+        );
+    }
+}
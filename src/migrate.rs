@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, anyhow};
+use sqlx::{
+    PgPool,
+    migrate::{Migrate, Migrator},
+};
+
+use crate::config::get_table_prefix;
+
+/// The `migrations` directory, embedded at build time.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies the embedded migrations that the database doesn't have yet, logging each one.
+/// Applied migrations are recorded in `_sqlx_migrations`, the same table sqlx-cli uses, so
+/// running it again, or after `sqlx migrate run`, applies nothing. sqlx holds an advisory
+/// lock while migrating, so containers starting together don't apply a migration twice.
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+    // Миграции создают таблицы без префикса
+    if !get_table_prefix()?.is_empty() {
+        return Err(anyhow!(
+            "--migrate creates unprefixed tables and can't be used with TABLE_PREFIX"
+        ));
+    }
+
+    let applied = get_applied_versions(pool).await?;
+    let pending: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|migration| {
+            !migration.migration_type.is_down_migration() && !applied.contains(&migration.version)
+        })
+        .collect();
+
+    MIGRATOR
+        .run(pool)
+        .await
+        .map_err(|err| anyhow!("Can't apply migrations: {}", err))?;
+
+    if pending.is_empty() {
+        log::info!("Database schema is up to date");
+    }
+
+    for migration in pending {
+        log::info!(
+            "Applied migration {} {}",
+            migration.version,
+            migration.description
+        );
+    }
+
+    Ok(())
+}
+
+async fn get_applied_versions(pool: &PgPool) -> Result<HashSet<i64>> {
+    let mut connection = pool.acquire().await?;
+
+    connection.ensure_migrations_table().await?;
+
+    Ok(connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
@@ -0,0 +1,56 @@
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use chrono::NaiveDate;
+use indicatif::{ProgressBar, ProgressStyle};
+
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Shows a bar of fetched dates on stderr. indicatif hides it when stderr is not a
+/// terminal, so piped logs never see it.
+pub fn start(total: usize) {
+    let bar = ProgressBar::new(total as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} dates, at {msg} ({eta} left)")
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+
+    *lock() = Some(bar);
+}
+
+pub fn advance(date: &NaiveDate) {
+    if let Some(bar) = lock().as_ref() {
+        bar.set_message(date.to_string());
+        bar.inc(1);
+    }
+}
+
+pub fn finish() {
+    if let Some(bar) = lock().take() {
+        bar.finish_and_clear();
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<ProgressBar>> {
+    BAR.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Log sink that clears the bar while a line is written and redraws it after, so log
+/// lines (text or JSON) are never mixed with the bar.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bar = lock().clone();
+
+        match bar {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
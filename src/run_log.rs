@@ -0,0 +1,52 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::config::get_table_name;
+use crate::{WriteSummary, logging};
+
+pub async fn start(pool: &PgPool, dates: &[NaiveDate], reason: Option<&str>) -> Result<Uuid> {
+    let start_date = dates.iter().min().ok_or(anyhow!("No dates to store"))?;
+    let end_date = dates.iter().max().ok_or(anyhow!("No dates to store"))?;
+
+    let (id,): (Uuid,) = sqlx::query_as(&format!(
+        r#"
+            INSERT INTO {run_log} (run_id, reason, start_date, end_date, started_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            RETURNING id
+        "#,
+        run_log = get_table_name("run_log")?,
+    ))
+    .bind(logging::run_id())
+    .bind(reason)
+    .bind(start_date)
+    .bind(end_date)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn finish(pool: &PgPool, id: &Uuid, result: &Result<WriteSummary>) -> Result<()> {
+    let (summary, error) = match result {
+        Ok(summary) => (Some(serde_json::to_string(summary)?), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+
+    sqlx::query(&format!(
+        r#"
+            UPDATE {run_log}
+            SET finished_at = NOW(), summary = $1::jsonb, error = $2
+            WHERE id = $3
+        "#,
+        run_log = get_table_name("run_log")?,
+    ))
+    .bind(summary)
+    .bind(error)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::cli::SampleArgs;
+use crate::config::{get_cbr_lang, get_currencies, get_currency_aliases};
+use crate::val_curs::{ParsedRate, ValCurs};
+use crate::{get_today, get_url, get_val_curs};
+
+/// `--json`: the feed as scripts need it, rates keyed by code after `CURRENCY_ALIASES`.
+#[derive(Debug, Serialize)]
+struct FeedDump {
+    source: &'static str,
+    url: String,
+    date: NaiveDate,
+    /// The feed's `Date`, earlier than `date` on a weekend; none if the feed has none
+    feed_date: Option<NaiveDate>,
+    rates: BTreeMap<String, Decimal>,
+    /// Currencies whose rate doesn't parse, with the reason
+    invalid: BTreeMap<String, String>,
+}
+
+/// Fetches and parses one feed and prints it, without touching the database, to check the
+/// network and the parsing on their own.
+pub async fn sample(args: SampleArgs, today: Option<NaiveDate>) -> Result<()> {
+    let date = match args.date {
+        Some(date) => date,
+        None => get_today(today)?,
+    };
+    let val_curs = get_val_curs(date).await?;
+    let currencies = get_currencies()?;
+    let aliases = get_currency_aliases()?;
+
+    if args.json {
+        let dump = get_dump(date, &val_curs, &currencies, &aliases, args.all).await?;
+        println!("{}", serde_json::to_string(&dump)?);
+        return Ok(());
+    }
+
+    println!(
+        "{}: feed date {}, {} currencies",
+        date,
+        val_curs.date.as_deref().unwrap_or("(not set)"),
+        val_curs.valute.len()
+    );
+
+    for valute in &val_curs.valute {
+        let char_code = aliases.get(&valute.char_code).unwrap_or(&valute.char_code);
+
+        if !args.all && !currencies.contains(char_code) {
+            continue;
+        }
+
+        let rate = match ParsedRate::try_from(valute) {
+            Ok(parsed) => parsed.rate.to_string(),
+            Err(err) => format!("invalid: {}", err),
+        };
+
+        println!(
+            "{} {} {} {} (nominal {}): {}",
+            valute.char_code,
+            valute.num_code,
+            valute.id,
+            valute.name,
+            valute.nominal.trim(),
+            rate
+        );
+    }
+
+    Ok(())
+}
+
+async fn get_dump(
+    date: NaiveDate,
+    val_curs: &ValCurs,
+    currencies: &[String],
+    aliases: &HashMap<String, String>,
+    all: bool,
+) -> Result<FeedDump> {
+    let mut rates = BTreeMap::new();
+    let mut invalid = BTreeMap::new();
+
+    for valute in &val_curs.valute {
+        let char_code = aliases.get(&valute.char_code).unwrap_or(&valute.char_code);
+
+        if !all && !currencies.contains(char_code) {
+            continue;
+        }
+
+        match ParsedRate::try_from(valute) {
+            Ok(parsed) => {
+                rates.insert(char_code.clone(), parsed.rate);
+            }
+            Err(err) => {
+                invalid.insert(char_code.clone(), err.to_string());
+            }
+        }
+    }
+
+    Ok(FeedDump {
+        source: "cbr",
+        url: get_url(date, get_cbr_lang()?).await?,
+        date,
+        feed_date: val_curs.get_date(),
+        rates,
+        invalid,
+    })
+}
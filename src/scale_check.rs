@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Result, anyhow};
+use sqlx::PgPool;
+
+use crate::config::{ScaleCheck, get_rate_scale, get_scale_check, get_table_name};
+
+/// Decimal places a rate can have without `RATE_SCALE`, the most `Decimal` keeps.
+const FULL_PRECISION_SCALE: i32 = 28;
+
+static CHECKED: AtomicBool = AtomicBool::new(false);
+
+/// Runs `check` on the first connection of the process only, so the daemon doesn't repeat
+/// it every hour.
+pub async fn check_once(pool: &PgPool) -> Result<()> {
+    if CHECKED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    check(pool).await
+}
+
+/// Postgres rounds a value to the column's `NUMERIC(p, s)` scale without an error, so a
+/// column narrower than `RATE_SCALE` (or than full precision without it) silently loses
+/// digits. `DB_SCALE_CHECK` decides whether that is logged or fails the run.
+pub async fn check(pool: &PgPool) -> Result<()> {
+    let severity = get_scale_check()?;
+
+    if severity == ScaleCheck::Off {
+        return Ok(());
+    }
+
+    let problems = get_problems(pool).await?;
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    match severity {
+        ScaleCheck::Error => Err(anyhow!("{} (DB_SCALE_CHECK=error)", problems.join("; "))),
+        ScaleCheck::Warn | ScaleCheck::Off => {
+            for problem in problems {
+                log::warn!("{}", problem);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// For `config-check --db`: the columns' scales, or the problems with them.
+pub async fn describe(pool: &PgPool) -> Result<String> {
+    let problems = get_problems(pool).await?;
+
+    if !problems.is_empty() {
+        return Err(anyhow!("{}", problems.join("; ")));
+    }
+
+    let columns: Vec<String> = get_column_scales(pool)
+        .await?
+        .into_iter()
+        .map(|(column, scale)| match scale {
+            Some(scale) => format!("{} scale {}", column, scale),
+            None => format!("{} unconstrained", column),
+        })
+        .collect();
+
+    Ok(columns.join(", "))
+}
+
+async fn get_problems(pool: &PgPool) -> Result<Vec<String>> {
+    let rate_scale = get_rate_scale()?;
+    let expected = rate_scale.map_or(FULL_PRECISION_SCALE, |scale| scale as i32);
+    let table = get_table_name("exchange_rates")?;
+    let mut problems = vec![];
+
+    for (column, scale) in get_column_scales(pool).await? {
+        // NUMERIC без параметров хранит значение как есть
+        let Some(scale) = scale else {
+            continue;
+        };
+
+        if scale < expected {
+            problems.push(format!(
+                "{}.{} is NUMERIC with scale {}, fewer decimal places than {}, so Postgres rounds stored rates",
+                table,
+                column,
+                scale,
+                match rate_scale {
+                    Some(rate_scale) => format!("RATE_SCALE {}", rate_scale),
+                    None => format!("the full precision of {} kept without RATE_SCALE", expected),
+                }
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// `numeric_scale` of `rate` and `raw_rate`, `None` for an unconstrained `NUMERIC`.
+async fn get_column_scales(pool: &PgPool) -> Result<Vec<(String, Option<i32>)>> {
+    let rows: Vec<(String, Option<i32>)> = sqlx::query_as(
+        r#"
+            SELECT column_name::text, numeric_scale::int
+            FROM information_schema.columns
+            WHERE table_schema = current_schema()
+                AND table_name = $1
+                AND column_name IN ('rate', 'raw_rate')
+            ORDER BY column_name
+        "#,
+    )
+    .bind(get_table_name("exchange_rates")?)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
@@ -0,0 +1,194 @@
+use anyhow::{Result, anyhow};
+use sqlx::PgPool;
+
+use crate::config::get_table_name;
+
+/// Columns of `exchange_rates` after every migration: name, `information_schema`
+/// `data_type` and whether it is nullable.
+const EXPECTED_COLUMNS: [(&str, &str, bool); 14] = [
+    ("id", "uuid", false),
+    ("from_currency", "text", false),
+    ("to_currency", "text", false),
+    ("rate", "numeric", false),
+    ("date", "date", false),
+    ("created_at", "timestamp with time zone", false),
+    ("updated_at", "timestamp with time zone", false),
+    ("effective_at", "timestamp with time zone", false),
+    ("raw_rate", "numeric", false),
+    ("source", "text", false),
+    ("fetched_at", "timestamp with time zone", true),
+    ("nominal", "integer", false),
+    ("quote_convention", "text", false),
+    ("feed_date", "date", true),
+];
+
+/// Unique indexes of `exchange_rates` by their columns, whatever they are named: the primary
+/// key and the pair-and-date index every lookup by pair and date and every upsert relies on.
+const EXPECTED_INDEXES: [(&str, &[&str]); 2] = [
+    ("primary key", &["id"]),
+    (
+        "exchange_rates_pair_date_key",
+        &["from_currency", "to_currency", "date"],
+    ),
+];
+
+#[derive(Debug, sqlx::FromRow)]
+struct Column {
+    name: String,
+    data_type: String,
+    is_nullable: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Index {
+    is_primary: bool,
+    is_unique: bool,
+    columns: Vec<String>,
+}
+
+/// Compares the live `exchange_rates` of the current schema with what the migrations
+/// create, for databases whose schema was changed by hand or whose migrations diverged.
+/// Prints one line per difference, `-` for a missing column or unique index, `~` for a
+/// column of another type or nullability and `+` for a column the migrations don't create,
+/// and fails when there is any. The scale of the rate columns is `config-check --db`'s job.
+pub async fn schema_check(pool: &PgPool) -> Result<()> {
+    let table = get_table_name("exchange_rates")?;
+    let columns = get_columns(pool, &table).await?;
+
+    if columns.is_empty() {
+        return Err(anyhow!(
+            "Table {} doesn't exist in the current schema",
+            table
+        ));
+    }
+
+    let indexes = get_indexes(pool, &table).await?;
+    let differences = get_differences(&columns, &indexes);
+
+    if differences.is_empty() {
+        println!(
+            "{} matches the migrations: {} columns, {} indexes checked",
+            table,
+            EXPECTED_COLUMNS.len(),
+            EXPECTED_INDEXES.len()
+        );
+        return Ok(());
+    }
+
+    println!("{} differs from the migrations:", table);
+
+    for difference in &differences {
+        println!("{}", difference);
+    }
+
+    Err(anyhow!(
+        "Schema drift in {}: {} differences",
+        table,
+        differences.len()
+    ))
+}
+
+fn get_differences(columns: &[Column], indexes: &[Index]) -> Vec<String> {
+    let mut differences = vec![];
+
+    for (name, data_type, is_nullable) in EXPECTED_COLUMNS {
+        let expected = describe_column(data_type, is_nullable);
+
+        match columns.iter().find(|column| column.name == name) {
+            None => differences.push(format!("- column {} {}: missing", name, expected)),
+            Some(column) if column.data_type != data_type || column.is_nullable != is_nullable => {
+                differences.push(format!(
+                    "~ column {}: expected {}, found {}",
+                    name,
+                    expected,
+                    describe_column(&column.data_type, column.is_nullable)
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for column in columns {
+        if !EXPECTED_COLUMNS
+            .iter()
+            .any(|(name, _, _)| *name == column.name)
+        {
+            differences.push(format!(
+                "+ column {} {}: not created by the migrations",
+                column.name,
+                describe_column(&column.data_type, column.is_nullable)
+            ));
+        }
+    }
+
+    for (name, expected_columns) in EXPECTED_INDEXES {
+        let is_primary = name == "primary key";
+        let found = indexes.iter().any(|index| {
+            // Уникальность по большему набору колонок не делает уникальной пару и дату
+            index.is_primary == is_primary && index.is_unique && index.columns == *expected_columns
+        });
+
+        if !found {
+            differences.push(format!(
+                "- index {} ({}): missing",
+                name,
+                expected_columns.join(", ")
+            ));
+        }
+    }
+
+    differences
+}
+
+fn describe_column(data_type: &str, is_nullable: bool) -> String {
+    if is_nullable {
+        format!("{} NULL", data_type)
+    } else {
+        format!("{} NOT NULL", data_type)
+    }
+}
+
+async fn get_columns(pool: &PgPool, table: &str) -> Result<Vec<Column>> {
+    let columns = sqlx::query_as(
+        r#"
+            SELECT
+                column_name::text AS name,
+                data_type::text AS data_type,
+                is_nullable = 'YES' AS is_nullable
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+            ORDER BY ordinal_position
+        "#,
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(columns)
+}
+
+/// Indexes aren't in `information_schema`, so they come from `pg_index`, with their
+/// columns in index order.
+async fn get_indexes(pool: &PgPool, table: &str) -> Result<Vec<Index>> {
+    let indexes = sqlx::query_as(
+        r#"
+            SELECT
+                pg_index.indisprimary AS is_primary,
+                pg_index.indisunique AS is_unique,
+                array_agg(pg_attribute.attname::text ORDER BY key.position) AS columns
+            FROM pg_index
+            JOIN pg_class table_class ON table_class.oid = pg_index.indrelid
+            JOIN pg_namespace ON pg_namespace.oid = table_class.relnamespace
+            CROSS JOIN LATERAL unnest(pg_index.indkey) WITH ORDINALITY AS key(attnum, position)
+            JOIN pg_attribute
+                ON pg_attribute.attrelid = table_class.oid AND pg_attribute.attnum = key.attnum
+            WHERE pg_namespace.nspname = current_schema() AND table_class.relname = $1
+            GROUP BY pg_index.indexrelid, pg_index.indisprimary, pg_index.indisunique
+        "#,
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(indexes)
+}
@@ -0,0 +1,100 @@
+use std::{sync::OnceLock, time::Duration};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+
+use crate::config::{get_secondary_connection_string, get_table_name};
+use crate::exchange_rate::QuoteConvention;
+
+/// Short, so an unreachable mirror slows every write by seconds rather than the default 30.
+const ACQUIRE_TIMEOUT_SEC: u64 = 5;
+
+static POOL: OnceLock<Option<PgPool>> = OnceLock::new();
+
+/// Writes the row the primary just stored to the `DATABASE_URL_SECONDARY` database, if
+/// one is configured. The mirror is best effort: a failure is only logged, and a row
+/// that failed is not retried, so after an outage the secondary needs a backfill.
+#[allow(clippy::too_many_arguments)]
+pub async fn mirror_rate(
+    from_currency: &str,
+    to_currency: &str,
+    rate: &Decimal,
+    raw_rate: &Decimal,
+    nominal: u32,
+    convention: QuoteConvention,
+    date: &NaiveDate,
+    effective_at: &DateTime<Utc>,
+    source: &str,
+    fetched_at: Option<&DateTime<Utc>>,
+    feed_date: Option<NaiveDate>,
+) {
+    let Some(pool) = POOL.get_or_init(get_pool) else {
+        return;
+    };
+
+    let result = async {
+        let exchange_rates = get_table_name("exchange_rates")?;
+
+        sqlx::query(&format!(
+            r#"
+                INSERT INTO {exchange_rates} AS exchange_rates (from_currency, to_currency, rate, raw_rate, nominal, quote_convention, date, effective_at, source, fetched_at, feed_date, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NOW(), NOW())
+                ON CONFLICT (from_currency, to_currency, date) DO UPDATE
+                SET rate = EXCLUDED.rate, raw_rate = EXCLUDED.raw_rate, nominal = EXCLUDED.nominal, quote_convention = EXCLUDED.quote_convention, effective_at = EXCLUDED.effective_at, source = EXCLUDED.source, fetched_at = COALESCE(EXCLUDED.fetched_at, exchange_rates.fetched_at), feed_date = COALESCE(EXCLUDED.feed_date, exchange_rates.feed_date), updated_at = NOW()
+            "#,
+        ))
+        .bind(from_currency)
+        .bind(to_currency)
+        .bind(rate)
+        .bind(raw_rate)
+        .bind(nominal as i32)
+        .bind(convention.as_str())
+        .bind(date)
+        .bind(effective_at)
+        .bind(source)
+        .bind(fetched_at)
+        .bind(feed_date)
+        .execute(pool)
+        .await?;
+
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    if let Err(err) = result {
+        log::warn!(
+            "Can't mirror {} -> {} at {} to DATABASE_URL_SECONDARY: {}",
+            from_currency,
+            to_currency,
+            date,
+            err
+        );
+    }
+}
+
+fn get_pool() -> Option<PgPool> {
+    let connection_string = match get_secondary_connection_string() {
+        Ok(connection_string) => connection_string?,
+        Err(err) => {
+            log::error!("{}, rates are not mirrored", err);
+            return None;
+        }
+    };
+
+    // Пул подключается лениво, чтобы недоступная копия не мешала основной записи
+    match PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(ACQUIRE_TIMEOUT_SEC))
+        .connect_lazy(&connection_string)
+    {
+        Ok(pool) => {
+            log::info!("Mirroring written rates to DATABASE_URL_SECONDARY");
+            Some(pool)
+        }
+
+        Err(err) => {
+            log::error!("Can't create DATABASE_URL_SECONDARY pool: {}", err);
+            None
+        }
+    }
+}
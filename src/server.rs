@@ -0,0 +1,797 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, get, post, web};
+use anyhow::{Result, anyhow};
+use chrono::{Days, NaiveDate};
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use subtle::ConstantTimeEq;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+use crate::config::{get_connection_string, get_max_staleness_days, get_table_name};
+use crate::exchange_rate::QuoteConvention;
+use crate::{
+    CurrencySummary, IngestLocked, RunSummary, WriteSummary, get_rounded_rate, get_today, http,
+    reingest_date, reingest_pair,
+};
+
+/// Fetch of a missing date, awaited by every request that asked for it meanwhile.
+type DateFetch = Shared<BoxFuture<'static, Result<(), Arc<anyhow::Error>>>>;
+
+struct AppState {
+    pool: PgPool,
+    today: Option<NaiveDate>,
+    read_through: bool,
+    fetches: Mutex<HashMap<NaiveDate, DateFetch>>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReingestQuery {
+    /// Date to refetch
+    date: NaiveDate,
+    /// Only rewrite the pair from this currency, together with `to`
+    from: Option<String>,
+    /// Only rewrite the pair to this currency, together with `from`
+    to: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RateQuery {
+    /// Currency code, e.g. USD
+    from: String,
+    /// Currency code, e.g. RUB
+    to: String,
+    /// Date of the rate; the newest stored rate when omitted
+    date: Option<NaiveDate>,
+    /// Fall back to the newest rate before `date` when there is none on it
+    #[serde(default)]
+    asof: bool,
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+struct Rate {
+    from_currency: String,
+    to_currency: String,
+    #[schema(value_type = String, example = "92.2628")]
+    rate: Decimal,
+    /// Units of the non-RUB currency the rate is for; more than 1 only for
+    /// `--keep-nominal-for` currencies, e.g. 10000 for IRR
+    nominal: i32,
+    /// `direct` for `X -> RUB` as CBR quotes it, `indirect` for `RUB -> X`, `cross` for a
+    /// pair without RUB
+    #[schema(example = "direct")]
+    quote_convention: String,
+    date: NaiveDate,
+    /// Date asked for with `asof=true`; `date` is the date the rate was stored for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    requested_date: Option<NaiveDate>,
+    /// The pair isn't stored and the rate was derived from both currencies' RUB rates
+    #[sqlx(default)]
+    derived: bool,
+}
+
+/// Which stored date `/rate` answers with.
+#[derive(Debug, Clone, Copy)]
+enum RateDate {
+    On(NaiveDate),
+    AsOf(NaiveDate),
+    Latest,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(health, show_rate, reingest, metrics, openapi_json),
+    components(schemas(Rate, RunSummary, WriteSummary, CurrencySummary)),
+    modifiers(&BearerAuth)
+)]
+struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+// Страница без внешних скриптов: рендерит /openapi.json сама, ничего не грузит с CDN
+const DOCS_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>valut API</title>
+<style>
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }
+h2 code { background: #eee; padding: 0.1em 0.4em; }
+td, th { text-align: left; padding: 0.2em 0.6em; vertical-align: top; }
+</style>
+</head>
+<body>
+<h1>valut API</h1>
+<p>Rendered from <a href="/openapi.json">/openapi.json</a>.</p>
+<div id="paths"></div>
+<script>
+function add(parent, tag, text) {
+  const element = document.createElement(tag);
+  if (text !== undefined) element.textContent = text;
+  parent.appendChild(element);
+  return element;
+}
+
+function addTable(parent, header, rows) {
+  if (rows.length === 0) return;
+  const table = add(parent, "table");
+  const head = add(table, "tr");
+  header.forEach(name => add(head, "th", name));
+  rows.forEach(row => {
+    const tr = add(table, "tr");
+    row.forEach(cell => add(tr, "td", cell));
+  });
+}
+
+fetch("/openapi.json")
+  .then(response => response.json())
+  .then(doc => {
+    const root = document.getElementById("paths");
+    for (const [path, methods] of Object.entries(doc.paths)) {
+      for (const [method, operation] of Object.entries(methods)) {
+        add(add(root, "h2"), "code", method.toUpperCase() + " " + path);
+        if (operation.summary) add(root, "p", operation.summary);
+        if (operation.description) add(root, "p", operation.description);
+        addTable(root, ["Parameter", "In", "Required", "Description"],
+          (operation.parameters || []).map(parameter => [
+            parameter.name, parameter.in, parameter.required ? "yes" : "no",
+            parameter.description || ""]));
+        addTable(root, ["Status", "Description"],
+          Object.entries(operation.responses || {}).map(([status, response]) => [
+            status, response.description || ""]));
+      }
+    }
+  })
+  .catch(err => add(document.getElementById("paths"), "p", "Can't load /openapi.json: " + err));
+</script>
+</body>
+</html>
+"##;
+
+/// With `read_through`, `/rate` for a date that has no stored rates at all fetches and
+/// stores it from CBR before answering, instead of answering 404.
+pub async fn start_server(today: Option<NaiveDate>, read_through: bool) -> Result<()> {
+    // Пул подключается лениво, чтобы сервер поднимался и без базы
+    let state = web::Data::new(AppState {
+        pool: PgPool::connect_lazy(&get_connection_string()?)?,
+        today,
+        read_through,
+        fetches: Mutex::new(HashMap::new()),
+    });
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .service(health)
+            .service(show_rate)
+            .service(reingest)
+            .service(metrics)
+            .service(openapi_json)
+            .service(docs)
+    })
+    .bind("0.0.0.0:8000")?
+    .run();
+
+    tokio::spawn(server);
+
+    Ok(())
+}
+
+#[utoipa::path(responses((status = 200, description = "Server is up", body = String)))]
+#[get("/health")]
+async fn health() -> impl Responder {
+    HttpResponse::Ok().body("OK")
+}
+
+#[utoipa::path(
+    params(RateQuery),
+    responses(
+        (status = 200, description = "Stored rate, or one derived from both currencies' RUB rates when the pair isn't stored; 1 without a lookup when from and to are the same currency", body = Rate),
+        (status = 404, description = "No rate for the pair or date (with asof=true, on or before the date); when deriving, the body names the missing RUB rate"),
+        (status = 503, description = "Newest rate is older than MAX_STALENESS_DAYS, or with --read-through the date isn't stored and an ingest run holds the lock", body = String)
+    )
+)]
+#[get("/rate")]
+async fn show_rate(state: web::Data<AppState>, query: web::Query<RateQuery>) -> impl Responder {
+    let from_currency = query.from.to_uppercase();
+    let to_currency = query.to.to_uppercase();
+
+    // Курс валюты к самой себе не хранится, а всегда равен 1
+    if from_currency == to_currency {
+        let date = match query.date.map(Ok).unwrap_or_else(|| get_today(state.today)) {
+            Ok(date) => date,
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+
+        return HttpResponse::Ok().json(Rate {
+            quote_convention: QuoteConvention::of(&from_currency, &to_currency)
+                .as_str()
+                .to_string(),
+            from_currency,
+            to_currency,
+            rate: Decimal::ONE,
+            nominal: 1,
+            date,
+            requested_date: None,
+            derived: false,
+        });
+    }
+
+    let rate_date = match query.date {
+        Some(date) if query.asof => RateDate::AsOf(date),
+        Some(date) => RateDate::On(date),
+        None => RateDate::Latest,
+    };
+
+    let result = match rate_date {
+        RateDate::AsOf(date) => {
+            get_rate_as_of(&state.pool, &from_currency, &to_currency, date).await
+        }
+        RateDate::On(date) if state.read_through => {
+            get_rate_read_through(&state, &from_currency, &to_currency, date).await
+        }
+        RateDate::On(date) => get_rate(&state.pool, &from_currency, &to_currency, date).await,
+        RateDate::Latest => {
+            latest_rate(&state.pool, &from_currency, &to_currency, state.today).await
+        }
+    };
+
+    // Пары нет, но её можно вывести через RUB, как ingest выводит кросс-курсы
+    let result = match result {
+        Ok(None) if from_currency != "RUB" && to_currency != "RUB" => {
+            derive_rate(
+                &state.pool,
+                &from_currency,
+                &to_currency,
+                rate_date,
+                state.today,
+            )
+            .await
+        }
+        result => result,
+    };
+
+    match result {
+        Ok(Some(exchange_rate)) => HttpResponse::Ok().json(exchange_rate),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(RateError::MissingLeg(message)) => HttpResponse::NotFound().body(message),
+        Err(RateError::Stale(message) | RateError::Busy(message)) => {
+            HttpResponse::ServiceUnavailable().body(message)
+        }
+        Err(RateError::Other(err)) => {
+            log::error!(
+                "Error reading {}/{} rate: {:?}",
+                from_currency,
+                to_currency,
+                err
+            );
+            HttpResponse::InternalServerError().body(err.to_string())
+        }
+    }
+}
+
+#[utoipa::path(
+    params(ReingestQuery),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Write outcome of the refetched date or pair", body = RunSummary),
+        (status = 400, description = "Only one of from and to is given"),
+        (status = 401, description = "Wrong bearer token"),
+        (status = 404, description = "ADMIN_TOKEN is not set"),
+        (status = 409, description = "An ingest run holds the lock; retry when it is done", body = String),
+        (status = 500, description = "Fetch or store failed", body = String)
+    )
+)]
+#[post("/reingest")]
+async fn reingest(request: HttpRequest, query: web::Query<ReingestQuery>) -> impl Responder {
+    let Ok(admin_token) = env::var("ADMIN_TOKEN") else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if !is_authorized(&request, &admin_token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let result = match (&query.from, &query.to) {
+        (Some(from_currency), Some(to_currency)) => {
+            reingest_pair(
+                query.date,
+                &from_currency.to_uppercase(),
+                &to_currency.to_uppercase(),
+            )
+            .await
+        }
+        (None, None) => reingest_date(query.date).await,
+        _ => return HttpResponse::BadRequest().body("from and to must be given together"),
+    };
+
+    match result {
+        Ok(summary) => {
+            log::info!("Reingested {}: {:?}", query.date, summary);
+            HttpResponse::Ok().json(summary)
+        }
+
+        Err(err) if err.is::<IngestLocked>() => {
+            log::warn!("Not reingesting {}: {}", query.date, err);
+            HttpResponse::Conflict().body("An ingest run is in progress, retry when it is done")
+        }
+
+        Err(err) => {
+            log::error!("Error reingesting {}: {:?}", query.date, err);
+            HttpResponse::InternalServerError().body(err.to_string())
+        }
+    }
+}
+
+#[utoipa::path(responses(
+    (status = 200, description = "Gauges in the Prometheus text format: valut_feed_date_lag_days, the days the last fetched CBR feed's Date is behind the requested date", body = String)
+))]
+#[get("/metrics")]
+async fn metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(crate::metrics::render())
+}
+
+#[utoipa::path(responses((status = 200, description = "This OpenAPI document")))]
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[get("/docs")]
+async fn docs() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((
+            "Content-Security-Policy",
+            "default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'",
+        ))
+        .body(DOCS_HTML)
+}
+
+/// The token is compared in constant time, so response times don't tell a client how much
+/// of a guess was right; only a wrong length returns early.
+fn is_authorized(request: &HttpRequest, admin_token: &str) -> bool {
+    request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| {
+            !admin_token.is_empty() && bool::from(token.as_bytes().ct_eq(admin_token.as_bytes()))
+        })
+}
+
+enum RateError {
+    Stale(String),
+    /// A read-through fetch can't take the ingest lock.
+    Busy(String),
+    /// A RUB rate needed to derive a pair that isn't stored is missing.
+    MissingLeg(String),
+    Other(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for RateError {
+    fn from(err: E) -> Self {
+        RateError::Other(err.into())
+    }
+}
+
+async fn get_rate(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    date: NaiveDate,
+) -> Result<Option<Rate>, RateError> {
+    let exchange_rate = sqlx::query_as(&format!(
+        r#"
+            SELECT from_currency, to_currency, rate, nominal, quote_convention, date
+            FROM {exchange_rates}
+            WHERE from_currency = $1 AND to_currency = $2 AND date = $3
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(date)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(exchange_rate)
+}
+
+/// Like `get_rate`, but a date without any stored rate is fetched from CBR first. Dates
+/// after tomorrow, which CBR can't have published, are not fetched.
+async fn get_rate_read_through(
+    state: &AppState,
+    from_currency: &str,
+    to_currency: &str,
+    date: NaiveDate,
+) -> Result<Option<Rate>, RateError> {
+    if let Some(exchange_rate) = get_rate(&state.pool, from_currency, to_currency, date).await? {
+        return Ok(Some(exchange_rate));
+    }
+
+    let tomorrow = get_today(state.today)?
+        .checked_add_days(Days::new(1))
+        .ok_or(anyhow!("Can't get next date"))?;
+
+    // Если за дату есть другие курсы, её уже загружали, и пары просто нет в CURRENCIES
+    if date > tomorrow || is_date_stored(&state.pool, date).await? {
+        return Ok(None);
+    }
+
+    match fetch_date(state, date).await {
+        Ok(()) => {}
+        Err(err) if err.is::<http::NotFound>() => return Ok(None),
+        Err(err) if err.is::<IngestLocked>() => {
+            return Err(RateError::Busy(format!(
+                "{} isn't stored yet and an ingest run is in progress, retry when it is done",
+                date
+            )));
+        }
+        Err(err) => return Err(anyhow!("Can't fetch {} from CBR: {:#}", date, err).into()),
+    }
+
+    get_rate(&state.pool, from_currency, to_currency, date).await
+}
+
+/// Requests for the same missing date share one fetch instead of each hitting CBR. The
+/// fetch runs in its own task, so it completes even if every waiting client disconnects.
+async fn fetch_date(state: &AppState, date: NaiveDate) -> Result<(), Arc<anyhow::Error>> {
+    let fetch = state
+        .fetches
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(date)
+        .or_insert_with(|| {
+            let task = tokio::spawn(async move {
+                log::info!("Fetching {} for /rate", date);
+                reingest_date(date).await.map(|_| ())
+            });
+
+            async move {
+                match task.await {
+                    Ok(result) => result.map_err(Arc::new),
+                    Err(err) => Err(Arc::new(anyhow!(err))),
+                }
+            }
+            .boxed()
+            .shared()
+        })
+        .clone();
+
+    let result = fetch.clone().await;
+
+    // Следующий запрос после завершения начнёт новую загрузку, а не получит старую ошибку
+    let mut fetches = state.fetches.lock().unwrap_or_else(|err| err.into_inner());
+
+    if fetches
+        .get(&date)
+        .is_some_and(|stored| stored.ptr_eq(&fetch))
+    {
+        fetches.remove(&date);
+    }
+
+    result
+}
+
+async fn is_date_stored(pool: &PgPool, date: NaiveDate) -> Result<bool> {
+    let (is_stored,): (bool,) = sqlx::query_as(&format!(
+        r#"
+            SELECT EXISTS (SELECT 1 FROM {exchange_rates} WHERE date = $1)
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(date)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(is_stored)
+}
+
+/// Newest stored rate of the pair on or before `date`, e.g. Friday's for a Sunday that
+/// was not stored. Nothing is carried forward in the table.
+async fn get_rate_as_of(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    date: NaiveDate,
+) -> Result<Option<Rate>, RateError> {
+    let exchange_rate: Option<Rate> = sqlx::query_as(&format!(
+        r#"
+            SELECT from_currency, to_currency, rate, nominal, quote_convention, date
+            FROM {exchange_rates}
+            WHERE from_currency = $1 AND to_currency = $2 AND date <= $3
+            ORDER BY date DESC
+            LIMIT 1
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(date)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(exchange_rate.map(|exchange_rate| Rate {
+        requested_date: Some(date),
+        ..exchange_rate
+    }))
+}
+
+/// Newest stored rate of the pair, refused when it is older than `MAX_STALENESS_DAYS`.
+async fn latest_rate(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    today: Option<NaiveDate>,
+) -> Result<Option<Rate>, RateError> {
+    let exchange_rate: Option<Rate> = sqlx::query_as(&format!(
+        r#"
+            SELECT from_currency, to_currency, rate, nominal, quote_convention, date
+            FROM {exchange_rates}
+            WHERE from_currency = $1 AND to_currency = $2
+            ORDER BY date DESC
+            LIMIT 1
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(exchange_rate) = exchange_rate else {
+        return Ok(None);
+    };
+
+    check_staleness(&exchange_rate, today)?;
+
+    Ok(Some(exchange_rate))
+}
+
+fn check_staleness(exchange_rate: &Rate, today: Option<NaiveDate>) -> Result<(), RateError> {
+    let max_staleness_days = get_max_staleness_days()?;
+    let today = get_today(today)?;
+    let oldest_date = today
+        .checked_sub_days(Days::new(max_staleness_days))
+        .ok_or(anyhow!("Can't get previous date for {}", today))?;
+
+    if exchange_rate.date < oldest_date {
+        return Err(RateError::Stale(format!(
+            "Latest {}/{} rate is from {}, older than {} days",
+            exchange_rate.from_currency,
+            exchange_rate.to_currency,
+            exchange_rate.date,
+            max_staleness_days
+        )));
+    }
+
+    Ok(())
+}
+
+/// `from -> to` as `(from -> RUB) / (to -> RUB)` per unit, from the newest date (for
+/// `RateDate::On`, the date itself) with both RUB rates stored, rounded like the stored
+/// cross rates. A date without one of them answers `RateError::MissingLeg`.
+async fn derive_rate(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    rate_date: RateDate,
+    today: Option<NaiveDate>,
+) -> Result<Option<Rate>, RateError> {
+    let (date_condition, requested_date) = match rate_date {
+        RateDate::On(date) => ("from_rub.date = $3", Some(date)),
+        RateDate::AsOf(date) => ("from_rub.date <= $3", Some(date)),
+        RateDate::Latest => ("$3::date IS NULL", None),
+    };
+
+    let legs: Option<(NaiveDate, Decimal, Decimal)> = sqlx::query_as(&format!(
+        r#"
+            SELECT from_rub.date, from_rub.rate / from_rub.nominal, to_rub.rate / to_rub.nominal
+            FROM {exchange_rates} from_rub
+            JOIN {exchange_rates} to_rub
+                ON to_rub.date = from_rub.date
+                AND to_rub.from_currency = $2
+                AND to_rub.to_currency = 'RUB'
+            WHERE from_rub.from_currency = $1 AND from_rub.to_currency = 'RUB' AND {date_condition}
+            ORDER BY from_rub.date DESC
+            LIMIT 1
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(requested_date)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((date, from_rate, to_rate)) = legs else {
+        return Err(RateError::MissingLeg(
+            describe_missing_legs(pool, from_currency, to_currency, rate_date).await?,
+        ));
+    };
+
+    let rate = from_rate.checked_div(to_rate).ok_or(anyhow!(
+        "Can't derive {} -> {} at {}: {} / {} is undefined or out of range",
+        from_currency,
+        to_currency,
+        date,
+        from_rate,
+        to_rate
+    ))?;
+    let exchange_rate = Rate {
+        from_currency: from_currency.to_string(),
+        to_currency: to_currency.to_string(),
+        rate: get_rounded_rate(&rate)?,
+        nominal: 1,
+        quote_convention: QuoteConvention::of(from_currency, to_currency)
+            .as_str()
+            .to_string(),
+        date,
+        requested_date: match rate_date {
+            RateDate::AsOf(date) => Some(date),
+            RateDate::On(_) | RateDate::Latest => None,
+        },
+        derived: true,
+    };
+
+    if let RateDate::Latest = rate_date {
+        check_staleness(&exchange_rate, today)?;
+    }
+
+    Ok(Some(exchange_rate))
+}
+
+/// Names the RUB rates a pair can't be derived from, e.g.
+/// "No USD -> RUB rate at 2024-03-05 to derive USD -> EUR".
+async fn describe_missing_legs(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    rate_date: RateDate,
+) -> Result<String> {
+    let (date_condition, date, at) = match rate_date {
+        RateDate::On(date) => ("date = $2", Some(date), format!(" at {}", date)),
+        RateDate::AsOf(date) => ("date <= $2", Some(date), format!(" on or before {}", date)),
+        RateDate::Latest => ("$2::date IS NULL", None, String::new()),
+    };
+    let mut missing = vec![];
+
+    for currency in [from_currency, to_currency] {
+        let (is_stored,): (bool,) = sqlx::query_as(&format!(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM {exchange_rates}
+                    WHERE from_currency = $1 AND to_currency = 'RUB' AND {date_condition}
+                )
+            "#,
+            exchange_rates = get_table_name("exchange_rates")?,
+        ))
+        .bind(currency)
+        .bind(date)
+        .fetch_one(pool)
+        .await?;
+
+        if !is_stored {
+            missing.push(format!("{} -> RUB", currency));
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(format!(
+            "No date{} with both {} -> RUB and {} -> RUB rates to derive {} -> {}",
+            at, from_currency, to_currency, from_currency, to_currency
+        ))
+    } else {
+        Ok(format!(
+            "No {} rate{} to derive {} -> {}",
+            missing.join(" or "),
+            at,
+            from_currency,
+            to_currency
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::refresh_pair;
+    use crate::test_support::{Env, Feed, FeedServer, TestDb, ingest_vars};
+
+    fn request(authorization: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header(("Authorization", authorization))
+            .to_http_request()
+    }
+
+    #[test]
+    fn authorizes_only_the_admin_token() {
+        assert!(is_authorized(&request("Bearer s3cret"), "s3cret"));
+        assert!(!is_authorized(&request("Bearer s3creT"), "s3cret"));
+        assert!(!is_authorized(&request("Bearer s3cre"), "s3cret"));
+        assert!(!is_authorized(&request("Bearer s3crets"), "s3cret"));
+        assert!(!is_authorized(&request("s3cret"), "s3cret"));
+        assert!(!is_authorized(&request("Bearer "), ""));
+        assert!(!is_authorized(
+            &TestRequest::default().to_http_request(),
+            "s3cret"
+        ));
+    }
+
+    #[test]
+    fn docs_load_nothing_from_other_hosts() {
+        assert!(DOCS_HTML.contains(r#"fetch("/openapi.json")"#));
+        assert!(!DOCS_HTML.contains("http://"));
+        assert!(!DOCS_HTML.contains("https://"));
+        assert!(!DOCS_HTML.contains("<script src"));
+        assert!(!DOCS_HTML.contains("<link"));
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Docker or TEST_DATABASE_URL"]
+    async fn derived_rate_matches_the_stored_cross_rate() {
+        let db = TestDb::start().await;
+        let feeds = FeedServer::start(vec![Feed::fixture("2024-03-01")]).await;
+        let _env = Env::set(&ingest_vars(&db, &feeds, "USD,EUR")).await;
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        for (from_currency, to_currency) in [("USD", "RUB"), ("EUR", "RUB"), ("USD", "EUR")] {
+            refresh_pair(&db.pool, "cbr", date, from_currency, to_currency)
+                .await
+                .unwrap();
+        }
+
+        let stored: Decimal = sqlx::query_scalar(
+            "SELECT rate FROM exchange_rates WHERE from_currency = 'USD' AND to_currency = 'EUR'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        let Ok(Some(derived)) = derive_rate(&db.pool, "USD", "EUR", RateDate::On(date), None).await
+        else {
+            panic!("USD -> EUR not derived");
+        };
+        assert_eq!(derived.rate, stored);
+        assert!(derived.derived);
+
+        let Err(RateError::MissingLeg(message)) =
+            derive_rate(&db.pool, "USD", "CNY", RateDate::On(date), None).await
+        else {
+            panic!("USD -> CNY derived without a CNY -> RUB rate");
+        };
+        assert_eq!(
+            message,
+            "No CNY -> RUB rate at 2024-03-01 to derive USD -> CNY"
+        );
+
+        db.close().await;
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::currency_cache::CurrencyNames;
+use crate::exchange_rate::QuoteConvention;
+use crate::val_curs::Valute;
+
+/// Keeps the row when it was fetched later than `fetched_at`, like `set_exchange_rate`.
+pub fn update_rate(
+    table: &str,
+    id: &Uuid,
+    rate: &Decimal,
+    raw_rate: &Decimal,
+    nominal: u32,
+    fetched_at: Option<&DateTime<Utc>>,
+    feed_date: Option<NaiveDate>,
+) -> String {
+    let fetched_at = optional_timestamp_literal(fetched_at);
+
+    format!(
+        "UPDATE {} SET rate = {}, raw_rate = {}, nominal = {}, fetched_at = COALESCE({}, fetched_at), feed_date = COALESCE({}, feed_date), updated_at = NOW() WHERE id = {} AND (fetched_at IS NULL OR {} IS NULL OR fetched_at <= {});",
+        table,
+        decimal_literal(rate),
+        decimal_literal(raw_rate),
+        nominal,
+        fetched_at,
+        optional_date_literal(feed_date.as_ref()),
+        string_literal(&id.to_string()),
+        fetched_at,
+        fetched_at
+    )
+}
+
+/// Upserts, so a script replayed over a row stored meanwhile updates it like `update_rate`.
+#[allow(clippy::too_many_arguments)]
+pub fn insert_rate(
+    table: &str,
+    from_currency: &str,
+    to_currency: &str,
+    rate: &Decimal,
+    raw_rate: &Decimal,
+    nominal: u32,
+    convention: QuoteConvention,
+    date: &NaiveDate,
+    effective_at: &DateTime<Utc>,
+    source: &str,
+    fetched_at: Option<&DateTime<Utc>>,
+    feed_date: Option<NaiveDate>,
+) -> String {
+    format!(
+        "INSERT INTO {} AS exchange_rates (from_currency, to_currency, rate, raw_rate, nominal, quote_convention, date, effective_at, source, fetched_at, feed_date, created_at, updated_at) VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, NOW(), NOW()) ON CONFLICT (from_currency, to_currency, date) DO UPDATE SET rate = EXCLUDED.rate, raw_rate = EXCLUDED.raw_rate, nominal = EXCLUDED.nominal, quote_convention = EXCLUDED.quote_convention, effective_at = EXCLUDED.effective_at, source = EXCLUDED.source, fetched_at = COALESCE(EXCLUDED.fetched_at, exchange_rates.fetched_at), feed_date = COALESCE(EXCLUDED.feed_date, exchange_rates.feed_date), updated_at = NOW() WHERE exchange_rates.rate <> EXCLUDED.rate AND (exchange_rates.fetched_at IS NULL OR EXCLUDED.fetched_at IS NULL OR exchange_rates.fetched_at <= EXCLUDED.fetched_at);",
+        table,
+        string_literal(from_currency),
+        string_literal(to_currency),
+        decimal_literal(rate),
+        decimal_literal(raw_rate),
+        nominal,
+        string_literal(convention.as_str()),
+        date_literal(date),
+        timestamp_literal(effective_at),
+        string_literal(source),
+        optional_timestamp_literal(fetched_at),
+        optional_date_literal(feed_date.as_ref())
+    )
+}
+
+pub fn upsert_currency(table: &str, char_code: &str, valute: &Valute) -> String {
+    format!(
+        "INSERT INTO {} (char_code, cbr_id, num_code, name, created_at, updated_at) VALUES ({}, {}, {}, {}, NOW(), NOW()) ON CONFLICT (char_code) DO UPDATE SET cbr_id = EXCLUDED.cbr_id, num_code = EXCLUDED.num_code, name = EXCLUDED.name, updated_at = NOW();",
+        table,
+        string_literal(char_code),
+        string_literal(&valute.id),
+        string_literal(&valute.num_code),
+        string_literal(&valute.name)
+    )
+}
+
+pub fn update_currency_names(table: &str, char_code: &str, names: &CurrencyNames) -> String {
+    format!(
+        "UPDATE {} SET name_ru = {}, name_en = {}, updated_at = NOW() WHERE char_code = {};",
+        table,
+        string_literal(&names.ru),
+        string_literal(&names.en),
+        string_literal(char_code)
+    )
+}
+
+fn string_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// Display у Decimal никогда не использует экспоненту, так что значение можно вставлять как есть
+fn decimal_literal(value: &Decimal) -> String {
+    value.to_string()
+}
+
+fn date_literal(date: &NaiveDate) -> String {
+    format!("DATE '{}'", date.format("%Y-%m-%d"))
+}
+
+fn optional_date_literal(value: Option<&NaiveDate>) -> String {
+    value.map(date_literal).unwrap_or("NULL::date".to_string())
+}
+
+/// With microseconds, the precision of `timestamptz`, so a replayed script stores the same
+/// `fetched_at` as a direct run.
+fn timestamp_literal(value: &DateTime<Utc>) -> String {
+    format!("TIMESTAMPTZ '{}'", value.format("%Y-%m-%d %H:%M:%S%.6f%:z"))
+}
+
+fn optional_timestamp_literal(value: Option<&DateTime<Utc>>) -> String {
+    value
+        .map(timestamp_literal)
+        .unwrap_or("NULL::timestamptz".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+
+    #[test]
+    fn timestamp_keeps_microseconds() {
+        let value = DateTime::parse_from_rfc3339("2024-03-01T09:15:30.123456789Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            timestamp_literal(&value),
+            "TIMESTAMPTZ '2024-03-01 09:15:30.123456+00:00'"
+        );
+        assert_eq!(
+            timestamp_literal(&value.with_nanosecond(0).unwrap()),
+            "TIMESTAMPTZ '2024-03-01 09:15:30.000000+00:00'"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::cli::StatsArgs;
+use crate::config::get_table_name;
+use crate::exchange_rate::PER_UNIT_RATE_SQL;
+
+/// Decimal places of `mean` and `stddev`. Postgres computes them with up to 40, more than
+/// `Decimal` holds; 20 still leaves room for 8 integer digits.
+const STATS_SCALE: u32 = 20;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct Stats {
+    count: i64,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+    mean: Option<Decimal>,
+    /// Sample standard deviation; none for fewer than two dates
+    stddev: Option<Decimal>,
+}
+
+/// Prints the count, min, max, mean and sample standard deviation of one pair's stored
+/// rates in the range, aggregated by Postgres. Every stored date counts once, so a weekend
+/// repeating Friday's rate weighs like a business day. Rates are per unit, also for a
+/// `--keep-nominal-for` pair stored per nominal.
+pub async fn stats(args: StatsArgs, pool: &PgPool) -> Result<()> {
+    if args.start > args.end {
+        return Err(anyhow!("Start date must be before end date"));
+    }
+
+    let from_currency = args.from.to_uppercase();
+    let to_currency = args.to.to_uppercase();
+    let stats = get_stats(pool, &from_currency, &to_currency, args.start, args.end).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+
+    let (Some(min), Some(max), Some(mean)) = (stats.min, stats.max, stats.mean) else {
+        println!(
+            "No data: no stored {} -> {} rates between {} and {}",
+            from_currency, to_currency, args.start, args.end
+        );
+        return Ok(());
+    };
+
+    println!(
+        "{} -> {} from {} to {}",
+        from_currency, to_currency, args.start, args.end
+    );
+    println!("count  {}", stats.count);
+    println!("min    {}", min);
+    println!("max    {}", max);
+    println!("mean   {}", mean);
+    println!(
+        "stddev {}",
+        match stats.stddev {
+            Some(stddev) => stddev.to_string(),
+            None => "-".to_string(),
+        }
+    );
+
+    Ok(())
+}
+
+async fn get_stats(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Stats> {
+    let mut stats: Stats = sqlx::query_as(&format!(
+        r#"
+            SELECT
+                count(*) AS count,
+                min(rate) AS min,
+                max(rate) AS max,
+                round(avg(rate), {scale}) AS mean,
+                round(stddev_samp(rate), {scale}) AS stddev
+            FROM (
+                SELECT {per_unit_rate} AS rate
+                FROM {exchange_rates}
+                WHERE from_currency = $1 AND to_currency = $2 AND date BETWEEN $3 AND $4
+            ) rates
+        "#,
+        per_unit_rate = PER_UNIT_RATE_SQL,
+        exchange_rates = get_table_name("exchange_rates")?,
+        scale = STATS_SCALE,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(start)
+    .bind(end)
+    .fetch_one(pool)
+    .await?;
+
+    stats.min = stats.min.map(|min| min.normalize());
+    stats.max = stats.max.map(|max| max.normalize());
+    stats.mean = stats.mean.map(|mean| mean.normalize());
+    stats.stddev = stats.stddev.map(|stddev| stddev.normalize());
+
+    Ok(stats)
+}
@@ -0,0 +1,110 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::cli::TrailArgs;
+use crate::config::get_table_name;
+use crate::exchange_rate::PER_UNIT_RATE_SQL;
+
+#[derive(Debug, Serialize)]
+struct TrailPoint {
+    date: NaiveDate,
+    rate: Decimal,
+    /// Change from the previous stored date; none for the first one
+    change: Option<Decimal>,
+    change_percent: Option<Decimal>,
+}
+
+/// Prints every stored rate of one pair in the range, oldest first, with the change from
+/// the previous stored date. Dates without a stored rate are left out, so a gap shows as
+/// one change over several days. Rates are per unit, so a `--keep-nominal-for` pair stored
+/// per nominal on some dates doesn't jump.
+pub async fn trail(args: TrailArgs, pool: &PgPool) -> Result<()> {
+    if args.start > args.end {
+        return Err(anyhow!("Start date must be before end date"));
+    }
+
+    let from_currency = args.from.to_uppercase();
+    let to_currency = args.to.to_uppercase();
+    let rates = get_rates(pool, &from_currency, &to_currency, args.start, args.end).await?;
+
+    let mut points = vec![];
+    let mut previous_rate: Option<Decimal> = None;
+
+    for (date, rate) in rates {
+        let change = previous_rate.map(|previous_rate| (rate - previous_rate).normalize());
+        let change_percent = previous_rate
+            .zip(change)
+            .and_then(|(previous_rate, change)| {
+                change
+                    .checked_mul(Decimal::ONE_HUNDRED)?
+                    .checked_div(previous_rate)
+            })
+            .map(|percent| percent.round_dp(4).normalize());
+
+        points.push(TrailPoint {
+            date,
+            rate,
+            change,
+            change_percent,
+        });
+        previous_rate = Some(rate);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string(&points)?);
+        return Ok(());
+    }
+
+    if points.is_empty() {
+        println!(
+            "No stored {} -> {} rates between {} and {}",
+            from_currency, to_currency, args.start, args.end
+        );
+        return Ok(());
+    }
+
+    for point in &points {
+        match (point.change, point.change_percent) {
+            (Some(change), Some(change_percent)) => println!(
+                "{} {} {:+} {:+}%",
+                point.date, point.rate, change, change_percent
+            ),
+            _ => println!("{} {}", point.date, point.rate),
+        }
+    }
+
+    Ok(())
+}
+
+async fn get_rates(
+    pool: &PgPool,
+    from_currency: &str,
+    to_currency: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<(NaiveDate, Decimal)>> {
+    let rates: Vec<(NaiveDate, Decimal)> = sqlx::query_as(&format!(
+        r#"
+            SELECT date, {per_unit_rate}
+            FROM {exchange_rates}
+            WHERE from_currency = $1 AND to_currency = $2 AND date BETWEEN $3 AND $4
+            ORDER BY date
+        "#,
+        per_unit_rate = PER_UNIT_RATE_SQL,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(from_currency)
+    .bind(to_currency)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rates
+        .into_iter()
+        .map(|(date, rate)| (date, rate.normalize()))
+        .collect())
+}
@@ -1,16 +1,580 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
 pub struct Valute {
+    #[serde(rename = "@ID")]
+    pub id: String,
+    #[serde(rename = "NumCode")]
+    pub num_code: String,
     #[serde(rename = "CharCode")]
     pub char_code: String,
-    #[serde(rename = "VunitRate")]
-    pub vunit_rate: String,
+    #[serde(rename = "Nominal")]
+    pub nominal: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Value")]
+    pub value: String,
+    // В старых фидах поля VunitRate нет
+    // Иначе None записывается как пустой <VunitRate/> и читается обратно как Some("")
+    #[serde(rename = "VunitRate", default, skip_serializing_if = "Option::is_none")]
+    pub vunit_rate: Option<String>,
+}
+
+impl Valute {
+    /// CBR writes `91,3336`: a decimal comma and no digit grouping.
+    pub const DECIMAL_FORMAT: DecimalFormat = DecimalFormat {
+        decimal_separator: ',',
+        group_separator: None,
+    };
+}
+
+/// How a source writes numbers. A value is only read right with the convention of the
+/// source it came from: `1,234.56` is 1234.56 with a decimal point and comma grouping, but
+/// not a number at all with CBR's decimal comma.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecimalFormat {
+    pub decimal_separator: char,
+    /// Dropped before parsing; `None` when the source doesn't group digits, so a grouped
+    /// value fails instead of being misread.
+    pub group_separator: Option<char>,
+}
+
+impl DecimalFormat {
+    /// `None` when `value` isn't a number in this format or doesn't fit in `Decimal`.
+    pub fn parse(&self, value: &str) -> Option<Decimal> {
+        parse_decimal_string(&self.normalize(value))
+    }
+
+    /// `value` with the grouping dropped and a decimal point, as `parse_decimal_string`
+    /// expects.
+    fn normalize(&self, value: &str) -> String {
+        let value = value.trim();
+        let value = match self.group_separator {
+            Some(group_separator) => value.replace(group_separator, ""),
+            None => value.to_string(),
+        };
+
+        if self.decimal_separator == '.' {
+            value
+        } else {
+            value.replace(self.decimal_separator, ".")
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
 pub struct ValCurs {
-    #[serde(rename = "Valute")]
+    /// Date the rates were set for, e.g. `02.03.2024`; on a weekend or before the day's
+    /// publication it is earlier than the requested date.
+    #[serde(rename = "@Date", default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    // Пустой <ValCurs/> разбирается в пустой список и отклоняется в validate
+    #[serde(rename = "Valute", default)]
     pub valute: Vec<Valute>,
 }
+
+impl ValCurs {
+    /// `Date` as a date, `None` when the feed has none or it doesn't parse.
+    pub fn get_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(self.date.as_deref()?, "%d.%m.%Y").ok()
+    }
+}
+
+/// A problem found by `ValCurs::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedError {
+    /// `<ValCurs>` without any `<Valute>`, which CBR answers for far-future dates.
+    Empty,
+    InvalidCharCode {
+        id: String,
+        char_code: String,
+    },
+    InvalidRate(ParseRateError),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::Empty => write!(f, "Empty feed: no Valute elements"),
+            FeedError::InvalidCharCode { id, char_code } => write!(
+                f,
+                "Invalid CharCode {} for {}, expected 3 uppercase letters",
+                char_code, id
+            ),
+            FeedError::InvalidRate(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+impl ValCurs {
+    /// Every problem of the feed in feed order; an empty feed is only `Empty`.
+    pub fn validate(&self) -> Vec<FeedError> {
+        if self.valute.is_empty() {
+            return vec![FeedError::Empty];
+        }
+
+        let mut errors = vec![];
+
+        for valute in &self.valute {
+            let char_code = &valute.char_code;
+
+            if char_code.len() != 3 || !char_code.chars().all(|c| c.is_ascii_uppercase()) {
+                errors.push(FeedError::InvalidCharCode {
+                    id: valute.id.clone(),
+                    char_code: char_code.clone(),
+                });
+            }
+
+            if let Err(err) = ParsedRate::try_from(valute) {
+                errors.push(FeedError::InvalidRate(err));
+            }
+        }
+
+        errors
+    }
+}
+
+/// The feed was fetched and parsed, but a stored currency is not in it (or its rate is
+/// out of range). Unlike a failed fetch or parse this leaves the other currencies intact.
+#[derive(Debug)]
+pub struct PartialFeed {
+    pub date: NaiveDate,
+    pub currency: String,
+    /// Stored currencies the feed does have
+    pub present: usize,
+}
+
+impl fmt::Display for PartialFeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Partial CBR feed at {}: {} is missing, {} other stored currencies are present",
+            self.date, self.currency, self.present
+        )
+    }
+}
+
+impl Error for PartialFeed {}
+
+/// Rate of one unit of `char_code` in rubles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedRate {
+    pub char_code: String,
+    /// Keeps the scale printed in the feed (`73,5000` stays `73.5000`); it becomes
+    /// `raw_rate` as is, and only `rate` is rounded and normalized.
+    pub rate: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseRateError {
+    InvalidNumber {
+        char_code: String,
+        field: &'static str,
+        value: String,
+    },
+    /// A well-formed number too large (or too precise a power of ten) for `Decimal`.
+    OutOfRange {
+        char_code: String,
+        field: &'static str,
+        value: String,
+    },
+    InvalidNominal {
+        char_code: String,
+        value: String,
+    },
+    NotPositive {
+        char_code: String,
+        rate: Decimal,
+    },
+}
+
+impl fmt::Display for ParseRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseRateError::InvalidNumber {
+                char_code,
+                field,
+                value,
+            } => write!(f, "Invalid {} {} for {}", field, value, char_code),
+            ParseRateError::OutOfRange {
+                char_code,
+                field,
+                value,
+            } => write!(
+                f,
+                "{} {} for {} is out of the decimal range",
+                field, value, char_code
+            ),
+            ParseRateError::InvalidNominal { char_code, value } => {
+                write!(f, "Invalid Nominal {} for {}", value, char_code)
+            }
+            ParseRateError::NotPositive { char_code, rate } => {
+                write!(f, "Rate {} for {} is not positive", rate, char_code)
+            }
+        }
+    }
+}
+
+impl Error for ParseRateError {}
+
+impl ParseRateError {
+    pub fn is_out_of_range(&self) -> bool {
+        matches!(self, ParseRateError::OutOfRange { .. })
+    }
+}
+
+impl TryFrom<&Valute> for ParsedRate {
+    type Error = ParseRateError;
+
+    /// Prefers `VunitRate` and falls back to `Value / Nominal` when the feed has no per-unit rate.
+    fn try_from(valute: &Valute) -> Result<Self, Self::Error> {
+        let rate = match &valute.vunit_rate {
+            Some(vunit_rate) => parse_field(valute, "VunitRate", vunit_rate)?,
+            None => {
+                let value = parse_field(valute, "Value", &valute.value)?;
+                let nominal = parse_nominal(valute)?;
+
+                // Деление даже на 1 меняет scale, а raw_rate должен совпадать с фидом
+                if nominal == 1 {
+                    value
+                } else {
+                    value / Decimal::from(nominal)
+                }
+            }
+        };
+
+        if rate <= Decimal::ZERO {
+            return Err(ParseRateError::NotPositive {
+                char_code: valute.char_code.clone(),
+                rate,
+            });
+        }
+
+        Ok(ParsedRate {
+            char_code: valute.char_code.clone(),
+            rate,
+        })
+    }
+}
+
+/// `Value` as printed in the feed, the price of `nominal` units of `char_code`, e.g.
+/// `Value` 18,5 for 10000 IRR.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NominalRate {
+    pub char_code: String,
+    pub value: Decimal,
+    pub nominal: u32,
+}
+
+impl TryFrom<&Valute> for NominalRate {
+    type Error = ParseRateError;
+
+    fn try_from(valute: &Valute) -> Result<Self, Self::Error> {
+        let value = parse_field(valute, "Value", &valute.value)?;
+
+        if value <= Decimal::ZERO {
+            return Err(ParseRateError::NotPositive {
+                char_code: valute.char_code.clone(),
+                rate: value,
+            });
+        }
+
+        Ok(NominalRate {
+            char_code: valute.char_code.clone(),
+            value,
+            nominal: parse_nominal(valute)?,
+        })
+    }
+}
+
+fn parse_nominal(valute: &Valute) -> Result<u32, ParseRateError> {
+    valute
+        .nominal
+        .trim()
+        .parse::<u32>()
+        .ok()
+        .filter(|nominal| *nominal > 0)
+        .ok_or_else(|| ParseRateError::InvalidNominal {
+            char_code: valute.char_code.clone(),
+            value: valute.nominal.clone(),
+        })
+}
+
+fn parse_field(
+    valute: &Valute,
+    field: &'static str,
+    value: &str,
+) -> Result<Decimal, ParseRateError> {
+    let normalized = Valute::DECIMAL_FORMAT.normalize(value);
+
+    parse_decimal_string(&normalized).ok_or_else(|| {
+        // Число записано верно, но не помещается в Decimal
+        if is_decimal_syntax(&normalized) {
+            ParseRateError::OutOfRange {
+                char_code: valute.char_code.clone(),
+                field,
+                value: value.to_string(),
+            }
+        } else {
+            ParseRateError::InvalidNumber {
+                char_code: valute.char_code.clone(),
+                field,
+                value: value.to_string(),
+            }
+        }
+    })
+}
+
+/// `[+-]digits[.digits][e[+-]digits]`, the numbers `parse_decimal_string` accepts.
+fn is_decimal_syntax(s: &str) -> bool {
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (s, None),
+    };
+    let mantissa = mantissa.strip_prefix(['+', '-']).unwrap_or(mantissa);
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    let exponent_is_valid = exponent.is_none_or(|exponent| {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        !exponent.is_empty() && is_digits(exponent)
+    });
+
+    !(integer.is_empty() && fraction.is_empty())
+        && is_digits(integer)
+        && is_digits(fraction)
+        && exponent_is_valid
+}
+
+fn parse_decimal_string(s: &str) -> Option<Decimal> {
+    // Проверяем наличие научной нотации (e или E)
+    if let Some(e_pos) = s.find(['e', 'E']) {
+        // Разделяем на мантиссу и экспоненту
+        let (mantissa_str, exp_str) = s.split_at(e_pos);
+        let exp_str = &exp_str[1..]; // Пропускаем символ 'e' или 'E'
+
+        // Парсим мантиссу и экспоненту
+        let mantissa = Decimal::from_str(mantissa_str).ok()?;
+        let exponent: i32 = exp_str.parse().ok()?;
+
+        // Вычисляем 10^|exponent|
+        let ten = Decimal::from(10);
+        let mut power = Decimal::ONE;
+        for _ in 0..exponent.abs() {
+            power = power.checked_mul(ten)?;
+        }
+
+        // Применяем экспоненту
+        if exponent >= 0 {
+            mantissa.checked_mul(power)
+        } else {
+            mantissa.checked_div(power)
+        }
+    } else {
+        // Обычный decimal без научной нотации
+        Decimal::from_str(s).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valute(char_code: &str, nominal: &str, value: &str, vunit_rate: Option<&str>) -> Valute {
+        Valute {
+            id: format!("R{}", char_code),
+            num_code: "000".to_string(),
+            char_code: char_code.to_string(),
+            nominal: nominal.to_string(),
+            name: char_code.to_string(),
+            value: value.to_string(),
+            vunit_rate: vunit_rate.map(str::to_string),
+        }
+    }
+
+    fn decimal(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn each_source_reads_only_its_own_decimal_format() {
+        let grouped = DecimalFormat {
+            decimal_separator: '.',
+            group_separator: Some(','),
+        };
+
+        assert_eq!(
+            Valute::DECIMAL_FORMAT.parse("91,3336"),
+            Some(decimal("91.3336"))
+        );
+        assert_eq!(Valute::DECIMAL_FORMAT.parse("1,234.56"), None);
+        assert_eq!(
+            crate::ecb::DECIMAL_FORMAT.parse("1.0812"),
+            Some(decimal("1.0812"))
+        );
+        assert_eq!(crate::ecb::DECIMAL_FORMAT.parse("1,0812"), None);
+        assert_eq!(grouped.parse("1,234.56"), Some(decimal("1234.56")));
+    }
+
+    fn round_trip(val_curs: &ValCurs) -> ValCurs {
+        quick_xml::de::from_str(&quick_xml::se::to_string(val_curs).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn golden_feeds_round_trip_losslessly() {
+        for entry in std::fs::read_dir("golden/feeds").unwrap() {
+            let path = entry.unwrap().path();
+            let val_curs: ValCurs =
+                quick_xml::de::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+            assert!(!val_curs.valute.is_empty(), "{}", path.display());
+            assert_eq!(round_trip(&val_curs), val_curs, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn feed_without_vunit_rate_or_date_round_trips_losslessly() {
+        // None не должен стать пустым элементом, который читается обратно как Some("")
+        let val_curs = ValCurs {
+            date: None,
+            valute: vec![valute("USD", "1", "90,8423", None)],
+        };
+
+        assert_eq!(round_trip(&val_curs), val_curs);
+    }
+
+    #[test]
+    fn parsed_rate_prefers_vunit_rate() {
+        let parsed =
+            ParsedRate::try_from(&valute("JPY", "100", "61,2345", Some("0,612345"))).unwrap();
+
+        assert_eq!(
+            parsed,
+            ParsedRate {
+                char_code: "JPY".to_string(),
+                rate: decimal("0.612345"),
+            }
+        );
+    }
+
+    #[test]
+    fn parsed_rate_divides_value_by_nominal_without_vunit_rate() {
+        let parsed = ParsedRate::try_from(&valute("JPY", "100", "61,2345", None)).unwrap();
+        assert_eq!(parsed.rate, decimal("0.612345"));
+
+        // При номинале 1 значение берётся как есть, вместе с его scale
+        let parsed = ParsedRate::try_from(&valute("USD", "1", "91,3000", None)).unwrap();
+        assert_eq!(parsed.rate.to_string(), "91.3000");
+    }
+
+    #[test]
+    fn parsed_rate_rejects_invalid_values() {
+        assert_eq!(
+            ParsedRate::try_from(&valute("USD", "1", "abc", None)),
+            Err(ParseRateError::InvalidNumber {
+                char_code: "USD".to_string(),
+                field: "Value",
+                value: "abc".to_string(),
+            })
+        );
+        assert_eq!(
+            ParsedRate::try_from(&valute("USD", "0", "91,3", None)),
+            Err(ParseRateError::InvalidNominal {
+                char_code: "USD".to_string(),
+                value: "0".to_string(),
+            })
+        );
+        assert_eq!(
+            ParsedRate::try_from(&valute("USD", "1", "-1", None)),
+            Err(ParseRateError::NotPositive {
+                char_code: "USD".to_string(),
+                rate: decimal("-1"),
+            })
+        );
+        assert_eq!(
+            ParsedRate::try_from(&valute("USD", "1", "91,3", Some("0"))),
+            Err(ParseRateError::NotPositive {
+                char_code: "USD".to_string(),
+                rate: Decimal::ZERO,
+            })
+        );
+    }
+
+    #[test]
+    fn parsed_rate_keeps_the_scale_of_the_feed() {
+        for (value, rate) in [
+            ("1,0000", "1.0000"),
+            ("73,5000", "73.5000"),
+            ("0,0010", "0.0010"),
+            ("0,00002345678901234567", "0.00002345678901234567"),
+            ("12345,678901234567890123", "12345.678901234567890123"),
+        ] {
+            let parsed = ParsedRate::try_from(&valute("USD", "1", value, None)).unwrap();
+            assert_eq!(parsed.rate.to_string(), rate);
+        }
+    }
+
+    #[test]
+    fn empty_feed_is_only_empty() {
+        let val_curs: ValCurs = quick_xml::de::from_str(
+            r#"<ValCurs Date="01.01.2100" name="Foreign Currency Market"/>"#,
+        )
+        .unwrap();
+
+        assert_eq!(val_curs.validate(), [FeedError::Empty]);
+    }
+
+    #[test]
+    fn malformed_valutes_are_reported_in_feed_order() {
+        let val_curs = ValCurs {
+            date: Some("01.03.2024".to_string()),
+            valute: vec![
+                valute("usd", "1", "91,3", None),
+                valute("EUR", "1", "98,3", None),
+                valute("CNY", "1", "12,6.1", None),
+                valute("GBPX", "1", "0", None),
+            ],
+        };
+
+        assert_eq!(
+            val_curs.validate(),
+            [
+                FeedError::InvalidCharCode {
+                    id: "Rusd".to_string(),
+                    char_code: "usd".to_string(),
+                },
+                FeedError::InvalidRate(ParseRateError::InvalidNumber {
+                    char_code: "CNY".to_string(),
+                    field: "Value",
+                    value: "12,6.1".to_string(),
+                }),
+                FeedError::InvalidCharCode {
+                    id: "RGBPX".to_string(),
+                    char_code: "GBPX".to_string(),
+                },
+                FeedError::InvalidRate(ParseRateError::NotPositive {
+                    char_code: "GBPX".to_string(),
+                    rate: Decimal::ZERO,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_feed_has_no_errors() {
+        let val_curs: ValCurs = quick_xml::de::from_str(
+            &std::fs::read_to_string("golden/feeds/2024-03-01.xml").unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(val_curs.validate(), []);
+    }
+}
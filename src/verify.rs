@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use sqlx::{FromRow, PgPool};
+
+use crate::cli::VerifyAllArgs;
+use crate::config::get_table_name;
+
+#[derive(Debug, FromRow)]
+struct Violation {
+    kind: String,
+    date: NaiveDate,
+    from_currency: String,
+    to_currency: String,
+    value: String,
+    total: i64,
+}
+
+/// Accepts plain and scientific notation, e.g. `0.000000001` or `1e-9`.
+pub fn parse_tolerance(value: &str) -> Result<Decimal, String> {
+    let tolerance = Decimal::from_str(value)
+        .or_else(|_| Decimal::from_scientific(value))
+        .map_err(|e| e.to_string())?;
+
+    if tolerance.is_sign_negative() {
+        return Err("tolerance must not be negative".to_string());
+    }
+
+    Ok(tolerance)
+}
+
+/// Checks every stored date at once in Postgres: `A -> B` times `B -> A` must be 1, and
+/// a cross rate `A -> B` between two non-RUB currencies must equal
+/// `(A -> RUB) / (B -> RUB)` per unit, both within the relative tolerance. Only a sample of the
+/// violations is read back.
+pub async fn verify_all(args: VerifyAllArgs, pool: &PgPool) -> Result<()> {
+    let violations: Vec<Violation> = sqlx::query_as(&format!(
+        r#"
+            WITH violations AS (
+                SELECT
+                    'reciprocal' AS kind,
+                    forward_rate.date,
+                    forward_rate.from_currency,
+                    forward_rate.to_currency,
+                    forward_rate.rate * reverse_rate.rate AS value
+                FROM {exchange_rates} forward_rate
+                JOIN {exchange_rates} reverse_rate
+                    ON reverse_rate.date = forward_rate.date
+                    AND reverse_rate.from_currency = forward_rate.to_currency
+                    AND reverse_rate.to_currency = forward_rate.from_currency
+                WHERE forward_rate.from_currency < forward_rate.to_currency
+                    AND ABS(forward_rate.rate * reverse_rate.rate - 1) > $1
+                UNION ALL
+                SELECT
+                    'cross',
+                    pair_rate.date,
+                    pair_rate.from_currency,
+                    pair_rate.to_currency,
+                    pair_rate.rate * to_rub.rate * from_rub.nominal / (from_rub.rate * to_rub.nominal)
+                FROM {exchange_rates} pair_rate
+                JOIN {exchange_rates} from_rub
+                    ON from_rub.date = pair_rate.date
+                    AND from_rub.from_currency = pair_rate.from_currency
+                    AND from_rub.to_currency = 'RUB'
+                JOIN {exchange_rates} to_rub
+                    ON to_rub.date = pair_rate.date
+                    AND to_rub.from_currency = pair_rate.to_currency
+                    AND to_rub.to_currency = 'RUB'
+                WHERE pair_rate.from_currency <> 'RUB'
+                    AND pair_rate.to_currency <> 'RUB'
+                    AND from_rub.rate <> 0
+                    AND ABS(
+                        pair_rate.rate * to_rub.rate * from_rub.nominal
+                            / (from_rub.rate * to_rub.nominal) - 1
+                    ) > $1
+            )
+            SELECT
+                kind,
+                date,
+                from_currency,
+                to_currency,
+                value::text AS value,
+                COUNT(*) OVER () AS total
+            FROM violations
+            ORDER BY date, kind, from_currency, to_currency
+            LIMIT $2
+        "#,
+        exchange_rates = get_table_name("exchange_rates")?,
+    ))
+    .bind(args.tolerance)
+    .bind(args.sample as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let total = violations.first().map_or(0, |violation| violation.total);
+
+    for violation in &violations {
+        println!(
+            "{} {} {} -> {}: {}",
+            violation.date,
+            violation.kind,
+            violation.from_currency,
+            violation.to_currency,
+            violation.value
+        );
+    }
+
+    println!(
+        "{} violations beyond tolerance {}{}",
+        total,
+        args.tolerance,
+        if total > violations.len() as i64 {
+            format!(", showing the first {}", violations.len())
+        } else {
+            String::new()
+        }
+    );
+
+    if total > 0 {
+        return Err(anyhow!(
+            "{} stored rates are inconsistent with their reciprocal or RUB rates",
+            total
+        ));
+    }
+
+    Ok(())
+}
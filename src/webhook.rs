@@ -0,0 +1,44 @@
+use anyhow::Result;
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::{WriteSummary, http, logging};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Succeeded,
+    Failed,
+}
+
+/// The run summary, with `error` set instead of the counts when the run failed.
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    run_id: &'static str,
+    status: Status,
+    #[serde(flatten)]
+    writes: Option<&'a WriteSummary>,
+    error: Option<String>,
+}
+
+/// A failed POST is only logged, it never fails the run.
+pub async fn notify(url: &Url, result: &Result<WriteSummary>) {
+    let notification = match result {
+        Ok(writes) => Notification {
+            run_id: logging::run_id(),
+            status: Status::Succeeded,
+            writes: Some(writes),
+            error: None,
+        },
+        Err(err) => Notification {
+            run_id: logging::run_id(),
+            status: Status::Failed,
+            writes: None,
+            error: Some(format!("{:#}", err)),
+        },
+    };
+
+    if let Err(err) = http::post_json(url, &notification).await {
+        log::error!("Can't send the run summary to the webhook: {}", err);
+    }
+}
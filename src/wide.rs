@@ -0,0 +1,102 @@
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use sqlx::PgPool;
+
+use crate::config::get_table_name;
+use crate::exchange_rate::PER_UNIT_RATE_SQL;
+
+/// Rewrites the `exchange_rates_wide` rows of the run's dates from the long table, with a
+/// `<code>_rub` column per currency, per unit also for `--keep-nominal-for` rows. Columns
+/// of newly configured currencies are added; columns of dropped ones are kept and left
+/// empty for the rewritten dates.
+pub async fn refresh(pool: &PgPool, dates: &[NaiveDate], currencies: &[String]) -> Result<()> {
+    let start_date = dates.iter().min().ok_or(anyhow!("No dates to refresh"))?;
+    let end_date = dates.iter().max().ok_or(anyhow!("No dates to refresh"))?;
+
+    // Коды проверены в get_currencies (три латинские буквы), их можно вставлять в SQL
+    let columns: Vec<String> = currencies
+        .iter()
+        .map(|currency| format!("{}_rub", currency.to_lowercase()))
+        .collect();
+
+    let exchange_rates = get_table_name("exchange_rates")?;
+    let exchange_rates_wide = get_table_name("exchange_rates_wide")?;
+    let mut transaction = pool.begin().await?;
+
+    let existing_columns: Vec<(String,)> = sqlx::query_as(
+        r#"
+            SELECT column_name::text
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+        "#,
+    )
+    .bind(&exchange_rates_wide)
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    for column in &columns {
+        if existing_columns.iter().any(|(name,)| name == column) {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} NUMERIC",
+            exchange_rates_wide, column
+        ))
+        .execute(&mut *transaction)
+        .await?;
+
+        log::info!("{}: column {} added", exchange_rates_wide, column);
+    }
+
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE date BETWEEN $1 AND $2",
+        exchange_rates_wide
+    ))
+    .bind(start_date)
+    .bind(end_date)
+    .execute(&mut *transaction)
+    .await?;
+
+    let pivots: Vec<String> = currencies
+        .iter()
+        .zip(&columns)
+        .map(|(currency, column)| {
+            format!(
+                "MAX({}) FILTER (WHERE from_currency = '{}') AS {}",
+                PER_UNIT_RATE_SQL, currency, column
+            )
+        })
+        .collect();
+
+    let result = sqlx::query(&format!(
+        r#"
+            INSERT INTO {} (date, {})
+            SELECT date, {}
+            FROM {}
+            WHERE to_currency = 'RUB' AND date BETWEEN $1 AND $2 AND from_currency = ANY($3)
+            GROUP BY date
+        "#,
+        exchange_rates_wide,
+        columns.join(", "),
+        pivots.join(", "),
+        exchange_rates
+    ))
+    .bind(start_date)
+    .bind(end_date)
+    .bind(currencies)
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    log::info!(
+        "{}: {} dates rewritten between {} and {}",
+        exchange_rates_wide,
+        result.rows_affected(),
+        start_date,
+        end_date
+    );
+
+    Ok(())
+}